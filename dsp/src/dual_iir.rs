@@ -0,0 +1,339 @@
+//! Dual IIR signal processing
+//!
+//! # Design
+//! Each of the two channels filters its input through a cascade of IIR biquads, adds the output of
+//! its signal generator and generates the result on its DAC output. If "hold" is asserted, the
+//! filter outputs are frozen at their latest value.
+//!
+//! The channels can be cross-coupled: the inputs of the IIR cascades are a linear combination of
+//! both ADC inputs (the input matrix) and the DAC outputs are a linear combination of both IIR
+//! cascade outputs (the output matrix). With identity matrices, ADC0 is filtered onto DAC0 and ADC1
+//! onto DAC1.
+//!
+//! The number of active biquads of each channel can be changed at run-time up to the capacity of
+//! the cascade. Inactive biquads are bypassed and not computed.
+//!
+//! Each channel can optionally be supervised by a [lock](super::lock) state machine. While a
+//! channel is sweeping, its IIR cascade is bypassed and the cascade output is replaced by the sweep.
+//! Once the error signal (the cascade input) crosses the threshold, the cascade is engaged starting
+//! from the current sweep output.
+//!
+//! The processor tracks the saturation of each channel: a channel output sample is saturated if the
+//! IIR cascade output is at its limits or the DAC output is clipped. The number of saturated
+//! samples and the duration of the current saturation are available as [Saturation].
+//!
+//! The [network analyzer](super::network_analyzer) excitation is added to the output of the excited
+//! channel together with its signal generator.
+//!
+//! Signal generators with a trigger source are triggered at the sample of the batch at which the
+//! triggering edge occurred (see [Input::triggers]).
+use idsp::iir;
+
+use super::{
+    code::DacCode,
+    lock::{self, Lock},
+    network_analyzer::NetworkAnalyzer,
+    signal_generator::{self, SignalGenerator},
+    Input, Output, Process, MAX_BATCH_SIZE,
+};
+
+/// The processing configuration of both channels.
+///
+/// # Note
+/// `N` is the maximum number of cascaded IIR biquads per channel.
+#[derive(Copy, Clone, Debug)]
+pub struct Config<const N: usize> {
+    /// The IIR filter cascade of each channel.
+    pub iir_ch: [[iir::IIR<f32>; N]; 2],
+
+    /// The number of active biquads of each channel. Values larger than `N` are limited to `N`.
+    pub cascade_len: [usize; 2],
+
+    /// The input matrix. `input_matrix[i][j]` is the gain from ADC `j` to IIR cascade `i`.
+    pub input_matrix: [[f32; 2]; 2],
+
+    /// The output matrix. `output_matrix[i][j]` is the gain from IIR cascade `j` to DAC `i`.
+    pub output_matrix: [[f32; 2]; 2],
+
+    /// Specified true if DI1 should be used as a "hold" input.
+    pub allow_hold: bool,
+
+    /// Specified true if "hold" should be forced regardless of DI1 state and hold allowance.
+    pub force_hold: bool,
+
+    /// The lock state machine configuration of each channel.
+    pub lock: [lock::Config; 2],
+
+    /// The number of consecutive saturated samples after which a saturation alarm is raised. Zero
+    /// disables the alarm.
+    pub saturation_alarm: u32,
+}
+
+/// The saturation statistics of a channel.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Saturation {
+    /// The total number of saturated output samples. The count wraps around on overflow.
+    pub count: u32,
+
+    /// The number of consecutive saturated output samples up to the latest sample.
+    pub rail: u32,
+}
+
+impl Saturation {
+    #[inline]
+    fn update(&mut self, saturated: bool) {
+        if saturated {
+            self.count = self.count.wrapping_add(1);
+            self.rail = self.rail.saturating_add(1);
+        } else {
+            self.rail = 0;
+        }
+    }
+}
+
+/// The internal signals of the latest batch for monitoring.
+///
+/// # Note
+/// Only the first batch size values of each signal are valid.
+#[derive(Copy, Clone, Debug)]
+pub struct Monitor {
+    /// The IIR cascade input of each channel.
+    pub iir_input: [[f32; MAX_BATCH_SIZE]; 2],
+
+    /// The IIR cascade output (or lock sweep) of each channel.
+    pub iir_output: [[f32; MAX_BATCH_SIZE]; 2],
+
+    /// The signal generator output codes (including the network analyzer excitation) added to the
+    /// DAC0/DAC1 outputs.
+    pub signal: [[i16; MAX_BATCH_SIZE]; 2],
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Self {
+            iir_input: [[0.; MAX_BATCH_SIZE]; 2],
+            iir_output: [[0.; MAX_BATCH_SIZE]; 2],
+            signal: [[0; MAX_BATCH_SIZE]; 2],
+        }
+    }
+}
+
+/// The dual IIR processor.
+///
+/// # Note
+/// Batches must not exceed [MAX_BATCH_SIZE] samples.
+pub struct DualIir<const N: usize> {
+    config: Config<N>,
+
+    /// The signal generators added to the DAC0/DAC1 outputs.
+    pub signal_generator: [SignalGenerator; 2],
+
+    /// The network analyzer measuring the response to an excitation of a DAC output.
+    pub analyzer: NetworkAnalyzer,
+
+    iir_state: [[iir::Vec5<f32>; N]; 2],
+    lock: [Lock; 2],
+    saturation: [Saturation; 2],
+    alarm: [bool; 2],
+    monitor: Monitor,
+}
+
+impl<const N: usize> DualIir<N> {
+    /// Construct a dual IIR processor.
+    ///
+    /// # Args
+    /// * `config` - The initial processing configuration.
+    /// * `signal_generator` - The initial configuration of the DAC0/DAC1 signal generators.
+    pub fn new(
+        config: Config<N>,
+        signal_generator: [signal_generator::Config; 2],
+    ) -> Self {
+        let mut processor = Self {
+            config,
+            signal_generator: signal_generator.map(SignalGenerator::new),
+            analyzer: NetworkAnalyzer::default(),
+            iir_state: [[[0.; 5]; N]; 2],
+            lock: config.lock.map(Lock::new),
+            saturation: [Saturation::default(); 2],
+            alarm: [false; 2],
+            monitor: Monitor::default(),
+        };
+        processor.configure(config);
+        processor
+    }
+
+    /// Update the processing configuration.
+    ///
+    /// # Note
+    /// The filter state of a channel is reset if its number of active biquads changes.
+    ///
+    /// # Args
+    /// * `config` - The new processing configuration.
+    pub fn configure(&mut self, mut config: Config<N>) {
+        for (channel, len) in config.cascade_len.iter_mut().enumerate() {
+            *len = (*len).min(N);
+
+            if *len != self.config.cascade_len[channel] {
+                self.iir_state[channel] = [[0.; 5]; N];
+            }
+        }
+
+        for (lock, config) in self.lock.iter_mut().zip(config.lock) {
+            lock.configure(config);
+        }
+
+        self.config = config;
+    }
+
+    /// Get the current processing configuration.
+    pub fn config(&self) -> &Config<N> {
+        &self.config
+    }
+
+    /// Get the lock status of both channels.
+    pub fn lock_status(&self) -> [lock::Status; 2] {
+        [self.lock[0].status(), self.lock[1].status()]
+    }
+
+    /// Get the saturation statistics of both channels.
+    pub fn saturation(&self) -> [Saturation; 2] {
+        self.saturation
+    }
+
+    /// Check which channels raised a saturation alarm during the latest batch.
+    ///
+    /// # Note
+    /// An alarm is raised once when the number of consecutive saturated samples of a channel
+    /// reaches the configured [Config::saturation_alarm].
+    pub fn saturation_alarm(&self) -> [bool; 2] {
+        self.alarm
+    }
+
+    /// Get the internal signals of the latest batch.
+    pub fn monitor(&self) -> &Monitor {
+        &self.monitor
+    }
+}
+
+impl<const N: usize> Process for DualIir<N> {
+    // Note: This is always inlined so that it ends up in the (ITCM) section of the calling task.
+    #[inline(always)]
+    fn process(&mut self, input: &Input<'_>, output: &mut Output<'_>) {
+        let Self {
+            config,
+            signal_generator,
+            analyzer,
+            iir_state,
+            lock,
+            saturation,
+            alarm,
+            monitor,
+        } = self;
+
+        let hold =
+            config.force_hold || (input.digital_inputs[1] && config.allow_hold);
+
+        let [state0, state1] = iir_state;
+        let state = [
+            &mut state0[..config.cascade_len[0]],
+            &mut state1[..config.cascade_len[1]],
+        ];
+        let iir = [
+            &config.iir_ch[0][..config.cascade_len[0]],
+            &config.iir_ch[1][..config.cascade_len[1]],
+        ];
+
+        // The output limits of the last active biquad of each channel.
+        let rails = iir.map(|iir| iir.last().map(|iir| (iir.y_min, iir.y_max)));
+
+        // The trigger input of each signal generator.
+        let trigger = [
+            signal_generator[0].config().trigger.source.input(),
+            signal_generator[1].config().trigger.source.input(),
+        ]
+        .map(|di| di.and_then(|di| input.triggers[di]));
+
+        for i in 0..input.adcs[0].len() {
+            let codes = [input.adcs[0][i] as i16, input.adcs[1][i] as i16];
+            let x = codes.map(f32::from);
+
+            let excited = analyzer.channel();
+            let excitation = analyzer.update(codes);
+
+            let mut y = [0.; 2];
+            let mut railed = [false; 2];
+            for (channel, y) in y.iter_mut().enumerate() {
+                let m = &config.input_matrix[channel];
+                let u = m[0] * x[0] + m[1] * x[1];
+                let lock = &mut lock[channel];
+                monitor.iir_input[channel][i] = u;
+
+                if lock.is_engaged() {
+                    *y = iir[channel]
+                        .iter()
+                        .zip(state[channel].iter_mut())
+                        .fold(u, |yi, (ch, state)| ch.update(state, yi, hold));
+
+                    railed[channel] = rails[channel]
+                        .map(|(min, max)| *y <= min || *y >= max)
+                        .unwrap_or(false);
+                    lock.supervise(u, railed[channel]);
+                } else {
+                    *y = f32::from(lock.sweep(u));
+
+                    if lock.is_engaged() {
+                        // Engage the cascade starting from the sweep output.
+                        for stage in state[channel].iter_mut() {
+                            *stage = [0.; 5];
+                        }
+                        if let Some(last) = state[channel].last_mut() {
+                            *last = [0., 0., *y, *y, *y];
+                        }
+                    }
+                }
+
+                monitor.iir_output[channel][i] = *y;
+            }
+
+            for (channel, signal) in signal_generator.iter_mut().enumerate() {
+                let m = &config.output_matrix[channel];
+
+                // Note: The conversion saturates at the DAC range.
+                // The truncation introduces 1/2 LSB distortion.
+                let y = m[0] * y[0] + m[1] * y[1];
+                let clipped = y <= i16::MIN as f32 || y >= i16::MAX as f32;
+                let y = y as i16;
+
+                if trigger[channel] == Some(i) {
+                    signal.trigger();
+                }
+
+                // Note(unwrap): The signal generator is an infinite iterator.
+                let mut signal = signal.next().unwrap();
+                if excited == Some(channel) {
+                    signal = signal.saturating_add(excitation);
+                }
+                monitor.signal[channel][i] = signal;
+                let clipped = clipped || y.checked_add(signal).is_none();
+                let y = y.saturating_add(signal);
+
+                saturation[channel].update(railed[channel] || clipped);
+
+                // Convert to DAC code
+                output.dacs[channel][i] = DacCode::from(y).0;
+            }
+        }
+
+        for lock in lock.iter_mut() {
+            lock.update();
+        }
+
+        for (alarm, saturation) in alarm.iter_mut().zip(saturation.iter()) {
+            let limit = config.saturation_alarm;
+            let batch = input.adcs[0].len() as u32;
+            *alarm = limit != 0
+                && saturation.rail >= limit
+                && saturation.rail.saturating_sub(batch) < limit;
+        }
+    }
+}
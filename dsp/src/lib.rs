@@ -0,0 +1,221 @@
+//! Hardware-independent signal processing of the Stabilizer applications
+//!
+//! # Design
+//! The per-batch signal processing of each application is implemented by a processor implementing
+//! [Process]. A processor receives a batch of raw ADC codes (together with the digital input
+//! states and the latest DI0 timestamp) and generates a batch of raw DAC codes. It does not depend
+//! on any Stabilizer hardware, so it can be exercised on the host, e.g. in `cargo test`, by feeding
+//! it synthetic [AdcCode](code::AdcCode) batches:
+//!
+//! ```sh
+//! cargo test -p stabilizer-dsp --target x86_64-unknown-linux-gnu
+//! ```
+//!
+//! On Stabilizer, the processors are called from the application `process` tasks with the ADC and
+//! DAC DMA buffers.
+#![no_std]
+
+use core::mem::MaybeUninit;
+
+pub mod code;
+pub mod dual_iir;
+pub mod lock;
+pub mod lockin;
+pub mod network_analyzer;
+pub mod signal_generator;
+pub mod statistics;
+
+/// The maximum number of samples in a batch.
+pub const MAX_BATCH_SIZE: usize = 32;
+
+/// The inputs of a batch of samples.
+pub struct Input<'a> {
+    /// The raw ADC codes of ADC0 and ADC1. See [AdcCode](code::AdcCode).
+    pub adcs: [&'a [u16]; 2],
+
+    /// The states of the digital inputs DI0 and DI1 at the time of processing.
+    pub digital_inputs: [bool; 2],
+
+    /// The latest DI0 timestamp in timer ticks, if any was captured since the previous batch.
+    pub timestamp: Option<u32>,
+
+    /// The index of the sample of the batch at which a rising edge occurred on DI0 and DI1, if any
+    /// occurred since the previous batch.
+    pub triggers: [Option<usize>; 2],
+}
+
+/// The outputs of a batch of samples.
+pub struct Output<'a> {
+    /// The raw DAC codes of DAC0 and DAC1. See [DacCode](code::DacCode).
+    pub dacs: [&'a mut [u16]; 2],
+}
+
+/// A hardware-independent batch processing routine.
+pub trait Process {
+    /// Process a batch of samples.
+    ///
+    /// # Note
+    /// The output buffers must be of the same length as the input buffers. Every output buffer
+    /// must be completely filled.
+    ///
+    /// # Args
+    /// * `input` - The inputs of the batch.
+    /// * `output` - The output buffers to fill.
+    fn process(&mut self, input: &Input<'_>, output: &mut Output<'_>);
+}
+
+/// Write the raw ADC and DAC codes of a batch as livestream data.
+///
+/// # Note
+/// The data is laid out as the codes of ADC0, ADC1, DAC0, and DAC1 in native (little-endian) byte
+/// order, i.e. the layout of the `AdcDacData` stream format.
+///
+/// # Args
+/// * `input` - The inputs of the batch.
+/// * `output` - The processed outputs of the batch.
+/// * `buf` - The stream buffer to fill. It must hold exactly the four batches of codes.
+#[inline(always)]
+pub fn stream_adc_dac(
+    input: &Input<'_>,
+    output: &Output<'_>,
+    buf: &mut [MaybeUninit<u8>],
+) {
+    let n = buf.len() / 4;
+    for (data, buf) in input
+        .adcs
+        .iter()
+        .map(|adc| &adc[..])
+        .chain(output.dacs.iter().map(|dac| &dac[..]))
+        .zip(buf.chunks_exact_mut(n))
+    {
+        // Note(unsafe): The codes are plain `u16` data and the slice covers exactly `n` bytes of
+        // them as checked by the assertion.
+        assert_eq!(core::mem::size_of_val(data), n);
+        let data = unsafe {
+            core::slice::from_raw_parts(
+                data.as_ptr() as *const MaybeUninit<u8>,
+                n,
+            )
+        };
+        buf.copy_from_slice(data)
+    }
+}
+
+/// Write the raw ADC and DAC codes of a batch followed by signal frequencies as livestream data.
+///
+/// # Note
+/// The data is laid out as in [stream_adc_dac] followed by the frequency tuning words in
+/// little-endian byte order, i.e. the layout of the `AdcDacFrequency` stream format.
+///
+/// # Args
+/// * `input` - The inputs of the batch.
+/// * `output` - The processed outputs of the batch.
+/// * `frequency` - The signal generator frequency tuning words of DAC0 and DAC1.
+/// * `buf` - The stream buffer to fill. It must hold exactly the four batches of codes and the two
+///   frequency tuning words.
+#[inline(always)]
+pub fn stream_adc_dac_frequency(
+    input: &Input<'_>,
+    output: &Output<'_>,
+    frequency: [u32; 2],
+    buf: &mut [MaybeUninit<u8>],
+) {
+    let (data, buf) = buf.split_at_mut(buf.len() - 8);
+    stream_adc_dac(input, output, data);
+
+    write_le(buf, frequency.iter().flat_map(|f| f.to_le_bytes()));
+}
+
+/// Write the IIR cascade inputs and outputs of a batch as livestream data.
+///
+/// # Note
+/// The data is laid out as the cascade inputs of channel 0 and 1 followed by the cascade outputs
+/// of channel 0 and 1 as <f32> in little-endian byte order, i.e. the layout of the `IirState`
+/// stream format.
+///
+/// # Args
+/// * `monitor` - The internal signals of the dual IIR processor.
+/// * `buf` - The stream buffer to fill. It must hold exactly the four batches of values.
+#[inline(always)]
+pub fn stream_iir_state(
+    monitor: &dual_iir::Monitor,
+    buf: &mut [MaybeUninit<u8>],
+) {
+    let n = buf.len() / 16;
+    write_le(
+        buf,
+        monitor
+            .iir_input
+            .iter()
+            .chain(monitor.iir_output.iter())
+            .flat_map(|x| x[..n].iter().flat_map(|x| x.to_le_bytes())),
+    );
+}
+
+/// Write the signal generator outputs of a batch as livestream data.
+///
+/// # Note
+/// The data is laid out as the signal generator output codes of DAC0 and DAC1 as <i16> in
+/// little-endian byte order, i.e. the layout of the `SignalGenerator` stream format.
+///
+/// # Args
+/// * `monitor` - The internal signals of the dual IIR processor.
+/// * `buf` - The stream buffer to fill. It must hold exactly the two batches of codes.
+#[inline(always)]
+pub fn stream_signal(monitor: &dual_iir::Monitor, buf: &mut [MaybeUninit<u8>]) {
+    let n = buf.len() / 4;
+    write_le(
+        buf,
+        monitor
+            .signal
+            .iter()
+            .flat_map(|x| x[..n].iter().flat_map(|x| x.to_le_bytes())),
+    );
+}
+
+/// Write the demodulated lockin signal of a batch as livestream data.
+///
+/// # Note
+/// The data is the in-phase and quadrature component as <i32> in little-endian byte order, i.e. the
+/// layout of the `LockinIq` stream format.
+///
+/// # Args
+/// * `monitor` - The internal signals of the lockin processor.
+/// * `buf` - The stream buffer to fill. It must hold exactly 8 bytes.
+#[inline(always)]
+pub fn stream_lockin_iq(
+    monitor: &lockin::Monitor,
+    buf: &mut [MaybeUninit<u8>],
+) {
+    write_le(buf, monitor.iq.iter().flat_map(|x| x.to_le_bytes()));
+}
+
+/// Write the lockin reference of a batch as livestream data.
+///
+/// # Note
+/// The data is the reference phase and frequency as <i32> in little-endian byte order, i.e. the
+/// layout of the `PhaseFrequency` stream format.
+///
+/// # Args
+/// * `monitor` - The internal signals of the lockin processor.
+/// * `buf` - The stream buffer to fill. It must hold exactly 8 bytes.
+#[inline(always)]
+pub fn stream_phase_frequency(
+    monitor: &lockin::Monitor,
+    buf: &mut [MaybeUninit<u8>],
+) {
+    write_le(
+        buf,
+        [monitor.phase, monitor.frequency]
+            .iter()
+            .flat_map(|x| x.to_le_bytes()),
+    );
+}
+
+// Fill a stream buffer with bytes.
+#[inline(always)]
+fn write_le(buf: &mut [MaybeUninit<u8>], bytes: impl Iterator<Item = u8>) {
+    for (buf, byte) in buf.iter_mut().zip(bytes) {
+        buf.write(byte);
+    }
+}
@@ -0,0 +1,299 @@
+//! Automatic lock acquisition
+//!
+//! # Design
+//! A lock state machine supervises a feedback loop. While the loop is not locked, its output is
+//! swept with a triangle signal until the error signal crosses a configurable threshold. The loop
+//! filter is then engaged, starting from the current sweep output. If the error signal exceeds a
+//! limit or the loop output is at its rails for too long, the loop falls back to sweeping.
+//!
+//! ```text
+//!              enable         threshold crossed          acquire time
+//!  Unlocked ----------> Sweeping ---------> Acquiring -------------> Locked
+//!                          ^                    |                      |
+//!                          +--------------------+----------------------+
+//!                                          fault time
+//! ```
+//!
+//! The state machine is updated once per batch, while the sweep and the threshold detection
+//! operate on every sample.
+use miniconf::Miniconf;
+use serde::Serialize;
+
+use super::signal_generator::{self, Signal, SignalGenerator};
+
+/// The states of the lock state machine.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum State {
+    /// Automatic locking is disabled. The loop filter is engaged unconditionally.
+    Unlocked,
+    /// The loop output is swept while waiting for the error signal to cross the threshold.
+    Sweeping,
+    /// The loop filter is engaged and the lock is being established.
+    Acquiring,
+    /// The loop is locked.
+    Locked,
+}
+
+/// The lock status reported as telemetry.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub struct Status {
+    /// The current lock state.
+    pub state: State,
+    /// The number of times the lock was lost and reacquisition was started.
+    pub relocks: u32,
+}
+
+/// Basic configuration of the lock state machine.
+///
+/// # Miniconf
+/// `{"enable": false, "sweep_frequency": 10.0, "sweep_amplitude": 1.0, "threshold": 0.0,
+/// "error_limit": 1000.0, "acquire_time": 0.1, "fault_time": 0.01}`
+#[derive(Copy, Clone, Debug, Miniconf)]
+pub struct BasicConfig {
+    /// Enables automatic locking.
+    pub enable: bool,
+
+    /// The frequency of the triangle sweep in Hertz.
+    pub sweep_frequency: f32,
+
+    /// The amplitude of the triangle sweep in volts.
+    pub sweep_amplitude: f32,
+
+    /// The error signal level in ADC codes that engages the loop when crossed while sweeping.
+    pub threshold: f32,
+
+    /// The maximum magnitude of the error signal in ADC codes while the loop is engaged.
+    pub error_limit: f32,
+
+    /// The time in seconds the loop must be engaged without fault to be considered locked.
+    pub acquire_time: f32,
+
+    /// The time in seconds the error may exceed its limit or the output may be at its rails before
+    /// the loop falls back to sweeping.
+    pub fault_time: f32,
+}
+
+impl Default for BasicConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            sweep_frequency: 10.0,
+            sweep_amplitude: 1.0,
+            threshold: 0.0,
+            error_limit: 1000.0,
+            acquire_time: 0.1,
+            fault_time: 0.01,
+        }
+    }
+}
+
+impl BasicConfig {
+    /// Convert configuration into lock state machine values.
+    ///
+    /// # Args
+    /// * `sample_period` - The time in seconds between samples.
+    /// * `batch_size` - The number of samples in each batch.
+    /// * `full_scale` - The full scale output voltage.
+    pub fn try_into_config(
+        self,
+        sample_period: f32,
+        batch_size: usize,
+        full_scale: f32,
+    ) -> Result<Config, signal_generator::Error> {
+        let sweep = signal_generator::BasicConfig {
+            signal: Signal::Triangle,
+            frequency: self.sweep_frequency,
+            symmetry: 0.5,
+            amplitude: self.sweep_amplitude,
+            phase: 0.0,
+            ..Default::default()
+        }
+        .try_into_config(sample_period, full_scale)?;
+
+        let batch_period = sample_period * batch_size as f32;
+
+        Ok(Config {
+            enable: self.enable,
+            sweep,
+            threshold: self.threshold,
+            error_limit: self.error_limit,
+            acquire_batches: (self.acquire_time / batch_period) as u32,
+            fault_batches: (self.fault_time / batch_period) as u32,
+        })
+    }
+}
+
+/// The configuration of the lock state machine.
+#[derive(Copy, Clone, Debug)]
+pub struct Config {
+    /// Enables automatic locking.
+    pub enable: bool,
+
+    /// The triangle sweep.
+    pub sweep: signal_generator::Config,
+
+    /// The error signal level that engages the loop when crossed while sweeping.
+    pub threshold: f32,
+
+    /// The maximum magnitude of the error signal while the loop is engaged.
+    pub error_limit: f32,
+
+    /// The number of batches the loop must be engaged without fault to be considered locked.
+    pub acquire_batches: u32,
+
+    /// The number of consecutive faulty batches after which the loop falls back to sweeping.
+    pub fault_batches: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            sweep: signal_generator::Config::default(),
+            threshold: 0.0,
+            error_limit: 0.0,
+            acquire_batches: 0,
+            fault_batches: 0,
+        }
+    }
+}
+
+/// The lock state machine of a single loop.
+#[derive(Debug)]
+pub struct Lock {
+    config: Config,
+    state: State,
+    sweep: SignalGenerator,
+    above: Option<bool>,
+    fault: bool,
+    batches: u32,
+    faults: u32,
+    relocks: u32,
+}
+
+impl Lock {
+    /// Construct a lock state machine.
+    ///
+    /// # Args
+    /// * `config` - The initial configuration.
+    pub fn new(config: Config) -> Self {
+        let mut lock = Self {
+            config: Config::default(),
+            state: State::Unlocked,
+            sweep: SignalGenerator::new(config.sweep),
+            above: None,
+            fault: false,
+            batches: 0,
+            faults: 0,
+            relocks: 0,
+        };
+        lock.configure(config);
+        lock
+    }
+
+    /// Update the configuration.
+    ///
+    /// # Note
+    /// Enabling automatic locking starts sweeping. Disabling it engages the loop unconditionally.
+    ///
+    /// # Args
+    /// * `config` - The new configuration.
+    pub fn configure(&mut self, config: Config) {
+        self.sweep.update_waveform(config.sweep);
+
+        if config.enable && !self.config.enable {
+            self.start_sweep();
+        } else if !config.enable {
+            self.state = State::Unlocked;
+        }
+
+        self.config = config;
+    }
+
+    /// Get the current lock status.
+    pub fn status(&self) -> Status {
+        Status {
+            state: self.state,
+            relocks: self.relocks,
+        }
+    }
+
+    /// Check if the loop filter is engaged.
+    pub fn is_engaged(&self) -> bool {
+        self.state != State::Sweeping
+    }
+
+    /// Get the next sweep output while sweeping.
+    ///
+    /// # Note
+    /// The state changes to [State::Acquiring] if the error signal crosses the threshold. The
+    /// loop filter should then be engaged starting from the returned output.
+    ///
+    /// # Args
+    /// * `error` - The current error signal.
+    ///
+    /// # Returns
+    /// The sweep output.
+    #[inline]
+    pub fn sweep(&mut self, error: f32) -> i16 {
+        // Note(unwrap): The signal generator is an infinite iterator.
+        let output = self.sweep.next().unwrap();
+
+        let above = error > self.config.threshold;
+        if self.above == Some(!above) {
+            self.state = State::Acquiring;
+            self.batches = 0;
+            self.faults = 0;
+            self.fault = false;
+        }
+        self.above = Some(above);
+
+        output
+    }
+
+    /// Supervise the engaged loop.
+    ///
+    /// # Args
+    /// * `error` - The current error signal.
+    /// * `railed` - Specified true if the loop output is at its rails.
+    #[inline]
+    pub fn supervise(&mut self, error: f32, railed: bool) {
+        self.fault |= railed || error.abs() > self.config.error_limit;
+    }
+
+    /// Update the state machine at the end of a batch.
+    pub fn update(&mut self) {
+        let fault = core::mem::replace(&mut self.fault, false);
+
+        match self.state {
+            State::Unlocked | State::Sweeping => return,
+            State::Acquiring | State::Locked => {}
+        }
+
+        if fault {
+            self.faults = self.faults.saturating_add(1);
+        } else {
+            self.faults = 0;
+        }
+
+        if self.faults > self.config.fault_batches {
+            if self.state == State::Locked {
+                self.relocks = self.relocks.wrapping_add(1);
+            }
+            self.start_sweep();
+        } else if self.state == State::Acquiring {
+            self.batches += 1;
+            if self.batches > self.config.acquire_batches {
+                self.state = State::Locked;
+            }
+        }
+    }
+
+    fn start_sweep(&mut self) {
+        self.state = State::Sweeping;
+        self.sweep.clear_phase_accumulator();
+        // Wait for a full threshold crossing.
+        self.above = None;
+        self.fault = false;
+    }
+}
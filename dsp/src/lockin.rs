@@ -0,0 +1,208 @@
+//! Lock-in amplifier signal processing
+//!
+//! # Design
+//! The ADC0 signal is demodulated using either an internally generated reference or a reference
+//! recovered from the DI0 timestamps by a reciprocal PLL. The demodulated signal is low-pass
+//! filtered, decimated to one value per batch, and converted to the configured outputs on DAC0 and
+//! DAC1.
+use idsp::{Accu, Complex, ComplexExt, RPLL};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    code::DacCode,
+    signal_generator::{self, SignalGenerator},
+    Input, Output, Process,
+};
+
+/// The DAC output modes.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum Conf {
+    /// Output the lockin magnitude.
+    Magnitude,
+    /// Output the phase of the lockin
+    Phase,
+    /// Output the lockin reference frequency as a sinusoid
+    ReferenceFrequency,
+    /// Output the logarithmic power of the lockin
+    LogPower,
+    /// Output the in-phase component of the lockin signal.
+    InPhase,
+    /// Output the quadrature component of the lockin signal.
+    Quadrature,
+    /// Output the lockin internal modulation frequency as a sinusoid
+    Modulation,
+}
+
+/// The reference sources of the lockin.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum LockinMode {
+    /// Utilize an internally generated reference for demodulation
+    Internal,
+    /// Utilize an external modulation signal supplied to DI0
+    External,
+}
+
+/// The processing configuration of the lockin.
+#[derive(Copy, Clone, Debug)]
+pub struct Config {
+    /// The operational mode of the lockin.
+    pub lockin_mode: LockinMode,
+
+    /// The PLL frequency and phase time constants (log2 counter cycles, 1-31).
+    pub pll_tc: [u32; 2],
+
+    /// The lockin low-pass time constant.
+    pub lockin_tc: u32,
+
+    /// Harmonic index of the LO. -1 to _de_modulate the fundamental (complex conjugate)
+    pub lockin_harmonic: i32,
+
+    /// Demodulation LO phase offset. [i32::MIN] is equivalent to -pi and [i32::MAX] to +pi.
+    pub lockin_phase: i32,
+
+    /// The output mode of DAC0 and DAC1.
+    pub output_conf: [Conf; 2],
+}
+
+/// The internal signals of the latest batch for monitoring.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Monitor {
+    /// The demodulated in-phase and quadrature components, full scale `i32`.
+    pub iq: [i32; 2],
+
+    /// The reference phase at the start of the batch. [i32::MIN] is equivalent to -pi and
+    /// [i32::MAX] to +pi.
+    pub phase: i32,
+
+    /// The reference frequency tuning word, where `1 << 32` corresponds to the sample rate.
+    pub frequency: i32,
+}
+
+/// The lockin processor.
+pub struct Lockin {
+    /// The processing configuration.
+    pub config: Config,
+
+    pll: RPLL,
+    lockin: idsp::Lockin<4>,
+    signal_generator: SignalGenerator,
+    batch_size_log2: u32,
+    monitor: Monitor,
+}
+
+impl Lockin {
+    /// Construct a lockin processor.
+    ///
+    /// # Args
+    /// * `config` - The initial processing configuration.
+    /// * `sample_ticks_log2` - The logarithm of the number of timer ticks between samples.
+    /// * `batch_size_log2` - The logarithm of the number of samples in each batch.
+    pub fn new(
+        config: Config,
+        sample_ticks_log2: u32,
+        batch_size_log2: u32,
+    ) -> Self {
+        let signal_config = signal_generator::Config {
+            // Same frequency as batch size.
+            phase_increment: [1 << (32 - batch_size_log2); 2],
+            // 1V Amplitude
+            amplitude: DacCode::try_from(1.0).unwrap().into(),
+            signal: signal_generator::Signal::Cosine,
+            phase_offset: 0,
+            chirp: Default::default(),
+            noise: Default::default(),
+            trigger: Default::default(),
+        };
+
+        Self {
+            config,
+            pll: RPLL::new(sample_ticks_log2 + batch_size_log2),
+            lockin: idsp::Lockin::default(),
+            signal_generator: SignalGenerator::new(signal_config),
+            batch_size_log2,
+            monitor: Monitor::default(),
+        }
+    }
+
+    /// Get the internal signals of the latest batch.
+    pub fn monitor(&self) -> &Monitor {
+        &self.monitor
+    }
+}
+
+impl Process for Lockin {
+    /// This is an implementation of a externally (DI0) referenced PLL lockin on the ADC0 signal.
+    /// It outputs either I/Q or power/phase on DAC0/DAC1. Data is normalized to full scale.
+    /// PLL bandwidth, filter bandwidth, slope, and x/y or power/phase post-filters are available.
+    // Note: This is always inlined so that it ends up in the (ITCM) section of the calling task.
+    #[inline(always)]
+    fn process(&mut self, input: &Input<'_>, output: &mut Output<'_>) {
+        let Self {
+            config,
+            pll,
+            lockin,
+            signal_generator,
+            batch_size_log2,
+            monitor,
+        } = self;
+
+        let (reference_phase, reference_frequency) = match config.lockin_mode {
+            LockinMode::External => {
+                let (pll_phase, pll_frequency) = pll.update(
+                    input.timestamp.map(|t| t as i32),
+                    config.pll_tc[0],
+                    config.pll_tc[1],
+                );
+                (pll_phase, (pll_frequency >> *batch_size_log2) as i32)
+            }
+            LockinMode::Internal => {
+                // Reference phase and frequency are known.
+                (1i32 << 30, 1i32 << (32 - *batch_size_log2))
+            }
+        };
+
+        let sample_frequency =
+            reference_frequency.wrapping_mul(config.lockin_harmonic);
+        let sample_phase = config
+            .lockin_phase
+            .wrapping_add(reference_phase.wrapping_mul(config.lockin_harmonic));
+
+        let demodulated: Complex<i32> = input.adcs[0]
+            .iter()
+            // Zip in the LO phase.
+            .zip(Accu::new(sample_phase, sample_frequency))
+            // Convert to signed, MSB align the ADC sample, update the Lockin (demodulate, filter)
+            .map(|(&sample, phase)| {
+                let s = (sample as i16 as i32) << 16;
+                lockin.update(s, phase, config.lockin_tc)
+            })
+            // Decimate
+            .last()
+            .unwrap()
+            * 2; // Full scale assuming the 2f component is gone.
+
+        *monitor = Monitor {
+            iq: [demodulated.re, demodulated.im],
+            phase: reference_phase,
+            frequency: reference_frequency,
+        };
+
+        // Convert to DAC data.
+        for (channel, samples) in output.dacs.iter_mut().enumerate() {
+            for sample in samples.iter_mut() {
+                let value = match config.output_conf[channel] {
+                    Conf::Magnitude => demodulated.abs_sqr() as i32 >> 16,
+                    Conf::Phase => demodulated.arg() >> 16,
+                    Conf::LogPower => demodulated.log2() << 8,
+                    Conf::ReferenceFrequency => reference_frequency >> 16,
+                    Conf::InPhase => demodulated.re >> 16,
+                    Conf::Quadrature => demodulated.im >> 16,
+
+                    Conf::Modulation => signal_generator.next().unwrap() as i32,
+                };
+
+                *sample = DacCode::from(value as i16).0;
+            }
+        }
+    }
+}
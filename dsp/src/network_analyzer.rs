@@ -0,0 +1,388 @@
+//! On-device network analyzer
+//!
+//! # Design
+//! The network analyzer measures the transfer function from one DAC output to both ADC inputs at
+//! a list of frequencies. At each frequency, a cosine excitation is added to the output of the
+//! excited channel. After a settling time, both ADC inputs are demodulated with the excitation
+//! (a digital lock-in) over an integration time. The complex response of both inputs is then
+//! reported as a [Point] and the analyzer steps to the next frequency.
+//!
+//! The measured response is the complete response from the DAC code to the ADC code. It includes
+//! the converter and processing latency as well as the response of any loop closed through the
+//! excited channel, so it can be used to measure both plant and closed-loop responses in situ.
+use core::fmt;
+
+use miniconf::Miniconf;
+use serde::{
+    de::{SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+/// The maximum number of frequencies of a measurement.
+pub const MAX_POINTS: usize = 32;
+
+/// A list of up to [MAX_POINTS] frequencies in Hertz.
+///
+/// # Miniconf
+/// A JSON array of frequencies, e.g. `[10.0, 100.0, 1000.0]`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Frequencies {
+    frequencies: [f32; MAX_POINTS],
+    len: usize,
+}
+
+impl Frequencies {
+    /// Get the frequencies.
+    pub fn as_slice(&self) -> &[f32] {
+        &self.frequencies[..self.len]
+    }
+}
+
+impl TryFrom<&[f32]> for Frequencies {
+    type Error = Error;
+
+    fn try_from(frequencies: &[f32]) -> Result<Self, Error> {
+        if frequencies.len() > MAX_POINTS {
+            return Err(Error::TooManyPoints);
+        }
+
+        let mut list = Self::default();
+        list.frequencies[..frequencies.len()].copy_from_slice(frequencies);
+        list.len = frequencies.len();
+        Ok(list)
+    }
+}
+
+impl Serialize for Frequencies {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.as_slice())
+    }
+}
+
+impl<'de> Deserialize<'de> for Frequencies {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        struct FrequenciesVisitor;
+
+        impl<'de> Visitor<'de> for FrequenciesVisitor {
+            type Value = Frequencies;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a sequence of at most {} frequencies", MAX_POINTS)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<Frequencies, A::Error> {
+                let mut list = Frequencies::default();
+                while let Some(frequency) = seq.next_element()? {
+                    if list.len == MAX_POINTS {
+                        return Err(serde::de::Error::invalid_length(
+                            list.len + 1,
+                            &self,
+                        ));
+                    }
+                    list.frequencies[list.len] = frequency;
+                    list.len += 1;
+                }
+                Ok(list)
+            }
+        }
+
+        deserializer.deserialize_seq(FrequenciesVisitor)
+    }
+}
+
+/// Basic configuration of the network analyzer.
+///
+/// # Miniconf
+/// `{"enable": false, "channel": 0, "amplitude": 0.1, "frequencies": [100.0, 1000.0],
+/// "settle_time": 0.01, "integration_time": 0.1, "repeat": false}`
+///
+/// Where `channel` is the excited DAC channel, `amplitude` is the excitation amplitude in volts,
+/// `frequencies` is a list of up to [MAX_POINTS] frequencies in Hertz, and `settle_time` and
+/// `integration_time` are the times in seconds to wait and to integrate at each frequency.
+#[derive(Copy, Clone, Debug, PartialEq, Miniconf)]
+pub struct BasicConfig {
+    /// Run the measurement. The measurement restarts at the first frequency when the configuration
+    /// changes.
+    pub enable: bool,
+
+    /// The DAC channel the excitation is added to.
+    pub channel: usize,
+
+    /// The amplitude of the excitation in volts.
+    pub amplitude: f32,
+
+    /// The frequencies of the measurement in Hertz.
+    pub frequencies: Frequencies,
+
+    /// The time in seconds to wait after changing the frequency before integrating.
+    pub settle_time: f32,
+
+    /// The time in seconds to integrate the response at each frequency.
+    pub integration_time: f32,
+
+    /// Restart the measurement at the first frequency once it ends.
+    pub repeat: bool,
+}
+
+impl Default for BasicConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            channel: 0,
+            amplitude: 0.1,
+            frequencies: Frequencies::default(),
+            settle_time: 0.01,
+            integration_time: 0.1,
+            repeat: false,
+        }
+    }
+}
+
+/// Errors that can occur when configuring the network analyzer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The excited channel does not exist.
+    InvalidChannel,
+    /// The excitation amplitude is zero or out of range.
+    InvalidAmplitude,
+    /// A frequency is not between zero and the Nyquist frequency.
+    InvalidFrequency,
+    /// The settling or integration time is out of range.
+    InvalidTime,
+    /// There are more than [MAX_POINTS] frequencies.
+    TooManyPoints,
+}
+
+impl BasicConfig {
+    /// Convert the configuration into network analyzer values.
+    ///
+    /// # Args
+    /// * `sample_period` - The time in seconds between samples.
+    /// * `full_scale` - The full scale output voltage.
+    pub fn try_into_config(
+        self,
+        sample_period: f32,
+        full_scale: f32,
+    ) -> Result<Config, Error> {
+        if self.channel > 1 {
+            return Err(Error::InvalidChannel);
+        }
+
+        let amplitude = self.amplitude * (i16::MIN as f32 / -full_scale);
+        if !(1.0..=i16::MAX as f32).contains(&amplitude) {
+            return Err(Error::InvalidAmplitude);
+        }
+
+        let mut frequencies = [0; MAX_POINTS];
+        for (ftw, frequency) in
+            frequencies.iter_mut().zip(self.frequencies.as_slice())
+        {
+            let turns = frequency * sample_period;
+            if !(turns > 0.0 && turns < 0.5) {
+                return Err(Error::InvalidFrequency);
+            }
+            *ftw = (turns * (1u64 << 32) as f32) as u32;
+        }
+
+        let settle = self.settle_time / sample_period;
+        let integrate = self.integration_time / sample_period;
+        if !(0.0..=u32::MAX as f32 / 2.).contains(&settle)
+            || !(1.0..=u32::MAX as f32 / 2.).contains(&integrate)
+        {
+            return Err(Error::InvalidTime);
+        }
+
+        Ok(Config {
+            enable: self.enable,
+            channel: self.channel,
+            amplitude: amplitude as i16,
+            frequencies,
+            points: self.frequencies.as_slice().len(),
+            settle: settle as u32,
+            integrate: integrate as u32,
+            repeat: self.repeat,
+        })
+    }
+}
+
+/// The configuration of the network analyzer.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Config {
+    /// Run the measurement.
+    pub enable: bool,
+
+    /// The DAC channel the excitation is added to.
+    pub channel: usize,
+
+    /// The full-scale output code of the excitation.
+    pub amplitude: i16,
+
+    /// The frequency tuning words of the measurement, where `1 << 32` corresponds to the sample
+    /// rate.
+    pub frequencies: [u32; MAX_POINTS],
+
+    /// The number of valid `frequencies`.
+    pub points: usize,
+
+    /// The number of samples to wait after changing the frequency.
+    pub settle: u32,
+
+    /// The number of samples to integrate at each frequency.
+    pub integrate: u32,
+
+    /// Restart the measurement once it ends.
+    pub repeat: bool,
+}
+
+/// The measured response at a frequency.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Point {
+    /// The index of the frequency in the configured list.
+    pub index: usize,
+
+    /// The frequency tuning word, where `1 << 32` corresponds to the sample rate.
+    pub frequency: u32,
+
+    /// The complex response `[real, imaginary]` of ADC0 and ADC1. The response is the ratio of the
+    /// ADC codes to the DAC codes of the excitation.
+    pub response: [[f32; 2]; 2],
+}
+
+impl Point {
+    /// Get the magnitude of the response of ADC0 and ADC1.
+    pub fn magnitude(&self) -> [f32; 2] {
+        self.response.map(|[re, im]| libm::sqrtf(re * re + im * im))
+    }
+
+    /// Get the phase of the response of ADC0 and ADC1 in turns.
+    pub fn phase(&self) -> [f32; 2] {
+        self.response.map(|[re, im]| {
+            libm::atan2f(im, re) * (1. / (2. * core::f32::consts::PI))
+        })
+    }
+}
+
+/// The network analyzer.
+#[derive(Clone, Debug, Default)]
+pub struct NetworkAnalyzer {
+    config: Config,
+    running: bool,
+    index: usize,
+    count: u32,
+    phase: i32,
+    sum: [[i64; 2]; 2],
+    result: Option<Point>,
+}
+
+impl NetworkAnalyzer {
+    /// Construct a network analyzer.
+    ///
+    /// # Args
+    /// * `config` - The initial configuration.
+    pub fn new(config: Config) -> Self {
+        let mut analyzer = Self::default();
+        analyzer.configure(config);
+        analyzer
+    }
+
+    /// Update the configuration.
+    ///
+    /// # Note
+    /// The measurement restarts at the first frequency.
+    ///
+    /// # Args
+    /// * `config` - The new configuration.
+    pub fn configure(&mut self, config: Config) {
+        *self = Self {
+            config,
+            running: config.enable && config.points > 0,
+            ..Default::default()
+        };
+    }
+
+    /// Get the excited channel.
+    ///
+    /// # Returns
+    /// The excited DAC channel while a measurement is running.
+    pub fn channel(&self) -> Option<usize> {
+        self.running.then_some(self.config.channel)
+    }
+
+    /// Take the latest measured point.
+    ///
+    /// # Note
+    /// This should be called after every batch. A point is lost if it is not taken before the next
+    /// point is measured.
+    pub fn take_result(&mut self) -> Option<Point> {
+        self.result.take()
+    }
+
+    /// Process a sample.
+    ///
+    /// # Args
+    /// * `x` - The ADC0 and ADC1 codes of the sample.
+    ///
+    /// # Returns
+    /// The excitation code to add to the output of the excited channel.
+    #[inline]
+    pub fn update(&mut self, x: [i16; 2]) -> i16 {
+        if !self.running {
+            return 0;
+        }
+
+        let (cos, sin) = idsp::cossin(self.phase);
+        let (cos, sin) = ((cos >> 16) as i64, (sin >> 16) as i64);
+
+        if self.count >= self.config.settle {
+            for (sum, x) in self.sum.iter_mut().zip(x) {
+                sum[0] += x as i64 * cos;
+                sum[1] += x as i64 * sin;
+            }
+        }
+
+        self.count += 1;
+        self.phase = self
+            .phase
+            .wrapping_add(self.config.frequencies[self.index] as i32);
+
+        if self.count == self.config.settle + self.config.integrate {
+            self.finish_point();
+        }
+
+        ((self.config.amplitude as i64 * cos) >> 15) as i16
+    }
+
+    fn finish_point(&mut self) {
+        // The demodulated sums are `N * A * (1 << 15) / 2` times the complex response.
+        let scale = 2.0
+            / (self.config.integrate as f32
+                * self.config.amplitude as f32
+                * (1 << 15) as f32);
+
+        self.result = Some(Point {
+            index: self.index,
+            frequency: self.config.frequencies[self.index],
+            response: self
+                .sum
+                .map(|[i, q]| [i as f32 * scale, -q as f32 * scale]),
+        });
+
+        self.index += 1;
+        if self.index == self.config.points {
+            self.index = 0;
+            self.running = self.config.repeat;
+        }
+
+        self.count = 0;
+        self.phase = 0;
+        self.sum = [[0; 2]; 2];
+    }
+}
@@ -0,0 +1,783 @@
+use idsp::iir;
+use miniconf::Miniconf;
+use rand_core::{RngCore, SeedableRng};
+use rand_xorshift::XorShiftRng;
+use serde::{Deserialize, Serialize};
+
+/// The maximum number of samples of an arbitrary [Waveform].
+pub const WAVEFORM_CAPACITY: usize = 1024;
+
+/// The number of octaves spanned by [Signal::PinkNoise].
+pub const PINK_NOISE_OCTAVES: usize = 15;
+
+/// Types of signals that can be generated.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Signal {
+    Cosine,
+    Square,
+    Triangle,
+    WhiteNoise,
+    /// Noise with a power spectral density proportional to 1/f over [PINK_NOISE_OCTAVES] octaves
+    /// below the Nyquist frequency.
+    PinkNoise,
+    /// White noise low-pass filtered to a bandwidth of the configured frequency.
+    BandLimitedNoise,
+    /// Play back the [Waveform] of the signal generator. One signal period spans the complete
+    /// waveform.
+    Arbitrary,
+}
+
+/// Frequency sweep (chirp) modes of a generated signal.
+#[derive(
+    Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize,
+)]
+pub enum ChirpMode {
+    /// The signal frequency is constant.
+    #[default]
+    Off,
+    /// The signal frequency changes linearly with time.
+    Linear,
+    /// The signal frequency changes exponentially with time, i.e. at a constant number of octaves
+    /// per second.
+    Logarithmic,
+}
+
+/// Digital inputs that can trigger a generated signal.
+#[derive(
+    Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize,
+)]
+pub enum TriggerSource {
+    /// The signal is not triggered.
+    #[default]
+    Off,
+    /// The signal is triggered by rising edges on DI0.
+    Di0,
+    /// The signal is triggered by rising edges on DI1.
+    Di1,
+}
+
+impl TriggerSource {
+    /// Get the index of the triggering digital input, if any.
+    pub fn input(&self) -> Option<usize> {
+        match self {
+            Self::Off => None,
+            Self::Di0 => Some(0),
+            Self::Di1 => Some(1),
+        }
+    }
+}
+
+/// Amplitude distributions of generated noise.
+#[derive(
+    Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize,
+)]
+pub enum Distribution {
+    /// Uniformly distributed within the signal amplitude. The RMS value is `amplitude / sqrt(3)`.
+    #[default]
+    Uniform,
+    /// Approximately normally distributed as the mean of four uniform samples. The RMS value is
+    /// `amplitude / sqrt(12)` and the peak value is the signal amplitude.
+    Gaussian,
+}
+
+/// Basic configuration for a generated signal.
+///
+/// # Miniconf
+/// `{"signal": <signal>, "frequency", 1000.0, "symmetry": 0.5, "amplitude": 1.0, "phase": 0.0,
+/// "chirp": <chirp>, "stop_frequency": 10000.0, "duration": 1.0, "repeat": true,
+/// "distribution": <distribution>, "seed": 0, "trigger": <trigger>, "burst": 0}`
+///
+/// Where `<signal>` may be any of [Signal] variants, `frequency` specifies the signal frequency
+/// in Hertz, `symmetry` specifies the normalized signal symmetry which ranges from 0 - 1.0, and
+/// `amplitude` specifies the signal amplitude in Volts. `<chirp>` may be any of [ChirpMode]
+/// variants. While chirping, the frequency is swept from `frequency` to `stop_frequency` (in Hertz)
+/// within `duration` seconds. Noise signals are drawn from the [Distribution] `<distribution>`
+/// of a pseudo-random sequence that is determined by `seed`. `<trigger>` may be any of
+/// [TriggerSource] variants. On a trigger, the signal phase and any sweep restart and `burst`
+/// signal periods are generated.
+#[derive(Copy, Clone, Debug, PartialEq, Miniconf)]
+pub struct BasicConfig {
+    /// The signal type that should be generated. See [Signal] variants.
+    pub signal: Signal,
+
+    /// The frequency of the generated signal in Hertz.
+    pub frequency: f32,
+
+    /// The normalized symmetry of the signal. At 0% symmetry, the duration of the first half oscillation is minimal.
+    /// At 25% symmetry, the first half oscillation lasts for 25% of the signal period. For square wave output this
+    /// symmetry is the duty cycle.
+    pub symmetry: f32,
+
+    /// The amplitude of the output signal in volts.
+    pub amplitude: f32,
+
+    /// The phase of the output signal in turns.
+    pub phase: f32,
+
+    /// The frequency sweep mode. While chirping, `frequency` is the start frequency of the sweep.
+    pub chirp: ChirpMode,
+
+    /// The frequency at the end of the sweep in Hertz.
+    pub stop_frequency: f32,
+
+    /// The duration of the sweep in seconds.
+    pub duration: f32,
+
+    /// Restart the sweep at the start frequency once it ends. Otherwise the signal stays at the
+    /// stop frequency.
+    pub repeat: bool,
+
+    /// The amplitude distribution of noise signals.
+    pub distribution: Distribution,
+
+    /// The seed of the pseudo-random noise sequence. The sequence restarts when the seed changes.
+    pub seed: u64,
+
+    /// The digital input that triggers the signal.
+    pub trigger: TriggerSource,
+
+    /// The number of signal periods generated after each trigger. The output is zero between
+    /// bursts. If zero, the signal is generated continuously and a trigger only restarts it.
+    pub burst: u32,
+}
+
+impl Default for BasicConfig {
+    fn default() -> Self {
+        Self {
+            frequency: 1.0e3,
+            symmetry: 0.5,
+            signal: Signal::Cosine,
+            amplitude: 0.0,
+            phase: 0.0,
+            chirp: ChirpMode::Off,
+            stop_frequency: 10.0e3,
+            duration: 1.0,
+            repeat: true,
+            distribution: Distribution::Uniform,
+            seed: 0,
+            trigger: TriggerSource::Off,
+            burst: 0,
+        }
+    }
+}
+
+/// Represents the errors that can occur when attempting to configure the signal generator.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The provided amplitude is out-of-range.
+    InvalidAmplitude,
+    /// The provided symmetry is out of range.
+    InvalidSymmetry,
+    /// The provided frequency is out of range.
+    InvalidFrequency,
+    /// The provided sweep duration is out of range.
+    InvalidDuration,
+    /// A provided waveform sample is out of range.
+    InvalidSample,
+    /// The provided waveform samples do not directly follow the existing samples or exceed the
+    /// [WAVEFORM_CAPACITY].
+    InvalidOffset,
+}
+
+impl BasicConfig {
+    /// Convert configuration into signal generator values.
+    ///
+    /// # Args
+    /// * `sample_period` - The time in seconds between samples.
+    /// * `full_scale` - The full scale output voltage.
+    pub fn try_into_config(
+        self,
+        sample_period: f32,
+        full_scale: f32,
+    ) -> Result<Config, Error> {
+        let symmetry_complement = 1.0 - self.symmetry;
+        // Validate symmetry
+        if self.symmetry < 0.0 || symmetry_complement < 0.0 {
+            return Err(Error::InvalidSymmetry);
+        }
+
+        const NYQUIST: f32 = (1u32 << 31) as _;
+        let ftw = self.frequency * sample_period * NYQUIST;
+
+        // Validate base frequency tuning word to be below Nyquist.
+        if ftw < 0.0 || 2.0 * ftw > NYQUIST {
+            return Err(Error::InvalidFrequency);
+        }
+
+        let chirp = if self.chirp == ChirpMode::Off {
+            Chirp::default()
+        } else {
+            let stop = self.stop_frequency * sample_period * NYQUIST;
+            if stop < 0.0 || 2.0 * stop > NYQUIST {
+                return Err(Error::InvalidFrequency);
+            }
+
+            let length = self.duration / sample_period;
+            if !(1.0..=u32::MAX as f32).contains(&length) {
+                return Err(Error::InvalidDuration);
+            }
+
+            let (start, stop) = (ftw as f64, stop as f64);
+            let step = if self.chirp == ChirpMode::Linear {
+                (stop - start) / length as f64
+            } else {
+                // A logarithmic sweep cannot start or stop at zero frequency.
+                if start <= 0.0 || stop <= 0.0 {
+                    return Err(Error::InvalidFrequency);
+                }
+                libm::pow(stop / start, 1.0 / length as f64)
+            };
+
+            Chirp {
+                mode: self.chirp,
+                start,
+                step,
+                length: length as u32,
+                repeat: self.repeat,
+                scale: [
+                    1.0 / self.symmetry as f64,
+                    1.0 / symmetry_complement as f64,
+                ],
+            }
+        };
+
+        // Calculate the frequency tuning words.
+        // Clip both frequency tuning words to within Nyquist before rounding.
+        let phase_increment = [
+            if self.symmetry * NYQUIST > ftw {
+                ftw / self.symmetry
+            } else {
+                NYQUIST
+            } as i32,
+            if symmetry_complement * NYQUIST > ftw {
+                ftw / symmetry_complement
+            } else {
+                NYQUIST
+            } as i32,
+        ];
+
+        let amplitude = self.amplitude * (i16::MIN as f32 / -full_scale);
+        if !(i16::MIN as f32..=i16::MAX as f32).contains(&amplitude) {
+            return Err(Error::InvalidAmplitude);
+        }
+
+        let phase = self.phase * (1u64 << 32) as f32;
+
+        let mut filter = iir::IIR::default();
+        if let Signal::BandLimitedNoise = self.signal {
+            // A band-limited signal needs a non-zero bandwidth.
+            if ftw <= 0.0 {
+                return Err(Error::InvalidFrequency);
+            }
+            filter = Noise::lowpass(self.frequency * sample_period);
+        }
+
+        Ok(Config {
+            amplitude: amplitude as i16,
+            signal: self.signal,
+            phase_increment,
+            phase_offset: phase as i32,
+            chirp,
+            noise: Noise {
+                distribution: self.distribution,
+                seed: self.seed,
+                filter,
+            },
+            trigger: Trigger {
+                source: self.trigger,
+                burst: self.burst,
+            },
+        })
+    }
+}
+
+/// The frequency sweep (chirp) configuration of a generated signal.
+///
+/// # Note
+/// The sweep is computed in double precision to avoid accumulating rounding errors over long
+/// sweeps. Frequency tuning words are in units of the base frequency tuning word of
+/// [BasicConfig::try_into_config], where `1 << 31` corresponds to the Nyquist frequency.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Chirp {
+    /// The sweep mode.
+    pub mode: ChirpMode,
+
+    /// The frequency tuning word at the start of the sweep.
+    pub start: f64,
+
+    /// The change of the frequency tuning word per sample. For a linear sweep, the step is added
+    /// to the frequency tuning word. For a logarithmic sweep, it is a factor.
+    pub step: f64,
+
+    /// The number of samples of the sweep.
+    pub length: u32,
+
+    /// Restart the sweep once it ends.
+    pub repeat: bool,
+
+    /// The factors from the frequency tuning word to the phase increments of the first and second
+    /// half oscillation.
+    pub scale: [f64; 2],
+}
+
+/// The noise configuration of a generated signal.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Noise {
+    /// The amplitude distribution of the noise.
+    pub distribution: Distribution,
+
+    /// The seed of the pseudo-random sequence.
+    pub seed: u64,
+
+    /// The low-pass filter of [Signal::BandLimitedNoise], operating on full scale codes.
+    pub filter: iir::IIR<f32>,
+}
+
+impl Noise {
+    /// Design the low-pass filter of band-limited noise.
+    ///
+    /// # Note
+    /// The filter is a second order Butterworth low-pass. Its gain is chosen such that the RMS
+    /// value of the filtered noise is approximately that of the unfiltered noise. The output is
+    /// limited to full scale.
+    ///
+    /// # Args
+    /// * `bandwidth` - The cutoff frequency in units of the sample rate.
+    pub fn lowpass(bandwidth: f32) -> iir::IIR<f32> {
+        let w0 = 2.0 * core::f32::consts::PI * bandwidth;
+        let (sin, cos) = (libm::sinf(w0), libm::cosf(w0));
+        // Q = 1/sqrt(2)
+        let alpha = sin * core::f32::consts::FRAC_1_SQRT_2;
+        let a0 = 1.0 + alpha;
+
+        // The equivalent noise bandwidth of the filter is `pi / (2 sqrt(2))` times its cutoff
+        // frequency. Compensate the noise power outside of that bandwidth.
+        let enbw = core::f32::consts::PI
+            * core::f32::consts::FRAC_1_SQRT_2
+            * 0.5
+            * bandwidth;
+        let gain = libm::sqrtf(0.5 / enbw).max(1.0);
+
+        let b = gain * (1.0 - cos) / (2.0 * a0);
+        iir::IIR {
+            ba: [b, 2.0 * b, b, 2.0 * cos / a0, (alpha - 1.0) / a0],
+            y_offset: 0.0,
+            y_min: i16::MIN as f32,
+            y_max: i16::MAX as f32,
+        }
+    }
+}
+
+/// The trigger configuration of a generated signal.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Trigger {
+    /// The digital input that triggers the signal.
+    pub source: TriggerSource,
+
+    /// The number of signal periods generated after each trigger. If zero, the signal is
+    /// generated continuously.
+    pub burst: u32,
+}
+
+impl Trigger {
+    /// Check whether the signal is generated in triggered bursts.
+    pub fn is_burst(&self) -> bool {
+        self.source != TriggerSource::Off && self.burst > 0
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Config {
+    /// The type of signal being generated
+    pub signal: Signal,
+
+    /// The full-scale output code of the signal
+    pub amplitude: i16,
+
+    /// The frequency tuning word of the signal. Phase is incremented by this amount
+    pub phase_increment: [i32; 2],
+
+    /// The phase offset
+    pub phase_offset: i32,
+
+    /// The frequency sweep configuration.
+    pub chirp: Chirp,
+
+    /// The noise configuration.
+    pub noise: Noise,
+
+    /// The trigger configuration.
+    pub trigger: Trigger,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            signal: Signal::Cosine,
+            amplitude: 0,
+            phase_increment: [0, 0],
+            phase_offset: 0,
+            chirp: Chirp::default(),
+            noise: Noise::default(),
+            trigger: Trigger::default(),
+        }
+    }
+}
+
+/// A lookup table of samples for arbitrary waveform generation.
+///
+/// # Note
+/// Samples are stored as full scale codes: `i16::MAX` corresponds to the configured signal
+/// amplitude. The waveform is played back with linear interpolation between adjacent samples,
+/// wrapping around from the last to the first sample.
+#[derive(Clone, Debug)]
+pub struct Waveform {
+    samples: [i16; WAVEFORM_CAPACITY],
+    len: usize,
+}
+
+impl Default for Waveform {
+    fn default() -> Self {
+        Self {
+            samples: [0; WAVEFORM_CAPACITY],
+            len: 0,
+        }
+    }
+}
+
+impl Waveform {
+    /// Get the samples of the waveform.
+    pub fn samples(&self) -> &[i16] {
+        &self.samples[..self.len]
+    }
+
+    /// Remove all samples of the waveform.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Write full scale codes to the waveform.
+    ///
+    /// # Note
+    /// Writing may replace existing samples or append samples directly following them. The
+    /// waveform is truncated to the end of the written samples.
+    ///
+    /// # Args
+    /// * `offset` - The index of the first sample to write.
+    /// * `samples` - The samples to write.
+    ///
+    /// # Returns
+    /// The number of samples of the waveform.
+    pub fn write_codes(
+        &mut self,
+        offset: usize,
+        samples: &[i16],
+    ) -> Result<usize, Error> {
+        let end = offset + samples.len();
+        if offset > self.len || end > WAVEFORM_CAPACITY {
+            return Err(Error::InvalidOffset);
+        }
+
+        self.samples[offset..end].copy_from_slice(samples);
+        self.len = end;
+        Ok(self.len)
+    }
+
+    /// Write normalized samples to the waveform.
+    ///
+    /// # Args
+    /// * `offset` - The index of the first sample to write.
+    /// * `samples` - The samples to write, normalized to the signal amplitude. Samples must be
+    ///   within [-1, 1].
+    ///
+    /// # Returns
+    /// The number of samples of the waveform.
+    pub fn write(
+        &mut self,
+        offset: usize,
+        samples: &[f32],
+    ) -> Result<usize, Error> {
+        let end = offset + samples.len();
+        if offset > self.len || end > WAVEFORM_CAPACITY {
+            return Err(Error::InvalidOffset);
+        }
+
+        if samples.iter().any(|x| !(-1.0..=1.0).contains(x)) {
+            return Err(Error::InvalidSample);
+        }
+
+        for (code, x) in self.samples[offset..end].iter_mut().zip(samples) {
+            *code = (x * i16::MAX as f32) as i16;
+        }
+        self.len = end;
+        Ok(self.len)
+    }
+
+    /// Get the interpolated waveform value at a phase.
+    ///
+    /// # Args
+    /// * `phase` - The phase in turns, full scale `1 << 32`.
+    ///
+    /// # Returns
+    /// The waveform value in full scale codes. An empty waveform is zero.
+    fn interpolate(&self, phase: u32) -> i32 {
+        if self.len == 0 {
+            return 0;
+        }
+
+        // The waveform position in samples with a 32 bit fractional part.
+        let position = phase as u64 * self.len as u64;
+        let index = (position >> 32) as usize;
+        // Note: A 15 bit interpolation fraction keeps the interpolation within `i32`.
+        let fraction = (position >> 17) as i32 & 0x7FFF;
+
+        let a = self.samples[index] as i32;
+        let b = self.samples[(index + 1) % self.len] as i32;
+        a + (((b - a) * fraction) >> 15)
+    }
+}
+
+#[derive(Debug)]
+pub struct SignalGenerator {
+    phase_accumulator: i32,
+    config: Config,
+    rng: XorShiftRng,
+    waveform: Waveform,
+    phase_increment: [i32; 2],
+    ftw: f64,
+    chirp_position: u32,
+    pink: [i32; PINK_NOISE_OCTAVES],
+    pink_sum: i32,
+    pink_counter: u32,
+    filter_state: iir::Vec5<f32>,
+    burst_remaining: u32,
+    waiting: bool,
+}
+
+impl SignalGenerator {
+    /// Construct a new signal generator with some specific config.
+    ///
+    /// # Args
+    /// * `config` - The config to use for generating signals.
+    ///
+    /// # Returns
+    /// The generator
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            phase_accumulator: 0,
+            rng: XorShiftRng::seed_from_u64(config.noise.seed),
+            waveform: Waveform::default(),
+            phase_increment: config.phase_increment,
+            ftw: config.chirp.start,
+            chirp_position: 0,
+            pink: [0; PINK_NOISE_OCTAVES],
+            pink_sum: 0,
+            pink_counter: 0,
+            filter_state: [0.; 5],
+            burst_remaining: 0,
+            waiting: config.trigger.is_burst(),
+        }
+    }
+
+    /// Get the current configuration.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Update the waveform played back by the [Signal::Arbitrary] signal.
+    ///
+    /// # Args
+    /// * `waveform` - The new waveform.
+    pub fn set_waveform(&mut self, waveform: &Waveform) {
+        self.waveform.clone_from(waveform);
+    }
+
+    /// Get the waveform played back by the [Signal::Arbitrary] signal.
+    pub fn waveform(&self) -> &Waveform {
+        &self.waveform
+    }
+
+    /// Update waveform generation settings.
+    ///
+    /// # Note
+    /// A frequency sweep restarts at its start frequency if the sweep or the frequency changes.
+    /// The noise sequence restarts if the seed changes. A running burst is aborted if the trigger
+    /// configuration changes.
+    pub fn update_waveform(&mut self, new_config: Config) {
+        if new_config.noise.seed != self.config.noise.seed {
+            self.rng = XorShiftRng::seed_from_u64(new_config.noise.seed);
+            self.pink = [0; PINK_NOISE_OCTAVES];
+            self.pink_sum = 0;
+            self.pink_counter = 0;
+            self.filter_state = [0.; 5];
+        }
+
+        if new_config.trigger != self.config.trigger {
+            self.burst_remaining = 0;
+            self.waiting = new_config.trigger.is_burst();
+        }
+
+        let restart = new_config.chirp != self.config.chirp
+            || new_config.phase_increment != self.config.phase_increment;
+
+        self.config = new_config;
+        if restart {
+            self.restart_chirp();
+        }
+    }
+
+    /// Trigger the signal.
+    ///
+    /// # Note
+    /// The phase and any frequency sweep restart. In burst mode, the configured number of signal
+    /// periods is generated.
+    pub fn trigger(&mut self) {
+        self.phase_accumulator = 0;
+        self.restart_chirp();
+        self.burst_remaining = self.config.trigger.burst;
+        self.waiting = false;
+    }
+
+    // Restart the frequency sweep at its start frequency.
+    fn restart_chirp(&mut self) {
+        self.phase_increment = self.config.phase_increment;
+        self.ftw = self.config.chirp.start;
+        self.chirp_position = 0;
+    }
+
+    /// Get the current frequency of the signal.
+    ///
+    /// # Returns
+    /// The frequency tuning word of the next sample, where `1 << 32` corresponds to the sample
+    /// rate. For asymmetric signals, this is the frequency of the complete oscillation.
+    pub fn frequency(&self) -> u32 {
+        let [a, b] = self.phase_increment.map(|inc| inc as u32 as u64);
+        // The harmonic mean of the phase increments of both half oscillations.
+        (2 * a * b).checked_div(a + b).unwrap_or(0) as u32
+    }
+
+    // Advance the frequency sweep by one sample.
+    #[inline]
+    fn chirp(&mut self) {
+        const NYQUIST: f64 = (1u32 << 31) as _;
+        let chirp = &self.config.chirp;
+
+        self.chirp_position += 1;
+        if self.chirp_position < chirp.length {
+            if chirp.mode == ChirpMode::Linear {
+                self.ftw += chirp.step;
+            } else {
+                self.ftw *= chirp.step;
+            }
+        } else if chirp.repeat {
+            self.chirp_position = 0;
+            self.ftw = chirp.start;
+        } else {
+            // Remain at the stop frequency.
+            self.chirp_position = chirp.length;
+            return;
+        }
+
+        // Note: Saturating conversion. Zero frequency with zero symmetry results in NaN which
+        // `min()` maps to Nyquist.
+        self.phase_increment = chirp
+            .scale
+            .map(|scale| (self.ftw * scale).min(NYQUIST) as i32);
+    }
+
+    // Get the next white noise sample in full scale codes.
+    #[inline]
+    fn noise(&mut self) -> i32 {
+        match self.config.noise.distribution {
+            Distribution::Uniform => self.rng.next_u32() as i32 >> 16,
+            Distribution::Gaussian => {
+                let x = self.rng.next_u64();
+                (0..4).map(|i| (x >> (16 * i)) as i16 as i32).sum::<i32>() >> 2
+            }
+        }
+    }
+
+    // Get the next pink noise sample in full scale codes.
+    //
+    // Note: This is the Voss-McCartney algorithm. Each octave holds a white noise sample that is
+    // renewed at half the rate of the next higher octave. The sum of all octaves and a white
+    // noise sample has a 1/f spectrum.
+    #[inline]
+    fn pink_noise(&mut self) -> i32 {
+        self.pink_counter = self.pink_counter.wrapping_add(1);
+        let octave = self.pink_counter.trailing_zeros() as usize;
+        if octave < PINK_NOISE_OCTAVES {
+            let x = self.noise();
+            self.pink_sum += x - self.pink[octave];
+            self.pink[octave] = x;
+        }
+
+        // Note: The mean of the 15 octaves and the white noise sample is within full scale.
+        (self.pink_sum + self.noise()) >> 4
+    }
+
+    /// Clear the phase accumulator.
+    pub fn clear_phase_accumulator(&mut self) {
+        self.phase_accumulator = 0;
+    }
+}
+
+impl core::iter::Iterator for SignalGenerator {
+    type Item = i16;
+
+    /// Get the next value in the generator sequence.
+    fn next(&mut self) -> Option<i16> {
+        if self.waiting {
+            return Some(0);
+        }
+
+        let phase = self
+            .phase_accumulator
+            .wrapping_add(self.config.phase_offset);
+        let sign = phase.is_negative();
+        let accumulator = self
+            .phase_accumulator
+            .wrapping_add(self.phase_increment[sign as usize]);
+
+        // A signal period ends when the phase accumulator wraps around to positive values.
+        if self.burst_remaining > 0
+            && self.phase_accumulator.is_negative()
+            && !accumulator.is_negative()
+        {
+            self.burst_remaining -= 1;
+            self.waiting = self.burst_remaining == 0;
+        }
+        self.phase_accumulator = accumulator;
+
+        if self.config.chirp.mode != ChirpMode::Off {
+            self.chirp();
+        }
+
+        let scale = match self.config.signal {
+            Signal::Cosine => idsp::cossin(phase).0 >> 16,
+            Signal::Square => {
+                if sign {
+                    i16::MIN as i32
+                } else {
+                    -(i16::MIN as i32)
+                }
+            }
+            Signal::Triangle => i16::MIN as i32 + (phase >> 15).abs(),
+            Signal::WhiteNoise => self.noise(),
+            Signal::PinkNoise => self.pink_noise(),
+            Signal::BandLimitedNoise => {
+                let x = self.noise() as f32;
+                self.config.noise.filter.update(
+                    &mut self.filter_state,
+                    x,
+                    false,
+                ) as i32
+            }
+            Signal::Arbitrary => self.waveform.interpolate(phase as u32),
+        };
+
+        // Calculate the final output result as an i16.
+        Some(((self.config.amplitude as i32 * scale) >> 15) as _)
+    }
+}
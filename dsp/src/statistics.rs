@@ -0,0 +1,98 @@
+//! Signal statistics
+//!
+//! # Design
+//! The statistics of a signal are accumulated sample by sample over a measurement period with a
+//! few integer operations per sample so that they can be tracked in the processing routines. The
+//! derived quantities (mean, RMS) are only computed when the statistics are evaluated, e.g. when
+//! telemetry is reported.
+//!
+//! Samples are signed Stabilizer-defined codes (i16 full range). A sample is at full scale if it
+//! is at the minimum or maximum code, e.g. due to ADC overrange or DAC clipping.
+
+/// The statistics of a signal over a measurement period.
+#[derive(Copy, Clone, Debug)]
+pub struct Statistics {
+    min: i16,
+    max: i16,
+    sum: i64,
+    sum_sq: u64,
+    count: u64,
+    full_scale: u32,
+}
+
+impl Default for Statistics {
+    fn default() -> Self {
+        Self {
+            min: i16::MAX,
+            max: i16::MIN,
+            sum: 0,
+            sum_sq: 0,
+            count: 0,
+            full_scale: 0,
+        }
+    }
+}
+
+impl Statistics {
+    /// Accumulate a sample.
+    ///
+    /// # Note
+    /// The sum of squares saturates after about 1.7e10 full scale samples. Measurement periods
+    /// should be shorter than that.
+    ///
+    /// # Args
+    /// * `x` - The sample code.
+    #[inline]
+    pub fn add(&mut self, x: i16) {
+        self.min = self.min.min(x);
+        self.max = self.max.max(x);
+        self.sum += x as i64;
+        self.sum_sq = self.sum_sq.saturating_add((x as i32 * x as i32) as u64);
+        self.count += 1;
+        if x == i16::MIN || x == i16::MAX {
+            self.full_scale = self.full_scale.saturating_add(1);
+        }
+    }
+
+    /// Accumulate a batch of samples.
+    ///
+    /// # Args
+    /// * `samples` - The sample codes.
+    #[inline]
+    pub fn extend(&mut self, samples: impl IntoIterator<Item = i16>) {
+        for x in samples {
+            self.add(x);
+        }
+    }
+
+    /// Get the number of accumulated samples.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Get the number of accumulated samples at full scale.
+    pub fn full_scale(&self) -> u32 {
+        self.full_scale
+    }
+
+    /// Get the minimum sample code, if any sample was accumulated.
+    pub fn min(&self) -> Option<i16> {
+        (self.count > 0).then_some(self.min)
+    }
+
+    /// Get the maximum sample code, if any sample was accumulated.
+    pub fn max(&self) -> Option<i16> {
+        (self.count > 0).then_some(self.max)
+    }
+
+    /// Get the mean sample code, if any sample was accumulated.
+    pub fn mean(&self) -> Option<f32> {
+        (self.count > 0).then(|| (self.sum as f64 / self.count as f64) as f32)
+    }
+
+    /// Get the root mean square of the sample codes, if any sample was accumulated.
+    pub fn rms(&self) -> Option<f32> {
+        (self.count > 0)
+            .then(|| libm::sqrt(self.sum_sq as f64 / self.count as f64) as f32)
+    }
+}
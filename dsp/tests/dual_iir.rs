@@ -0,0 +1,414 @@
+use idsp::iir;
+use stabilizer_dsp::{
+    code::{AdcCode, DacCode},
+    dual_iir::{Config, DualIir, Saturation},
+    lock::{self, State},
+    signal_generator::{self, Signal, Trigger, TriggerSource},
+    stream_adc_dac, stream_iir_state, stream_signal, Input, Output, Process,
+};
+
+use core::mem::MaybeUninit;
+
+const BATCH_SIZE: usize = 8;
+const SCALE: f32 = i16::MAX as _;
+
+fn processor(gain: f32, allow_hold: bool) -> DualIir<2> {
+    DualIir::new(
+        Config {
+            iir_ch: [[iir::IIR::new(gain, -SCALE, SCALE); 2]; 2],
+            cascade_len: [1; 2],
+            input_matrix: [[1., 0.], [0., 1.]],
+            output_matrix: [[1., 0.], [0., 1.]],
+            allow_hold,
+            force_hold: false,
+            lock: [lock::Config::default(); 2],
+            saturation_alarm: 0,
+        },
+        [signal_generator::Config::default(); 2],
+    )
+}
+
+// A square wave of amplitude 1000 and a period of four samples.
+fn square_wave() -> signal_generator::Config {
+    signal_generator::Config {
+        signal: Signal::Square,
+        amplitude: 1000,
+        phase_increment: [1 << 30; 2],
+        ..Default::default()
+    }
+}
+
+// Process a batch of signed input codes and return the signed output codes.
+fn process(
+    processor: &mut impl Process,
+    adcs: [[i16; BATCH_SIZE]; 2],
+    digital_inputs: [bool; 2],
+) -> [[i16; BATCH_SIZE]; 2] {
+    let adcs = adcs.map(|adc| adc.map(|x| AdcCode::from(x).0));
+    let mut dacs = [[0u16; BATCH_SIZE]; 2];
+    let [dac0, dac1] = &mut dacs;
+
+    processor.process(
+        &Input {
+            adcs: [&adcs[0], &adcs[1]],
+            digital_inputs,
+            timestamp: None,
+            triggers: [None; 2],
+        },
+        &mut Output { dacs: [dac0, dac1] },
+    );
+
+    dacs.map(|dac| dac.map(|code| i16::from(DacCode(code))))
+}
+
+#[test]
+fn unity_gain() {
+    let mut processor = processor(1., false);
+    let ramp = [-30000, -1000, -1, 0, 1, 1000, 20000, 32767];
+    let inverted = ramp.map(|x: i16| x.saturating_neg());
+
+    assert_eq!(
+        process(&mut processor, [ramp, inverted], [false; 2]),
+        [ramp, inverted]
+    );
+}
+
+#[test]
+fn gain_saturates() {
+    let mut processor = processor(2., false);
+    let input = [-20000, -1000, 0, 1000, 10000, 16383, 16384, 20000];
+
+    assert_eq!(
+        process(&mut processor, [input; 2], [false; 2]),
+        [[-32767, -2000, 0, 2000, 20000, 32766, 32767, 32767]; 2]
+    );
+}
+
+#[test]
+fn saturation() {
+    let mut processor = processor(2., false);
+    let input = [-20000, -1000, 0, 1000, 10000, 16383, 16384, 20000];
+
+    process(&mut processor, [input, [0; BATCH_SIZE]], [false; 2]);
+    assert_eq!(
+        processor.saturation(),
+        [Saturation { count: 3, rail: 2 }, Saturation::default()]
+    );
+
+    process(&mut processor, [[0; BATCH_SIZE]; 2], [false; 2]);
+    assert_eq!(processor.saturation()[0], Saturation { count: 3, rail: 0 });
+}
+
+#[test]
+fn saturation_signal_generator() {
+    let mut processor = processor(1., false);
+    processor.signal_generator[1].update_waveform(square_wave());
+
+    process(&mut processor, [[32000; BATCH_SIZE]; 2], [false; 2]);
+    assert_eq!(processor.saturation()[1].count, 4);
+}
+
+#[test]
+fn saturation_alarm() {
+    let mut processor = processor(2., false);
+    let mut config = *processor.config();
+    config.saturation_alarm = 12;
+    processor.configure(config);
+
+    let alarms: Vec<[bool; 2]> = (0..4)
+        .map(|_| {
+            process(&mut processor, [[20000; BATCH_SIZE]; 2], [false; 2]);
+            processor.saturation_alarm()
+        })
+        .collect();
+    assert_eq!(alarms, [[false; 2], [true; 2], [false; 2], [false; 2]]);
+    assert_eq!(processor.saturation()[0].rail, 32);
+
+    // The alarm is raised again after the saturation ended.
+    process(&mut processor, [[0; BATCH_SIZE]; 2], [false; 2]);
+    process(&mut processor, [[20000; BATCH_SIZE]; 2], [false; 2]);
+    process(&mut processor, [[20000; BATCH_SIZE]; 2], [false; 2]);
+    assert_eq!(processor.saturation_alarm(), [true; 2]);
+}
+
+#[test]
+fn hold() {
+    let mut held = processor(1., true);
+    let mut free = processor(1., false);
+
+    for processor in [&mut held, &mut free] {
+        assert_eq!(
+            process(processor, [[100; BATCH_SIZE]; 2], [false; 2]),
+            [[100; BATCH_SIZE]; 2]
+        );
+    }
+
+    // DI1 holds the output only if allowed.
+    assert_eq!(
+        process(&mut held, [[500; BATCH_SIZE]; 2], [false, true]),
+        [[100; BATCH_SIZE]; 2]
+    );
+    assert_eq!(
+        process(&mut free, [[500; BATCH_SIZE]; 2], [false, true]),
+        [[500; BATCH_SIZE]; 2]
+    );
+
+    // Releasing the hold resumes filtering.
+    assert_eq!(
+        process(&mut held, [[500; BATCH_SIZE]; 2], [false; 2]),
+        [[500; BATCH_SIZE]; 2]
+    );
+}
+
+#[test]
+fn cascade_length() {
+    let mut processor = processor(2., false);
+    let input = [[100; BATCH_SIZE]; 2];
+
+    for (cascade_len, expected) in [
+        ([1, 2], [200, 400]),
+        ([0, 2], [100, 400]),
+        ([2, 2], [400, 400]),
+        // The length is limited to the cascade capacity.
+        ([9, 1], [400, 200]),
+    ] {
+        let mut config = *processor.config();
+        config.cascade_len = cascade_len;
+        processor.configure(config);
+
+        assert_eq!(
+            process(&mut processor, input, [false; 2]),
+            expected.map(|y| [y; BATCH_SIZE])
+        );
+    }
+}
+
+#[test]
+fn cascade_length_resets_state() {
+    let mut processor = processor(1., true);
+    let input = [[100; BATCH_SIZE]; 2];
+    process(&mut processor, input, [false; 2]);
+
+    // Changing the cascade length of channel 1 clears its held output only.
+    let mut config = *processor.config();
+    config.cascade_len = [1, 2];
+    processor.configure(config);
+
+    assert_eq!(
+        process(&mut processor, [[500; BATCH_SIZE]; 2], [false, true]),
+        [[100; BATCH_SIZE], [0; BATCH_SIZE]]
+    );
+}
+
+#[test]
+fn input_matrix() {
+    let mut processor = processor(1., false);
+    let mut config = *processor.config();
+    // Sum and difference of the inputs.
+    config.input_matrix = [[1., 1.], [1., -1.]];
+    processor.configure(config);
+
+    assert_eq!(
+        process(
+            &mut processor,
+            [[300; BATCH_SIZE], [100; BATCH_SIZE]],
+            [false; 2]
+        ),
+        [[400; BATCH_SIZE], [200; BATCH_SIZE]]
+    );
+}
+
+#[test]
+fn output_matrix() {
+    let mut processor = processor(1., false);
+    let mut config = *processor.config();
+    // IIR0 drives both outputs, the second one inverted with gain.
+    config.output_matrix = [[1., 0.], [-2., 0.]];
+    processor.configure(config);
+
+    assert_eq!(
+        process(
+            &mut processor,
+            [[300; BATCH_SIZE], [100; BATCH_SIZE]],
+            [false; 2]
+        ),
+        [[300; BATCH_SIZE], [-600; BATCH_SIZE]]
+    );
+
+    // The output saturates at the DAC range.
+    assert_eq!(
+        process(
+            &mut processor,
+            [[20000; BATCH_SIZE], [100; BATCH_SIZE]],
+            [false; 2]
+        ),
+        [[20000; BATCH_SIZE], [i16::MIN; BATCH_SIZE]]
+    );
+}
+
+#[test]
+fn signal_generator() {
+    let mut processor = processor(1., false);
+    processor.signal_generator[1].update_waveform(square_wave());
+
+    assert_eq!(
+        process(&mut processor, [[10; BATCH_SIZE]; 2], [false; 2]),
+        [
+            [10; BATCH_SIZE],
+            [1010, 1010, -990, -990, 1010, 1010, -990, -990]
+        ]
+    );
+}
+
+#[test]
+fn stream() {
+    let adcs = [[1u16; BATCH_SIZE], [2; BATCH_SIZE]];
+    let mut dacs = [[3u16; BATCH_SIZE], [0x8004; BATCH_SIZE]];
+    let [dac0, dac1] = &mut dacs;
+
+    let input = Input {
+        adcs: [&adcs[0], &adcs[1]],
+        digital_inputs: [false; 2],
+        timestamp: None,
+        triggers: [None; 2],
+    };
+    let output = Output { dacs: [dac0, dac1] };
+
+    let mut buf = [MaybeUninit::new(0xAAu8); BATCH_SIZE * 2 * 4];
+    stream_adc_dac(&input, &output, &mut buf);
+
+    // Note(unsafe): All bytes of the buffer are initialized.
+    let buf = buf.map(|byte| unsafe { byte.assume_init() });
+    for (i, chunk) in buf.chunks_exact(BATCH_SIZE * 2).enumerate() {
+        let expected: &[u8] = match i {
+            0 => &[1, 0],
+            1 => &[2, 0],
+            2 => &[3, 0],
+            _ => &[4, 0x80],
+        };
+        for code in chunk.chunks_exact(2) {
+            assert_eq!(code, expected);
+        }
+    }
+}
+
+#[test]
+fn lock_engages_from_sweep() {
+    let mut processor = processor(1., false);
+    let mut config = *processor.config();
+    config.lock[0] = lock::Config {
+        enable: true,
+        sweep: signal_generator::Config {
+            signal: Signal::Triangle,
+            amplitude: 1000,
+            phase_increment: [1 << 29; 2],
+            phase_offset: 0,
+            chirp: Default::default(),
+            noise: Default::default(),
+            trigger: Default::default(),
+        },
+        threshold: 0.,
+        error_limit: 100.,
+        acquire_batches: 1,
+        fault_batches: 0,
+    };
+    processor.configure(config);
+    assert_eq!(processor.lock_status()[0].state, State::Sweeping);
+
+    // Without a threshold crossing, the sweep replaces the cascade output.
+    let y = process(&mut processor, [[-10; BATCH_SIZE]; 2], [false; 2]);
+    assert_ne!(y[0], [-10; BATCH_SIZE]);
+    assert_eq!(y[1], [-10; BATCH_SIZE]);
+
+    // The crossing engages the cascade.
+    process(&mut processor, [[10; BATCH_SIZE]; 2], [false; 2]);
+    assert_eq!(processor.lock_status()[0].state, State::Acquiring);
+
+    process(&mut processor, [[10; BATCH_SIZE]; 2], [false; 2]);
+    assert_eq!(
+        process(&mut processor, [[10; BATCH_SIZE]; 2], [false; 2])[0],
+        [10; BATCH_SIZE]
+    );
+    assert_eq!(processor.lock_status()[0].state, State::Locked);
+
+    // A large error makes it fall back to sweeping.
+    process(&mut processor, [[1000; BATCH_SIZE]; 2], [false; 2]);
+    let status = processor.lock_status()[0];
+    assert_eq!(status.state, State::Sweeping);
+    assert_eq!(status.relocks, 1);
+}
+
+#[test]
+fn signal_generator_trigger() {
+    let mut processor = processor(1., false);
+    processor.signal_generator[1].update_waveform(signal_generator::Config {
+        trigger: Trigger {
+            source: TriggerSource::Di1,
+            burst: 1,
+        },
+        ..square_wave()
+    });
+
+    // The burst waits for a trigger.
+    assert_eq!(
+        process(&mut processor, [[10; BATCH_SIZE]; 2], [false; 2])[1],
+        [10; BATCH_SIZE]
+    );
+
+    let adcs = [[AdcCode::from(10i16).0; BATCH_SIZE]; 2];
+    let mut dacs = [[0u16; BATCH_SIZE]; 2];
+    let [dac0, dac1] = &mut dacs;
+    processor.process(
+        &Input {
+            adcs: [&adcs[0], &adcs[1]],
+            digital_inputs: [false, true],
+            timestamp: None,
+            // A DI0 edge does not trigger the generator.
+            triggers: [Some(0), Some(2)],
+        },
+        &mut Output { dacs: [dac0, dac1] },
+    );
+
+    assert_eq!(
+        dacs[1].map(|code| i16::from(DacCode(code))),
+        [10, 10, 1010, 1010, -990, -990, 10, 10]
+    );
+}
+
+#[test]
+fn stream_monitor() {
+    let mut processor = processor(2., false);
+    processor.signal_generator[1].update_waveform(square_wave());
+    process(
+        &mut processor,
+        [[10; BATCH_SIZE], [-10; BATCH_SIZE]],
+        [false; 2],
+    );
+
+    let mut buf = [MaybeUninit::new(0xAAu8); BATCH_SIZE * 4 * 4];
+    stream_iir_state(processor.monitor(), &mut buf);
+    // Note(unsafe): All bytes of the buffer are initialized.
+    let buf = buf.map(|byte| unsafe { byte.assume_init() });
+    let values: Vec<f32> = buf
+        .chunks_exact(4)
+        .map(|x| f32::from_le_bytes(x.try_into().unwrap()))
+        .collect();
+    for (i, batch) in values.chunks_exact(BATCH_SIZE).enumerate() {
+        let expected = [10., -10., 20., -20.][i];
+        assert!(batch.iter().all(|&x| x == expected));
+    }
+
+    let mut buf = [MaybeUninit::new(0xAAu8); BATCH_SIZE * 2 * 2];
+    stream_signal(processor.monitor(), &mut buf);
+    // Note(unsafe): All bytes of the buffer are initialized.
+    let buf = buf.map(|byte| unsafe { byte.assume_init() });
+    let codes: Vec<i16> = buf
+        .chunks_exact(2)
+        .map(|x| i16::from_le_bytes([x[0], x[1]]))
+        .collect();
+    assert_eq!(codes[..BATCH_SIZE], [0; BATCH_SIZE]);
+    assert_eq!(
+        codes[BATCH_SIZE..],
+        [1000, 1000, -1000, -1000, 1000, 1000, -1000, -1000]
+    );
+}
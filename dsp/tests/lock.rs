@@ -0,0 +1,123 @@
+use stabilizer_dsp::{
+    lock::{Config, Lock, State},
+    signal_generator::{self, Signal},
+};
+
+fn lock(acquire_batches: u32, fault_batches: u32) -> Lock {
+    Lock::new(Config {
+        enable: true,
+        sweep: signal_generator::Config {
+            signal: Signal::Triangle,
+            amplitude: 1000,
+            phase_increment: [1 << 28; 2],
+            phase_offset: 0,
+            chirp: Default::default(),
+            noise: Default::default(),
+            trigger: Default::default(),
+        },
+        threshold: 0.,
+        error_limit: 100.,
+        acquire_batches,
+        fault_batches,
+    })
+}
+
+#[test]
+fn disabled() {
+    let lock = Lock::new(Config::default());
+    assert_eq!(lock.status().state, State::Unlocked);
+    assert!(lock.is_engaged());
+}
+
+#[test]
+fn sweep() {
+    let mut lock = lock(0, 0);
+    assert_eq!(lock.status().state, State::Sweeping);
+
+    let sweep: Vec<i16> = (0..16).map(|_| lock.sweep(-1.)).collect();
+    assert!(sweep.iter().any(|&y| y > 500));
+    assert!(sweep.iter().any(|&y| y < -500));
+
+    lock.update();
+    assert_eq!(lock.status().state, State::Sweeping);
+    assert!(!lock.is_engaged());
+}
+
+#[test]
+fn threshold_crossing() {
+    let mut lock = lock(0, 0);
+
+    // The first sample does not count as a crossing.
+    lock.sweep(1.);
+    lock.sweep(1.);
+    assert_eq!(lock.status().state, State::Sweeping);
+
+    lock.sweep(-1.);
+    assert_eq!(lock.status().state, State::Acquiring);
+    assert!(lock.is_engaged());
+}
+
+#[test]
+fn acquire() {
+    let mut lock = lock(2, 0);
+    lock.sweep(-1.);
+    lock.sweep(1.);
+
+    for _ in 0..2 {
+        lock.supervise(10., false);
+        lock.update();
+        assert_eq!(lock.status().state, State::Acquiring);
+    }
+
+    lock.update();
+    assert_eq!(lock.status().state, State::Locked);
+    assert_eq!(lock.status().relocks, 0);
+}
+
+#[test]
+fn fault() {
+    let mut lock = lock(0, 1);
+    lock.sweep(-1.);
+    lock.sweep(1.);
+    lock.update();
+    assert_eq!(lock.status().state, State::Locked);
+
+    // A single faulty batch is tolerated.
+    lock.supervise(0., true);
+    lock.update();
+    assert_eq!(lock.status().state, State::Locked);
+    lock.update();
+
+    // Consecutive faulty batches restart the sweep.
+    for _ in 0..2 {
+        lock.supervise(1000., false);
+        lock.update();
+    }
+    assert_eq!(lock.status().state, State::Sweeping);
+    assert_eq!(lock.status().relocks, 1);
+}
+
+#[test]
+fn fault_while_acquiring() {
+    let mut lock = lock(10, 0);
+    lock.sweep(-1.);
+    lock.sweep(1.);
+
+    lock.supervise(0., true);
+    lock.update();
+    assert_eq!(lock.status().state, State::Sweeping);
+    assert_eq!(lock.status().relocks, 0);
+}
+
+#[test]
+fn disable() {
+    let mut lock = lock(0, 0);
+    lock.configure(Config::default());
+    assert_eq!(lock.status().state, State::Unlocked);
+    assert!(lock.is_engaged());
+
+    // Faults do not affect the unlocked state.
+    lock.supervise(1000., true);
+    lock.update();
+    assert_eq!(lock.status().state, State::Unlocked);
+}
@@ -0,0 +1,142 @@
+use stabilizer_dsp::{
+    code::{AdcCode, DacCode},
+    lockin::{Conf, Config, Lockin, LockinMode},
+    Input, Output, Process,
+};
+
+use core::f32::consts::PI;
+
+const BATCH_SIZE_LOG2: u32 = 3;
+const BATCH_SIZE: usize = 1 << BATCH_SIZE_LOG2;
+const SAMPLE_TICKS_LOG2: u32 = 7;
+
+fn lockin(lockin_mode: LockinMode, output_conf: [Conf; 2]) -> Lockin {
+    Lockin::new(
+        Config {
+            lockin_mode,
+            pll_tc: [21, 21],
+            lockin_tc: 6,
+            lockin_harmonic: -1,
+            lockin_phase: 0,
+            output_conf,
+        },
+        SAMPLE_TICKS_LOG2,
+        BATCH_SIZE_LOG2,
+    )
+}
+
+// Process a batch of signed ADC0 codes and return the signed output codes.
+fn process(
+    processor: &mut impl Process,
+    adc0: [i16; BATCH_SIZE],
+    timestamp: Option<u32>,
+) -> [[i16; BATCH_SIZE]; 2] {
+    let adcs = [adc0.map(|x| AdcCode::from(x).0), [0; BATCH_SIZE]];
+    let mut dacs = [[0u16; BATCH_SIZE]; 2];
+    let [dac0, dac1] = &mut dacs;
+
+    processor.process(
+        &Input {
+            adcs: [&adcs[0], &adcs[1]],
+            digital_inputs: [false; 2],
+            timestamp,
+            triggers: [None; 2],
+        },
+        &mut Output { dacs: [dac0, dac1] },
+    );
+
+    dacs.map(|dac| dac.map(|code| i16::from(DacCode(code))))
+}
+
+// A batch of a sinusoid at the internal reference frequency (one period per batch).
+fn reference(amplitude: f32, phase: f32) -> [i16; BATCH_SIZE] {
+    let mut batch = [0; BATCH_SIZE];
+    for (i, sample) in batch.iter_mut().enumerate() {
+        let t = 2. * PI * i as f32 / BATCH_SIZE as f32;
+        *sample = (amplitude * (t + phase).cos()) as i16;
+    }
+    batch
+}
+
+#[test]
+fn internal_demodulation() {
+    for phase in [0., 1., -2.] {
+        let mut processor =
+            lockin(LockinMode::Internal, [Conf::InPhase, Conf::Quadrature]);
+
+        let mut output = [[0; BATCH_SIZE]; 2];
+        for _ in 0..1000 {
+            output = process(&mut processor, reference(10000., phase), None);
+        }
+
+        // The output is constant within a batch.
+        for dac in output.iter() {
+            assert!(dac.iter().all(|&y| y == dac[0]));
+        }
+
+        // The demodulated magnitude matches the input amplitude.
+        let (i, q) = (output[0][0] as f32, output[1][0] as f32);
+        let magnitude = (i * i + q * q).sqrt();
+        assert!((magnitude - 10000.).abs() < 200., "{}", magnitude);
+    }
+}
+
+#[test]
+fn internal_zero_input() {
+    let mut processor =
+        lockin(LockinMode::Internal, [Conf::InPhase, Conf::Quadrature]);
+
+    for _ in 0..100 {
+        for dac in process(&mut processor, [0; BATCH_SIZE], None).iter() {
+            assert!(dac.iter().all(|y| y.abs() <= 1));
+        }
+    }
+}
+
+#[test]
+fn modulation() {
+    let mut processor =
+        lockin(LockinMode::Internal, [Conf::Modulation, Conf::InPhase]);
+    let output = process(&mut processor, [0; BATCH_SIZE], None);
+
+    // The modulation output is a 1 V cosine with one period per batch.
+    let amplitude = i16::from(DacCode::try_from(1.0).unwrap()) as f32;
+    for (y, expected) in output[0].iter().zip(reference(amplitude, 0.)) {
+        assert!(
+            (*y as i32 - expected as i32).abs() <= 2,
+            "{} {}",
+            y,
+            expected
+        );
+    }
+}
+
+#[test]
+fn external_reference() {
+    let mut processor = lockin(
+        LockinMode::External,
+        [Conf::ReferenceFrequency, Conf::ReferenceFrequency],
+    );
+
+    // A reference edge on DI0 every other batch.
+    let batch_ticks = 1u32 << (SAMPLE_TICKS_LOG2 + BATCH_SIZE_LOG2);
+    let mut output = [[0; BATCH_SIZE]; 2];
+    // Settle the PLL faster than the default time constants do.
+    processor.config.pll_tc = [14, 14];
+    for batch in 0..10000u32 {
+        let timestamp = if batch % 2 == 0 {
+            Some((batch * batch_ticks).wrapping_add(17))
+        } else {
+            None
+        };
+        output = process(&mut processor, [0; BATCH_SIZE], timestamp);
+    }
+
+    // The reference frequency is half a turn per batch, i.e. 1/16 turn per sample.
+    let expected = (1i32 << (32 - BATCH_SIZE_LOG2 - 1)) >> 16;
+    for dac in output.iter() {
+        for y in dac.iter() {
+            assert!((*y as i32 - expected).abs() <= 1, "{}", y);
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use stabilizer_dsp::network_analyzer::{
+    BasicConfig, Error, Frequencies, NetworkAnalyzer, Point,
+};
+
+const SAMPLE_PERIOD: f32 = 1e-3;
+
+fn analyzer(frequencies: &[f32], repeat: bool) -> NetworkAnalyzer {
+    let config = BasicConfig {
+        enable: true,
+        channel: 1,
+        amplitude: 1.,
+        frequencies: Frequencies::try_from(frequencies).unwrap(),
+        settle_time: 0.01,
+        integration_time: 0.1,
+        repeat,
+    };
+    NetworkAnalyzer::new(config.try_into_config(SAMPLE_PERIOD, 10.).unwrap())
+}
+
+// Run the analyzer against a plant and collect the measured points.
+//
+// The plant gets the excitation history (latest first) and returns the ADC codes.
+fn measure(
+    analyzer: &mut NetworkAnalyzer,
+    samples: usize,
+    plant: impl Fn(&[i16]) -> [i16; 2],
+) -> Vec<Point> {
+    let mut history = vec![0; 4];
+    let mut points = vec![];
+    for _ in 0..samples {
+        let x = plant(&history);
+        let excitation = analyzer.update(x);
+        history.rotate_right(1);
+        history[0] = excitation;
+        points.extend(analyzer.take_result());
+    }
+    points
+}
+
+#[test]
+fn response() {
+    let mut analyzer = analyzer(&[50., 125.], false);
+    assert_eq!(analyzer.channel(), Some(1));
+
+    // ADC0 measures half the excitation delayed by one sample, ADC1 measures nothing.
+    let points = measure(&mut analyzer, 1000, |history| [history[0] / 2, 0]);
+
+    assert_eq!(points.len(), 2);
+    assert_eq!(analyzer.channel(), None);
+
+    for (index, (point, frequency)) in
+        points.iter().zip([50., 125.]).enumerate()
+    {
+        assert_eq!(point.index, index);
+
+        let [re, im] = point.response[0];
+        let phase = im.atan2(re);
+        let expected = -2. * core::f32::consts::PI * frequency * SAMPLE_PERIOD;
+        assert!(((re * re + im * im).sqrt() - 0.5).abs() < 1e-3);
+        assert!((phase - expected).abs() < 1e-3);
+
+        let [re, im] = point.response[1];
+        assert!(re.abs() < 1e-6 && im.abs() < 1e-6);
+    }
+}
+
+#[test]
+fn repeat() {
+    let mut analyzer = analyzer(&[100.], true);
+    let points = measure(&mut analyzer, 1000, |history| [history[0], 0]);
+
+    assert_eq!(points.len(), 9);
+    assert!(points.iter().all(|point| point.index == 0));
+    assert_eq!(analyzer.channel(), Some(1));
+}
+
+#[test]
+fn disabled() {
+    let mut analyzer = NetworkAnalyzer::default();
+    assert_eq!(analyzer.channel(), None);
+    assert_eq!(analyzer.update([1000, 1000]), 0);
+    assert_eq!(analyzer.take_result(), None);
+}
+
+#[test]
+fn invalid() {
+    let config = BasicConfig {
+        frequencies: Frequencies::try_from(&[600.][..]).unwrap(),
+        ..Default::default()
+    };
+    assert_eq!(
+        config.try_into_config(SAMPLE_PERIOD, 10.).unwrap_err(),
+        Error::InvalidFrequency
+    );
+
+    assert_eq!(
+        Frequencies::try_from(&[1.; 33][..]).unwrap_err(),
+        Error::TooManyPoints
+    );
+}
@@ -0,0 +1,309 @@
+use stabilizer_dsp::signal_generator::{
+    BasicConfig, ChirpMode, Config, Distribution, Error, Signal,
+    SignalGenerator, Trigger, TriggerSource, Waveform, WAVEFORM_CAPACITY,
+};
+
+const SAMPLE_PERIOD: f32 = 1e-3;
+
+fn chirp(chirp: ChirpMode, repeat: bool) -> SignalGenerator {
+    let config = BasicConfig {
+        frequency: 10.,
+        stop_frequency: 110.,
+        duration: 0.1,
+        chirp,
+        repeat,
+        amplitude: 1.,
+        ..Default::default()
+    };
+    SignalGenerator::new(config.try_into_config(SAMPLE_PERIOD, 10.).unwrap())
+}
+
+// Get the current frequency of a generator in Hertz.
+fn frequency(generator: &SignalGenerator) -> f32 {
+    generator.frequency() as f32 / (1u64 << 32) as f32 / SAMPLE_PERIOD
+}
+
+fn noise(signal: Signal, distribution: Distribution, seed: u64) -> Vec<f32> {
+    let config = BasicConfig {
+        signal,
+        frequency: 10.,
+        amplitude: 5.,
+        distribution,
+        seed,
+        ..Default::default()
+    };
+    SignalGenerator::new(config.try_into_config(SAMPLE_PERIOD, 10.).unwrap())
+        .take(1 << 16)
+        .map(f32::from)
+        .collect()
+}
+
+// Get the RMS value and the normalized lag-one autocorrelation of a signal.
+fn statistics(x: &[f32]) -> (f32, f32) {
+    let power = x.iter().map(|x| x * x).sum::<f32>();
+    let correlation = x.windows(2).map(|x| x[0] * x[1]).sum::<f32>();
+    ((power / x.len() as f32).sqrt(), correlation / power)
+}
+
+fn arbitrary(phase_increment: i32) -> SignalGenerator {
+    let mut waveform = Waveform::default();
+    waveform.write_codes(0, &[0, 16384, 0, -16384]).unwrap();
+
+    let mut generator = SignalGenerator::new(Config {
+        signal: Signal::Arbitrary,
+        amplitude: 16384,
+        phase_increment: [phase_increment; 2],
+        phase_offset: 0,
+        chirp: Default::default(),
+        noise: Default::default(),
+        trigger: Default::default(),
+    });
+    generator.set_waveform(&waveform);
+    generator
+}
+
+#[test]
+fn arbitrary_playback() {
+    let samples: Vec<i16> = arbitrary(1 << 30).take(8).collect();
+    assert_eq!(samples, [0, 8192, 0, -8192, 0, 8192, 0, -8192]);
+}
+
+#[test]
+fn arbitrary_interpolation() {
+    let samples: Vec<i16> = arbitrary(1 << 29).take(8).collect();
+    assert_eq!(samples, [0, 4096, 8192, 4096, 0, -4096, -8192, -4096]);
+}
+
+#[test]
+fn arbitrary_empty() {
+    let mut generator = arbitrary(1 << 30);
+    generator.set_waveform(&Waveform::default());
+    assert!(generator.take(4).all(|y| y == 0));
+}
+
+#[test]
+fn waveform_write() {
+    let mut waveform = Waveform::default();
+    assert_eq!(waveform.write(0, &[0., 0.5, 1.]), Ok(3));
+    assert_eq!(waveform.write(3, &[-1.]), Ok(4));
+    assert_eq!(waveform.samples(), [0, 16383, i16::MAX, -i16::MAX]);
+
+    // Rewriting truncates the waveform.
+    assert_eq!(waveform.write(1, &[0.]), Ok(2));
+    assert_eq!(waveform.samples(), [0, 0]);
+
+    assert_eq!(waveform.write(3, &[0.]), Err(Error::InvalidOffset));
+    assert_eq!(waveform.write(0, &[1.5]), Err(Error::InvalidSample));
+    assert_eq!(
+        waveform.write_codes(0, &[0; WAVEFORM_CAPACITY + 1]),
+        Err(Error::InvalidOffset)
+    );
+
+    waveform.clear();
+    assert!(waveform.samples().is_empty());
+}
+
+#[test]
+fn chirp_linear() {
+    let mut generator = chirp(ChirpMode::Linear, true);
+    assert!((frequency(&generator) - 10.).abs() < 1e-3);
+
+    generator.by_ref().take(50).count();
+    assert!((frequency(&generator) - 60.).abs() < 1e-3);
+
+    // The sweep restarts after 100 samples.
+    generator.by_ref().take(50).count();
+    assert!((frequency(&generator) - 10.).abs() < 1e-3);
+}
+
+#[test]
+fn chirp_update() {
+    let mut generator = chirp(ChirpMode::Linear, true);
+    generator.by_ref().take(50).count();
+
+    // An unrelated change continues the sweep.
+    let mut config = *generator.config();
+    config.amplitude /= 2;
+    generator.update_waveform(config);
+    assert!((frequency(&generator) - 60.).abs() < 1e-3);
+
+    // A change of the sweep restarts it.
+    config.chirp.repeat = false;
+    generator.update_waveform(config);
+    assert!((frequency(&generator) - 10.).abs() < 1e-3);
+}
+
+#[test]
+fn chirp_logarithmic() {
+    let mut generator = chirp(ChirpMode::Logarithmic, false);
+    generator.update_waveform(
+        BasicConfig {
+            frequency: 4.,
+            stop_frequency: 400.,
+            duration: 0.1,
+            chirp: ChirpMode::Logarithmic,
+            repeat: false,
+            ..Default::default()
+        }
+        .try_into_config(SAMPLE_PERIOD, 10.)
+        .unwrap(),
+    );
+
+    generator.by_ref().take(50).count();
+    assert!((frequency(&generator) - 40.).abs() < 1e-2);
+
+    // The sweep stays at the stop frequency.
+    generator.by_ref().take(200).count();
+    assert!((frequency(&generator) - 400.).abs() < 20.);
+}
+
+#[test]
+fn chirp_invalid() {
+    let config = BasicConfig {
+        frequency: 0.,
+        chirp: ChirpMode::Logarithmic,
+        ..Default::default()
+    };
+    assert_eq!(
+        config.try_into_config(SAMPLE_PERIOD, 10.).unwrap_err(),
+        Error::InvalidFrequency
+    );
+
+    let config = BasicConfig {
+        chirp: ChirpMode::Linear,
+        stop_frequency: 100.,
+        frequency: 10.,
+        duration: 0.,
+        ..Default::default()
+    };
+    assert_eq!(
+        config.try_into_config(SAMPLE_PERIOD, 10.).unwrap_err(),
+        Error::InvalidDuration
+    );
+}
+
+#[test]
+fn noise_seed() {
+    let a = noise(Signal::WhiteNoise, Distribution::Uniform, 1);
+    assert_eq!(a, noise(Signal::WhiteNoise, Distribution::Uniform, 1));
+    assert_ne!(a, noise(Signal::WhiteNoise, Distribution::Uniform, 2));
+
+    // Changing the seed restarts the sequence.
+    let config = |seed| {
+        BasicConfig {
+            signal: Signal::WhiteNoise,
+            frequency: 10.,
+            amplitude: 5.,
+            seed,
+            ..Default::default()
+        }
+        .try_into_config(SAMPLE_PERIOD, 10.)
+        .unwrap()
+    };
+    let mut generator = SignalGenerator::new(config(2));
+    generator.by_ref().take(10).count();
+    generator.update_waveform(config(1));
+    assert!(generator
+        .take(16)
+        .map(f32::from)
+        .eq(a[..16].iter().copied()));
+}
+
+#[test]
+fn noise_distribution() {
+    let uniform = noise(Signal::WhiteNoise, Distribution::Uniform, 0);
+    let (rms, correlation) = statistics(&uniform);
+    assert!((rms / (16384. / 3f32.sqrt()) - 1.).abs() < 0.02);
+    assert!(correlation.abs() < 0.02);
+
+    let gaussian = noise(Signal::WhiteNoise, Distribution::Gaussian, 0);
+    let (rms, correlation) = statistics(&gaussian);
+    assert!((rms / (16384. / 12f32.sqrt()) - 1.).abs() < 0.02);
+    assert!(correlation.abs() < 0.02);
+    assert!(gaussian.iter().all(|x| x.abs() <= 16384.));
+    // About 5% of normally distributed samples exceed two standard deviations.
+    let outliers = gaussian
+        .iter()
+        .filter(|x| x.abs() > 2. * 16384. / 12f32.sqrt())
+        .count() as f32;
+    assert!((outliers / gaussian.len() as f32 - 0.046).abs() < 0.01);
+}
+
+#[test]
+fn noise_pink() {
+    let pink = noise(Signal::PinkNoise, Distribution::Uniform, 0);
+    let (rms, correlation) = statistics(&pink);
+    assert!(rms > 0. && pink.iter().all(|x| x.abs() <= 16384.));
+    // Adjacent samples share all but one octave.
+    assert!(correlation > 0.8);
+}
+
+#[test]
+fn noise_band_limited() {
+    let white = noise(Signal::WhiteNoise, Distribution::Gaussian, 0);
+    let band = noise(Signal::BandLimitedNoise, Distribution::Gaussian, 0);
+    let (rms, correlation) = statistics(&band);
+    // The RMS value is preserved. The bandwidth is 1% of the sample rate.
+    assert!((rms / statistics(&white).0 - 1.).abs() < 0.1);
+    assert!(correlation > 0.9);
+
+    let config = BasicConfig {
+        signal: Signal::BandLimitedNoise,
+        frequency: 0.,
+        amplitude: 5.,
+        ..Default::default()
+    };
+    assert_eq!(
+        config.try_into_config(SAMPLE_PERIOD, 10.).unwrap_err(),
+        Error::InvalidFrequency
+    );
+}
+
+fn square(burst: u32) -> SignalGenerator {
+    SignalGenerator::new(Config {
+        signal: Signal::Square,
+        amplitude: 16384,
+        phase_increment: [1 << 30; 2],
+        phase_offset: 0,
+        chirp: Default::default(),
+        noise: Default::default(),
+        trigger: Trigger {
+            source: TriggerSource::Di0,
+            burst,
+        },
+    })
+}
+
+#[test]
+fn trigger_burst() {
+    let mut generator = square(2);
+    assert!(generator.by_ref().take(4).all(|y| y == 0));
+
+    generator.trigger();
+    let samples: Vec<i16> = generator.by_ref().take(12).collect();
+    assert_eq!(
+        samples,
+        [
+            16384, 16384, -16384, -16384, 16384, 16384, -16384, -16384, 0, 0,
+            0, 0
+        ]
+    );
+
+    // A trigger during a burst restarts it.
+    generator.trigger();
+    generator.by_ref().take(6).count();
+    generator.trigger();
+    assert_eq!(generator.by_ref().take(8).filter(|&y| y == 0).count(), 0);
+    assert_eq!(generator.next(), Some(0));
+}
+
+#[test]
+fn trigger_phase() {
+    let mut generator = square(0);
+    assert_eq!(generator.by_ref().take(3).last(), Some(-16384));
+
+    // The signal runs continuously and restarts on a trigger.
+    generator.trigger();
+    let samples: Vec<i16> = generator.take(6).collect();
+    assert_eq!(samples, [16384, 16384, -16384, -16384, 16384, 16384]);
+}
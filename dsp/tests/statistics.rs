@@ -0,0 +1,38 @@
+use stabilizer_dsp::statistics::Statistics;
+
+#[test]
+fn empty() {
+    let stats = Statistics::default();
+    assert_eq!(stats.count(), 0);
+    assert_eq!(stats.full_scale(), 0);
+    assert_eq!(stats.min(), None);
+    assert_eq!(stats.max(), None);
+    assert_eq!(stats.mean(), None);
+    assert_eq!(stats.rms(), None);
+}
+
+#[test]
+fn statistics() {
+    let mut stats = Statistics::default();
+    stats.extend([3, -4, 3, -4]);
+
+    assert_eq!(stats.count(), 4);
+    assert_eq!(stats.min(), Some(-4));
+    assert_eq!(stats.max(), Some(3));
+    assert_eq!(stats.mean(), Some(-0.5));
+    assert!((stats.rms().unwrap() - 12.5f32.sqrt()).abs() < 1e-6);
+    assert_eq!(stats.full_scale(), 0);
+}
+
+#[test]
+fn full_scale() {
+    let mut stats = Statistics::default();
+    stats.extend([i16::MIN, 0, i16::MAX, i16::MAX - 1]);
+
+    assert_eq!(stats.full_scale(), 2);
+    assert_eq!(stats.min(), Some(i16::MIN));
+    assert_eq!(stats.max(), Some(i16::MAX));
+    let rms = ((32768f64.powi(2) + 32767f64.powi(2) + 32766f64.powi(2)) / 4.)
+        .sqrt() as f32;
+    assert_eq!(stats.rms(), Some(rms));
+}
@@ -51,6 +51,33 @@ class AdcDac:
         """Return the data size of the frame in bytes"""
         return len(self.body)
 
+    def decimation(self):
+        """Return the decimation factor of the frame
+
+        Each decimated batch covers `decimation()` undecimated batches and
+        its values are averages over `decimation()` consecutive values.
+        """
+        return 1 << self.header.decimation
+
+    def sample_period(self):
+        """Return the (undecimated) device sample period in seconds"""
+        return self.header.sample_period * 1e-9
+
+    def batch_time(self):
+        """Return the device time of the first sample of each batch in
+        seconds since the start of sampling"""
+        period = self.header.sample_period * self.decimation()
+        batch_period = period * self.header.batch_size
+        return (self.header.timestamp
+                + np.arange(self.batch_count()) * batch_period) * 1e-9
+
+    def time(self):
+        """Return the device time of each sample in seconds since the start of
+        sampling"""
+        period = self.header.sample_period * self.decimation()
+        count = self.batch_count() * self.header.batch_size
+        return (self.header.timestamp + np.arange(count) * period) * 1e-9
+
     def to_mu(self):
         """Return the raw data in machine units"""
         data = np.frombuffer(self.body, "<i2")
@@ -79,19 +106,201 @@ class AdcDac:
         ]
 
 
+class AdcDacFrequency(AdcDac):
+    """Stabilizer streaming data format with signal generator frequencies"""
+    format_id = 3
+
+    def batch_count(self):
+        """Return the number of batches in the frame"""
+        return self.size() // (4 * 2 * self.header.batch_size + 2 * 4)
+
+    def _batches(self):
+        """Return the raw batches as a structured array"""
+        dtype = np.dtype([
+            ("data", "<i2", (4, self.header.batch_size)),
+            ("frequency", "<u4", (2,)),
+        ])
+        return np.frombuffer(self.body, dtype)
+
+    def to_mu(self):
+        """Return the raw data in machine units"""
+        data = self._batches()["data"].copy()
+        data = data.swapaxes(0, 1).reshape(4, -1)
+        # convert DAC offset binary to two's complement
+        data[2:] ^= np.int16(0x8000)
+        return data
+
+    def frequency(self):
+        """Return the signal generator frequencies of each batch in units of
+        the sample rate"""
+        return self._batches()["frequency"].T / (1 << 32)
+
+    def to_si(self):
+        """Convert the raw data to SI units"""
+        si = super().to_si()
+        si["frequency"] = self.frequency() / self.sample_period()
+        return si
+
+
+class IirState(AdcDac):
+    """Stabilizer streaming data format with IIR cascade inputs and outputs"""
+    format_id = 4
+
+    def batch_count(self):
+        """Return the number of batches in the frame"""
+        return self.size() // (4 * 4 * self.header.batch_size)
+
+    def to_mu(self):
+        """Return the raw data in machine units"""
+        data = np.frombuffer(self.body, "<f4")
+        data = data.reshape(-1, 4, self.header.batch_size)
+        return data.swapaxes(0, 1).reshape(4, -1)
+
+    def to_si(self):
+        """Convert the raw data to SI units"""
+        data = self.to_mu() * DAC_VOLTS_PER_LSB
+        return {
+            "input": data[:2],
+            "output": data[2:],
+        }
+
+    def to_traces(self):
+        """Convert the raw data to labelled Trace instances"""
+        data = self.to_mu()
+        return [
+            Trace(data[0], scale=DAC_VOLTS_PER_LSB, label='IN0'),
+            Trace(data[1], scale=DAC_VOLTS_PER_LSB, label='IN1'),
+            Trace(data[2], scale=DAC_VOLTS_PER_LSB, label='OUT0'),
+            Trace(data[3], scale=DAC_VOLTS_PER_LSB, label='OUT1')
+        ]
+
+
+class LockinIq(AdcDac):
+    """Stabilizer streaming data format with demodulated lockin components"""
+    format_id = 5
+
+    def batch_count(self):
+        """Return the number of batches in the frame"""
+        return self.size() // (2 * 4)
+
+    def time(self):
+        """Return the device time of each batch in seconds since the start of
+        sampling"""
+        return self.batch_time()
+
+    def to_mu(self):
+        """Return the raw data in machine units"""
+        return np.frombuffer(self.body, "<i4").reshape(-1, 2).T
+
+    def to_si(self):
+        """Convert the raw data to full scale units"""
+        data = self.to_mu() / (1 << 31)
+        return {
+            "i": data[0],
+            "q": data[1],
+        }
+
+    def to_traces(self):
+        """Convert the raw data to labelled Trace instances"""
+        data = self.to_mu()
+        return [
+            Trace(data[0], scale=1 / (1 << 31), label='I'),
+            Trace(data[1], scale=1 / (1 << 31), label='Q'),
+        ]
+
+
+class PhaseFrequency(AdcDac):
+    """Stabilizer streaming data format with the lockin reference phase and
+    frequency"""
+    format_id = 6
+
+    def batch_count(self):
+        """Return the number of batches in the frame"""
+        return self.size() // (2 * 4)
+
+    def time(self):
+        """Return the device time of each batch in seconds since the start of
+        sampling"""
+        return self.batch_time()
+
+    def to_mu(self):
+        """Return the raw data in machine units"""
+        return np.frombuffer(self.body, "<i4").reshape(-1, 2).T
+
+    def to_si(self):
+        """Convert the raw data to turns and Hertz"""
+        data = self.to_mu() / (1 << 32)
+        return {
+            "phase": data[0],
+            "frequency": data[1] / self.sample_period(),
+        }
+
+    def to_traces(self):
+        """Convert the raw data to labelled Trace instances"""
+        data = self.to_mu()
+        return [
+            Trace(data[0], scale=1 / (1 << 32), label='Phase'),
+            Trace(data[1], scale=1 / (1 << 32) / self.sample_period(),
+                  label='Frequency'),
+        ]
+
+
+class SignalGenerator(AdcDac):
+    """Stabilizer streaming data format with signal generator outputs"""
+    format_id = 7
+
+    def batch_count(self):
+        """Return the number of batches in the frame"""
+        return self.size() // (2 * 2 * self.header.batch_size)
+
+    def to_mu(self):
+        """Return the raw data in machine units"""
+        data = np.frombuffer(self.body, "<i2")
+        data = data.reshape(-1, 2, self.header.batch_size)
+        return data.swapaxes(0, 1).reshape(2, -1)
+
+    def to_si(self):
+        """Convert the raw data to SI units"""
+        return {
+            "signal": self.to_mu() * DAC_VOLTS_PER_LSB,
+        }
+
+    def to_traces(self):
+        """Convert the raw data to labelled Trace instances"""
+        data = self.to_mu()
+        return [
+            Trace(data[0], scale=DAC_VOLTS_PER_LSB, label='SIG0'),
+            Trace(data[1], scale=DAC_VOLTS_PER_LSB, label='SIG1'),
+        ]
+
+
 class StabilizerStream(asyncio.DatagramProtocol):
     """Stabilizer streaming receiver protocol"""
     # The magic header half-word at the start of each packet.
     magic = 0x057B
-    header_fmt = struct.Struct("<HBBI")
-    header = namedtuple("Header", "magic format_id batch_size sequence")
+    # The supported header layout version.
+    version = 1
+    header_fmt = struct.Struct("<HBBIBB2xQI")
+    header = namedtuple(
+        "Header",
+        "magic format_id batch_size sequence version decimation timestamp "
+        "sample_period")
     parsers = {
         AdcDac.format_id: AdcDac,
+        AdcDacFrequency.format_id: AdcDacFrequency,
+        IirState.format_id: IirState,
+        LockinIq.format_id: LockinIq,
+        PhaseFrequency.format_id: PhaseFrequency,
+        SignalGenerator.format_id: SignalGenerator,
     }
 
     @classmethod
-    async def open(cls, local_addr, maxsize=1):
-        """Open a UDP socket and start receiving frames"""
+    async def open(cls, local_addr, maxsize=1, group=None):
+        """Open a UDP socket and start receiving frames
+
+        If a multicast `group` address is given, the socket joins the group
+        on the interface with the local address.
+        """
         loop = asyncio.get_running_loop()
         transport, protocol = await loop.create_datagram_endpoint(
             lambda: cls(maxsize), local_addr=local_addr)
@@ -103,11 +312,37 @@ class StabilizerStream(asyncio.DatagramProtocol):
         sock = transport.get_extra_info("socket")
         if sock is not None:
             sock.setsockopt(socket.SOL_SOCKET, socket.SO_RCVBUF, 4 << 20)
+            if group is not None:
+                membership = struct.pack(
+                    "4s4s", socket.inet_aton(group),
+                    socket.inet_aton(local_addr[0]))
+                sock.setsockopt(socket.IPPROTO_IP, socket.IP_ADD_MEMBERSHIP,
+                                membership)
         return transport, protocol
 
+    @classmethod
+    async def open_tcp(cls, local_addr, maxsize=1):
+        """Listen for TCP stream connections and start receiving frames"""
+        protocol = cls(maxsize)
+        server = await asyncio.start_server(
+            protocol.serve_tcp, local_addr[0], local_addr[1])
+        return server, protocol
+
     def __init__(self, maxsize):
         self.queue = asyncio.Queue(maxsize)
 
+    async def serve_tcp(self, reader, writer):
+        """Receive size-prefixed frames from a TCP stream connection"""
+        logger.info("TCP stream connected")
+        try:
+            while True:
+                size, = struct.unpack("<H", await reader.readexactly(2))
+                self.frame_received(await reader.readexactly(size))
+        except asyncio.IncompleteReadError:
+            logger.info("TCP stream disconnected")
+        finally:
+            writer.close()
+
     def connection_made(self, _transport):
         logger.info("Connection made (listening)")
 
@@ -115,10 +350,18 @@ class StabilizerStream(asyncio.DatagramProtocol):
         logger.info("Connection lost")
 
     def datagram_received(self, data, _addr):
+        self.frame_received(data)
+
+    def frame_received(self, data):
+        """Parse a frame and queue it"""
         header = self.header._make(self.header_fmt.unpack_from(data))
         if header.magic != self.magic:
             logger.warning("Bad frame magic: %#04x, ignoring", header.magic)
             return
+        if header.version != self.version:
+            logger.warning("Unsupported header version %s, ignoring",
+                           header.version)
+            return
         try:
             parser = self.parsers[header.format_id]
         except KeyError:
@@ -181,11 +424,19 @@ async def main():
                         help="Frame queue size")
     parser.add_argument("--duration", type=float, default=1.,
                         help="Test duration")
+    parser.add_argument("--tcp", action="store_true",
+                        help="Receive the stream over TCP instead of UDP")
+    parser.add_argument("--group",
+                        help="Join the multicast group to receive the stream")
     args = parser.parse_args()
 
     logging.basicConfig(level=logging.INFO)
-    _transport, stream = await StabilizerStream.open(
-        (args.host, args.port), args.maxsize)
+    if args.tcp:
+        _server, stream = await StabilizerStream.open_tcp(
+            (args.host, args.port), args.maxsize)
+    else:
+        _transport, stream = await StabilizerStream.open(
+            (args.host, args.port), args.maxsize, args.group)
     await measure(stream, args.duration)
 
 
@@ -0,0 +1,124 @@
+#!/usr/bin/python3
+"""Stabilizer arbitrary waveform upload utility"""
+
+import argparse
+import asyncio
+import json
+import logging
+import uuid
+
+import numpy as np
+from gmqtt import Client as MqttClient
+
+logger = logging.getLogger(__name__)
+
+# The maximum number of samples per uploaded chunk. This must not exceed
+# `MAX_WAVEFORM_CHUNK` of the firmware.
+CHUNK_SIZE = 64
+
+# The maximum number of samples of a waveform.
+WAVEFORM_CAPACITY = 1024
+
+
+class WaveformClient:
+    """Upload arbitrary waveforms to the Stabilizer signal generators using
+    MQTT commands."""
+
+    @classmethod
+    async def create(cls, prefix, broker):
+        """Connect to the MQTT broker at `broker` and create a client for
+        the device with prefix `prefix`."""
+        client = MqttClient(client_id="")
+        await client.connect(broker)
+        return cls(client, prefix)
+
+    def __init__(self, client, prefix):
+        self.client = client
+        self.prefix = prefix
+        self._response_topic = f"{prefix}/response/{uuid.uuid1().hex}"
+        self._pending = None
+        self.client.on_message = self._handle_response
+        self.client.subscribe(self._response_topic)
+
+    def _handle_response(self, _client, topic, payload, _qos, _properties):
+        assert topic == self._response_topic
+        if self._pending is not None and not self._pending.done():
+            self._pending.set_result(json.loads(payload))
+
+    async def command(self, command, payload=b""):
+        """Issue a command and wait for its response.
+
+        Raises a `RuntimeError` if the command failed.
+        """
+        self._pending = asyncio.get_running_loop().create_future()
+        self.client.publish(f"{self.prefix}/command/{command}", payload,
+                            qos=1, response_topic=self._response_topic)
+        response = await self._pending
+        if response["code"] != 0:
+            raise RuntimeError(f"{command} failed: {response['msg']}")
+        return response["msg"]
+
+    async def upload(self, channel, samples, save=False):
+        """Upload and commit the waveform of a signal generator channel.
+
+        Args:
+            channel: The signal generator channel.
+            samples: The waveform samples normalized to [-1, 1].
+            save: Store the waveform in flash so that it is restored at boot.
+        """
+        samples = np.asarray(samples, dtype=float)
+        if len(samples) > WAVEFORM_CAPACITY:
+            raise ValueError(f"Waveform exceeds {WAVEFORM_CAPACITY} samples")
+        if np.any(np.abs(samples) > 1):
+            raise ValueError("Waveform samples must be within [-1, 1]")
+
+        await self.command(f"waveform/{channel}/clear")
+        for offset in range(0, len(samples), CHUNK_SIZE):
+            chunk = samples[offset:offset + CHUNK_SIZE]
+            msg = await self.command(f"waveform/{channel}/write", json.dumps({
+                "offset": offset,
+                "samples": [round(float(x), 6) for x in chunk],
+            }))
+            logger.debug("Uploaded %s", msg)
+
+        await self.command(f"waveform/{channel}/commit")
+        if save:
+            await self.command(f"waveform/{channel}/save")
+
+
+def _main():
+    parser = argparse.ArgumentParser(
+        description="Upload an arbitrary waveform to a Stabilizer signal "
+        "generator. Select the `Arbitrary` signal of the signal generator to "
+        "play it back.")
+    parser.add_argument("-v", "--verbose", action="count", default=0,
+                        help="Increase logging verbosity")
+    parser.add_argument("--broker", "-b", type=str, default="10.34.16.1",
+                        help="The MQTT broker address")
+    parser.add_argument("--prefix", "-p", type=str, required=True,
+                        help="The device prefix, e.g. "
+                        "dt/sinara/dual-iir/00-11-22-33-44-55")
+    parser.add_argument("--channel", "-c", type=int, choices=[0, 1],
+                        required=True, help="The signal generator channel")
+    parser.add_argument("--save", action="store_true",
+                        help="Store the waveform in flash")
+    parser.add_argument("file", type=str,
+                        help="A text file with one normalized sample per line")
+    args = parser.parse_args()
+
+    logging.basicConfig(
+        format='%(asctime)s [%(levelname)s] %(name)s: %(message)s',
+        level=logging.WARN - 10*args.verbose)
+
+    samples = np.loadtxt(args.file, ndmin=1)
+
+    async def upload():
+        client = await WaveformClient.create(args.prefix, args.broker)
+        await client.upload(args.channel, samples, args.save)
+        await client.client.disconnect()
+
+    asyncio.run(upload())
+
+
+if __name__ == "__main__":
+    _main()
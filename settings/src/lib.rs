@@ -0,0 +1,507 @@
+//! Stabilizer settings persistence
+//!
+//! # Design
+//! Run-time settings can be stored in non-volatile memory so that they survive a device reset.
+//! Settings are serialized path-by-path using [Miniconf]: every settings path is stored together
+//! with its JSON-encoded value. When restoring, each stored path is applied individually. Settings
+//! stored by a different firmware version are thus restored as far as their paths are still valid.
+//! Stored paths that no longer exist are skipped.
+//!
+//! Multiple named snapshots of the settings, called profiles, can be stored. Every profile occupies
+//! one erase sector of the flash, so storing or erasing a profile never affects the others. The
+//! profile named [DEFAULT_PROFILE] is restored at boot.
+//!
+//! A profile is replaced by writing the new copy to a free sector before the previous copy is
+//! erased, so a power loss while storing never loses the profile. Every copy carries a sequence
+//! number and the newest intact copy of a profile is restored. One sector is therefore always kept
+//! free to replace profiles in, see [MAX_PROFILES].
+//!
+//! The store is generic over the [NorFlash] it operates on, so it does not depend on Stabilizer
+//! hardware. It is tested on the host against a flash held in RAM:
+//!
+//! ```sh
+//! cargo test -p stabilizer-settings --target x86_64-unknown-linux-gnu
+//! ```
+//!
+//! ## Storage Layout
+//! Each profile consists of a header followed by the settings records, all in little-endian.
+//!
+//! * **Magic word 0x5E77** <u16>: a constant to identify stored settings.
+//! * **Layout Version** <u16>: the version of this storage layout.
+//! * **Application ID** <u32>: the CRC-32 of the application name. This prevents settings of one
+//!   application from being restored into another.
+//! * **Length** <u32>: the number of bytes of settings records following the header.
+//! * **CRC** <u32>: the CRC-32 of the sequence number, the profile name and the settings records.
+//! * **Sequence** <u32>: incremented every time the profile is stored.
+//! * **Name** <[u8; 16]>: the profile name, padded with zeros.
+//!
+//! Each settings record consists of:
+//!
+//! * **Path Length** <u8>: the number of bytes in the path.
+//! * **Path**: the UTF-8 encoded settings path.
+//! * **Value Length** <u16>: the number of bytes in the value.
+//! * **Value**: the JSON-encoded settings value.
+#![no_std]
+
+use embedded_storage::nor_flash::NorFlash;
+use heapless::{String, Vec};
+use miniconf::Miniconf;
+
+// Magic first bytes indicating stored settings.
+const MAGIC: u16 = 0x5E77;
+
+// The version of the storage layout. This must be incremented on any incompatible layout change.
+const VERSION: u16 = 2;
+
+// The offset of the sequence number within the header. It is the start of the CRC-protected data.
+const SEQUENCE_OFFSET: usize = 16;
+
+// The offset of the profile name within the header.
+const NAME_OFFSET: usize = 20;
+
+// The size of the header in bytes.
+const HEADER_SIZE: usize = NAME_OFFSET + MAX_NAME_LENGTH;
+
+// The maximum depth and length of a settings path. These match the limits of the miniconf MQTT
+// client, so any settings that can be configured can also be stored.
+const MAX_PATH_DEPTH: usize = 8;
+const MAX_PATH_LENGTH: usize = 128;
+
+/// The maximum size of a stored profile in bytes, including the header.
+pub const MAX_SETTINGS_SIZE: usize = 4096;
+
+/// The maximum number of profile slots.
+///
+/// # Note
+/// The number of slots is also limited by the number of erase sectors of the flash. One slot is
+/// always kept free to replace profiles in, so at most one profile less than the number of slots
+/// can be stored, including the profiles of other applications. Stabilizer has six slots, one of
+/// which holds the network configuration, leaving four for application profiles.
+pub const MAX_PROFILES: usize = 8;
+
+/// The maximum length of a profile name in bytes.
+pub const MAX_NAME_LENGTH: usize = 16;
+
+/// The name of the profile that is restored at boot.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// The name of a stored profile.
+pub type ProfileName = String<MAX_NAME_LENGTH>;
+
+/// Errors that can occur when storing or restoring settings.
+#[derive(Copy, Clone, Debug)]
+pub enum Error<E> {
+    /// There is no stored profile with the requested name.
+    NotFound,
+    /// All profile slots are in use. One slot is always kept free to replace profiles in.
+    Full,
+    /// The profile name is empty, too long or contains characters other than ASCII alphanumerics,
+    /// `-` and `_`.
+    InvalidName,
+    /// The stored settings are corrupt.
+    Corrupt,
+    /// The serialized settings exceed [MAX_SETTINGS_SIZE].
+    Overflow,
+    /// The settings paths could not be iterated.
+    Iter(miniconf::IterError),
+    /// A settings value could not be serialized.
+    Miniconf(miniconf::Error),
+    /// The underlying flash reported an error.
+    Flash(E),
+}
+
+// The contents of a profile slot.
+enum Slot {
+    // The slot does not contain valid settings and may be used.
+    Free,
+    // The slot contains settings of a different application.
+    Foreign,
+    // The slot contains a profile of this application with its sequence number.
+    Profile(ProfileName, u32),
+}
+
+/// Persistent storage of run-time settings profiles.
+pub struct SettingsStore<F> {
+    flash: F,
+    app_id: u32,
+    buffer: [u8; MAX_SETTINGS_SIZE],
+}
+
+impl<F: NorFlash> SettingsStore<F> {
+    /// Construct a settings store.
+    ///
+    /// # Args
+    /// * `flash` - The flash to store settings in. Every erase sector holds one profile.
+    /// * `app` - The name of the application the settings belong to.
+    pub fn new(flash: F, app: &str) -> Self {
+        Self {
+            flash,
+            app_id: crc32(app.as_bytes()),
+            buffer: [0; MAX_SETTINGS_SIZE],
+        }
+    }
+
+    /// Store settings as a profile, replacing any previously stored profile of the same name.
+    ///
+    /// # Note
+    /// This erases flash sectors, which may block for a significant amount of time. The previous
+    /// profile is only erased once the new profile is written.
+    ///
+    /// # Args
+    /// * `name` - The name of the profile.
+    /// * `settings` - The settings to store.
+    ///
+    /// # Returns
+    /// The number of bytes stored.
+    pub fn save<S: Miniconf>(
+        &mut self,
+        name: &str,
+        settings: &S,
+    ) -> Result<usize, Error<F::Error>> {
+        check_name(name)?;
+
+        let mut latest = None;
+        let mut free = None;
+        let mut spare = false;
+        for slot in 0..self.slots() {
+            match self.read_slot(slot)? {
+                Slot::Free if free.is_none() => free = Some(slot),
+                Slot::Free => spare = true,
+                Slot::Profile(profile, sequence)
+                    if profile == name
+                        && latest.map_or(true, |latest| {
+                            is_newer(sequence, latest)
+                        }) =>
+                {
+                    latest = Some(sequence)
+                }
+                _ => {}
+            }
+        }
+
+        // A new profile must leave a free slot to replace profiles in.
+        let slot = free
+            .filter(|_| latest.is_some() || spare)
+            .ok_or(Error::Full)?;
+        let sequence = latest.map_or(0, |latest| latest.wrapping_add(1));
+
+        let mut length = HEADER_SIZE;
+
+        for path in S::iter_paths::<MAX_PATH_DEPTH, MAX_PATH_LENGTH>()
+            .map_err(Error::Iter)?
+        {
+            let value_start = length + 1 + path.len() + 2;
+            if value_start > self.buffer.len() {
+                return Err(Error::Overflow);
+            }
+
+            let value_length =
+                match settings.get(&path, &mut self.buffer[value_start..]) {
+                    Ok(len) => len,
+                    // Paths that are absent at run-time have no value to store.
+                    Err(miniconf::Error::PathAbsent) => continue,
+                    Err(miniconf::Error::Serialization(_)) => {
+                        return Err(Error::Overflow)
+                    }
+                    Err(error) => return Err(Error::Miniconf(error)),
+                };
+
+            self.buffer[length] = path.len() as u8;
+            self.buffer[length + 1..value_start - 2]
+                .copy_from_slice(path.as_bytes());
+            self.buffer[value_start - 2..value_start]
+                .copy_from_slice(&(value_length as u16).to_le_bytes());
+
+            length = value_start + value_length;
+        }
+
+        self.buffer[SEQUENCE_OFFSET..NAME_OFFSET]
+            .copy_from_slice(&sequence.to_le_bytes());
+        let name_field = &mut self.buffer[NAME_OFFSET..HEADER_SIZE];
+        name_field.fill(0);
+        name_field[..name.len()].copy_from_slice(name.as_bytes());
+
+        let crc = crc32(&self.buffer[SEQUENCE_OFFSET..length]);
+        for (offset, byte) in MAGIC
+            .to_le_bytes()
+            .iter()
+            .chain(VERSION.to_le_bytes().iter())
+            .chain(self.app_id.to_le_bytes().iter())
+            .chain(((length - HEADER_SIZE) as u32).to_le_bytes().iter())
+            .chain(crc.to_le_bytes().iter())
+            .enumerate()
+        {
+            self.buffer[offset] = *byte;
+        }
+
+        // Pad the settings to the write granularity of the flash.
+        let padded =
+            (length + F::WRITE_SIZE - 1) / F::WRITE_SIZE * F::WRITE_SIZE;
+        if padded > self.buffer.len() {
+            return Err(Error::Overflow);
+        }
+        self.buffer[length..padded].fill(0xFF);
+
+        // Note: A free slot is not necessarily erased.
+        self.erase_slot(slot)?;
+        self.flash
+            .write(Self::offset(slot), &self.buffer[..padded])
+            .map_err(Error::Flash)?;
+
+        self.erase_copies(name, Some(slot))?;
+
+        Ok(length)
+    }
+
+    /// Restore a stored profile.
+    ///
+    /// # Note
+    /// The newest intact copy of the profile is restored. Stored paths that cannot be applied are
+    /// skipped. If an error is returned, `settings` may
+    /// have been partially updated.
+    ///
+    /// # Args
+    /// * `name` - The name of the profile.
+    /// * `settings` - The settings to apply the stored values to.
+    ///
+    /// # Returns
+    /// The number of settings paths that were restored.
+    pub fn load<S: Miniconf>(
+        &mut self,
+        name: &str,
+        settings: &mut S,
+    ) -> Result<usize, Error<F::Error>> {
+        let slot = self.find(name)?;
+        let length = self.read_profile(slot)?;
+
+        let mut restored = 0;
+        let mut records = &self.buffer[HEADER_SIZE..HEADER_SIZE + length];
+        while !records.is_empty() {
+            let (path, value, remainder) =
+                split_record(records).ok_or(Error::Corrupt)?;
+
+            match settings.set(path, value) {
+                Ok(_) => restored += 1,
+                Err(error) => {
+                    log::warn!("Skipping stored setting {}: {:?}", path, error)
+                }
+            }
+
+            records = remainder;
+        }
+
+        Ok(restored)
+    }
+
+    /// Erase a stored profile.
+    ///
+    /// # Note
+    /// This erases flash sectors, which may block for a significant amount of time.
+    ///
+    /// # Args
+    /// * `name` - The name of the profile.
+    pub fn erase(&mut self, name: &str) -> Result<(), Error<F::Error>> {
+        if self.erase_copies(name, None)? {
+            Ok(())
+        } else {
+            Err(Error::NotFound)
+        }
+    }
+
+    /// Get the names of all stored profiles.
+    pub fn profiles(
+        &mut self,
+    ) -> Result<Vec<ProfileName, MAX_PROFILES>, Error<F::Error>> {
+        let mut profiles = Vec::new();
+        for slot in 0..self.slots() {
+            match self.read_slot(slot)? {
+                // Note: A profile may have multiple copies if storing it was interrupted.
+                Slot::Profile(name, _) if !profiles.contains(&name) => {
+                    // Note(unwrap): There are at most `MAX_PROFILES` slots.
+                    profiles.push(name).unwrap();
+                }
+                _ => {}
+            }
+        }
+
+        Ok(profiles)
+    }
+
+    /// Get the flash the settings are stored in.
+    pub fn flash(&mut self) -> &mut F {
+        &mut self.flash
+    }
+
+    fn slots(&self) -> usize {
+        (self.flash.capacity() / F::ERASE_SIZE).min(MAX_PROFILES)
+    }
+
+    fn offset(slot: usize) -> u32 {
+        (slot * F::ERASE_SIZE) as u32
+    }
+
+    fn erase_slot(&mut self, slot: usize) -> Result<(), Error<F::Error>> {
+        self.flash
+            .erase(Self::offset(slot), Self::offset(slot + 1))
+            .map_err(Error::Flash)
+    }
+
+    // Read the header of a slot into the buffer and determine the slot contents.
+    fn read_slot(&mut self, slot: usize) -> Result<Slot, Error<F::Error>> {
+        self.flash
+            .read(Self::offset(slot), &mut self.buffer[..HEADER_SIZE])
+            .map_err(Error::Flash)?;
+
+        let header = &self.buffer[..HEADER_SIZE];
+        if u16::from_le_bytes([header[0], header[1]]) != MAGIC
+            || u16::from_le_bytes([header[2], header[3]]) != VERSION
+        {
+            return Ok(Slot::Free);
+        }
+
+        if u32::from_le_bytes(header[4..8].try_into().unwrap()) != self.app_id {
+            return Ok(Slot::Foreign);
+        }
+
+        let name = &header[NAME_OFFSET..HEADER_SIZE];
+        let length = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+        match core::str::from_utf8(&name[..length]) {
+            Ok(name) if check_name::<F::Error>(name).is_ok() => {
+                let sequence = u32::from_le_bytes(
+                    header[SEQUENCE_OFFSET..NAME_OFFSET].try_into().unwrap(),
+                );
+                Ok(Slot::Profile(String::from(name), sequence))
+            }
+            _ => Ok(Slot::Free),
+        }
+    }
+
+    // Read a profile into the buffer and check its integrity.
+    //
+    // Returns the length of the settings records.
+    fn read_profile(&mut self, slot: usize) -> Result<usize, Error<F::Error>> {
+        self.flash
+            .read(Self::offset(slot), &mut self.buffer[..HEADER_SIZE])
+            .map_err(Error::Flash)?;
+
+        let header = &self.buffer[..HEADER_SIZE];
+        let word = |offset: usize| {
+            u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap())
+        };
+
+        let length = word(8) as usize;
+        let crc = word(12);
+        if length > self.buffer.len() - HEADER_SIZE {
+            return Err(Error::Corrupt);
+        }
+
+        self.flash
+            .read(
+                Self::offset(slot) + HEADER_SIZE as u32,
+                &mut self.buffer[HEADER_SIZE..HEADER_SIZE + length],
+            )
+            .map_err(Error::Flash)?;
+        if crc32(&self.buffer[SEQUENCE_OFFSET..HEADER_SIZE + length]) != crc {
+            return Err(Error::Corrupt);
+        }
+
+        Ok(length)
+    }
+
+    // Find the slot of the newest intact copy of a profile.
+    fn find(&mut self, name: &str) -> Result<usize, Error<F::Error>> {
+        let mut newest: Option<(usize, u32)> = None;
+        let mut found = false;
+        for slot in 0..self.slots() {
+            let sequence = match self.read_slot(slot)? {
+                Slot::Profile(profile, sequence) if profile == name => sequence,
+                _ => continue,
+            };
+
+            found = true;
+            match self.read_profile(slot) {
+                Ok(_) => {}
+                // An interrupted store leaves a corrupt copy next to the previous one.
+                Err(Error::Corrupt) => continue,
+                Err(error) => return Err(error),
+            }
+
+            if newest.map_or(true, |(_, newest)| is_newer(sequence, newest)) {
+                newest = Some((slot, sequence));
+            }
+        }
+
+        match newest {
+            Some((slot, _)) => Ok(slot),
+            None if found => Err(Error::Corrupt),
+            None => Err(Error::NotFound),
+        }
+    }
+
+    // Erase all copies of a profile except the one in the `keep` slot.
+    //
+    // Returns whether any copy was erased.
+    fn erase_copies(
+        &mut self,
+        name: &str,
+        keep: Option<usize>,
+    ) -> Result<bool, Error<F::Error>> {
+        let mut erased = false;
+        for slot in (0..self.slots()).filter(|&slot| Some(slot) != keep) {
+            match self.read_slot(slot)? {
+                Slot::Profile(profile, _) if profile == name => {
+                    self.erase_slot(slot)?;
+                    erased = true;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(erased)
+    }
+}
+
+/// Check that a profile name is valid.
+fn check_name<E>(name: &str) -> Result<(), Error<E>> {
+    if name.is_empty()
+        || name.len() > MAX_NAME_LENGTH
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(Error::InvalidName);
+    }
+
+    Ok(())
+}
+
+/// Check if a sequence number is newer than another, allowing for wrap-around.
+fn is_newer(sequence: u32, other: u32) -> bool {
+    (sequence.wrapping_sub(other) as i32) > 0
+}
+
+/// Split the first settings record from a sequence of records.
+///
+/// # Returns
+/// (path, value, remainder) or `None` if the record is malformed.
+fn split_record(records: &[u8]) -> Option<(&str, &[u8], &[u8])> {
+    let (&path_length, records) = records.split_first()?;
+    let path = records.get(..path_length as usize)?;
+    let records = &records[path_length as usize..];
+
+    let value_length =
+        u16::from_le_bytes(records.get(..2)?.try_into().unwrap()) as usize;
+    let value = records.get(2..2 + value_length)?;
+
+    let path = core::str::from_utf8(path).ok()?;
+    Some((path, value, &records[2 + value_length..]))
+}
+
+/// Compute the CRC-32 (IEEE 802.3) of the provided data.
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
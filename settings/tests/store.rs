@@ -0,0 +1,215 @@
+use embedded_storage::nor_flash::{
+    check_erase, check_read, check_write, ErrorType, NorFlash,
+    NorFlashErrorKind, ReadNorFlash,
+};
+use miniconf::Miniconf;
+use stabilizer_settings::{Error, SettingsStore, DEFAULT_PROFILE};
+
+const SECTOR_SIZE: usize = 4096;
+
+/// A flash held in RAM. Like NOR flash, writes can only clear bits.
+#[derive(Clone)]
+struct RamFlash {
+    data: Vec<u8>,
+    // The number of erases that succeed before erasing fails, if limited.
+    erases: Option<usize>,
+}
+
+impl RamFlash {
+    fn new(sectors: usize) -> Self {
+        Self {
+            data: vec![0xFF; sectors * SECTOR_SIZE],
+            erases: None,
+        }
+    }
+
+    fn sector(&self, sector: usize) -> &[u8] {
+        &self.data[sector * SECTOR_SIZE..(sector + 1) * SECTOR_SIZE]
+    }
+
+    fn used_sectors(&self) -> usize {
+        (0..self.data.len() / SECTOR_SIZE)
+            .filter(|&sector| self.sector(sector).iter().any(|&b| b != 0xFF))
+            .count()
+    }
+}
+
+impl ErrorType for RamFlash {
+    type Error = NorFlashErrorKind;
+}
+
+impl ReadNorFlash for RamFlash {
+    const READ_SIZE: usize = 1;
+
+    fn read(
+        &mut self,
+        offset: u32,
+        bytes: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.data[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl NorFlash for RamFlash {
+    const WRITE_SIZE: usize = 32;
+    const ERASE_SIZE: usize = SECTOR_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+        match self.erases.as_mut() {
+            Some(0) => return Err(NorFlashErrorKind::Other),
+            Some(erases) => *erases -= 1,
+            None => {}
+        }
+        self.data[from as usize..to as usize].fill(0xFF);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+        let offset = offset as usize;
+        for (cell, byte) in self.data[offset..].iter_mut().zip(bytes) {
+            *cell &= byte;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Miniconf)]
+struct Settings {
+    gain: f32,
+    enable: bool,
+    channels: [u8; 2],
+}
+
+fn settings(gain: f32) -> Settings {
+    Settings {
+        gain,
+        enable: true,
+        channels: [3, 4],
+    }
+}
+
+#[test]
+fn round_trip() {
+    let mut store = SettingsStore::new(RamFlash::new(4), "app");
+    let saved = settings(1.5);
+    store.save(DEFAULT_PROFILE, &saved).unwrap();
+
+    let mut loaded = Settings::default();
+    assert_eq!(store.load(DEFAULT_PROFILE, &mut loaded).unwrap(), 3);
+    assert_eq!(loaded, saved);
+    assert_eq!(store.profiles().unwrap(), [DEFAULT_PROFILE]);
+
+    store.erase(DEFAULT_PROFILE).unwrap();
+    assert!(matches!(
+        store.load(DEFAULT_PROFILE, &mut loaded),
+        Err(Error::NotFound)
+    ));
+    assert_eq!(store.flash().used_sectors(), 0);
+}
+
+#[test]
+fn corrupt() {
+    let mut store = SettingsStore::new(RamFlash::new(4), "app");
+    let length = store.save(DEFAULT_PROFILE, &settings(1.5)).unwrap();
+
+    // Flip a bit of the last stored settings value.
+    store.flash().data[length - 2] ^= 0x01;
+
+    let mut loaded = Settings::default();
+    assert!(matches!(
+        store.load(DEFAULT_PROFILE, &mut loaded),
+        Err(Error::Corrupt)
+    ));
+    assert_eq!(loaded, Settings::default());
+}
+
+#[test]
+fn foreign() {
+    let mut other = SettingsStore::new(RamFlash::new(4), "other");
+    other.save(DEFAULT_PROFILE, &settings(2.0)).unwrap();
+
+    let mut store = SettingsStore::new(other.flash().clone(), "app");
+    assert!(store.profiles().unwrap().is_empty());
+    let mut loaded = Settings::default();
+    assert!(matches!(
+        store.load(DEFAULT_PROFILE, &mut loaded),
+        Err(Error::NotFound)
+    ));
+
+    // Storing a profile of the same name leaves the other application's profile intact.
+    store.save(DEFAULT_PROFILE, &settings(1.0)).unwrap();
+    assert_eq!(store.flash().used_sectors(), 2);
+    SettingsStore::new(store.flash().clone(), "other")
+        .load(DEFAULT_PROFILE, &mut loaded)
+        .unwrap();
+    assert_eq!(loaded, settings(2.0));
+}
+
+#[test]
+fn replace() {
+    let mut store = SettingsStore::new(RamFlash::new(3), "app");
+    for gain in 0..10 {
+        store.save(DEFAULT_PROFILE, &settings(gain as f32)).unwrap();
+        assert_eq!(store.flash().used_sectors(), 1);
+    }
+
+    assert_eq!(store.profiles().unwrap(), [DEFAULT_PROFILE]);
+    let mut loaded = Settings::default();
+    store.load(DEFAULT_PROFILE, &mut loaded).unwrap();
+    assert_eq!(loaded, settings(9.0));
+}
+
+#[test]
+fn full() {
+    let mut store = SettingsStore::new(RamFlash::new(3), "app");
+    store.save("a", &settings(1.0)).unwrap();
+    store.save("b", &settings(2.0)).unwrap();
+
+    // The last free slot is kept to replace profiles in.
+    assert!(matches!(store.save("c", &settings(3.0)), Err(Error::Full)));
+    store.save("a", &settings(4.0)).unwrap();
+
+    let mut loaded = Settings::default();
+    store.load("a", &mut loaded).unwrap();
+    assert_eq!(loaded, settings(4.0));
+    assert_eq!(store.profiles().unwrap(), ["b", "a"]);
+}
+
+#[test]
+fn interrupted() {
+    let mut store = SettingsStore::new(RamFlash::new(3), "app");
+    store.save(DEFAULT_PROFILE, &settings(1.0)).unwrap();
+
+    // Fail to erase the previous copy after the new copy is written.
+    store.flash().erases = Some(1);
+    assert!(matches!(
+        store.save(DEFAULT_PROFILE, &settings(2.0)),
+        Err(Error::Flash(_))
+    ));
+    store.flash().erases = None;
+    assert_eq!(store.flash().used_sectors(), 2);
+    assert_eq!(store.profiles().unwrap(), [DEFAULT_PROFILE]);
+
+    // The newest copy is restored.
+    let mut loaded = Settings::default();
+    store.load(DEFAULT_PROFILE, &mut loaded).unwrap();
+    assert_eq!(loaded, settings(2.0));
+
+    // A corrupt newest copy falls back to the previous copy.
+    store.flash().data[SECTOR_SIZE + 64] ^= 0x01;
+    store.load(DEFAULT_PROFILE, &mut loaded).unwrap();
+    assert_eq!(loaded, settings(1.0));
+
+    // Storing again removes both previous copies.
+    store.save(DEFAULT_PROFILE, &settings(3.0)).unwrap();
+    assert_eq!(store.flash().used_sectors(), 1);
+}
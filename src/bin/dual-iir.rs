@@ -7,11 +7,19 @@
 //! ## Features
 //! * Two indpenendent channels
 //! * up to 800 kHz rate, timed sampling
+//! * Sample rate and batch size configurable at boot
 //! * Run-time filter configuration
+//! * Signal generators with frequency sweeps (chirps) and arbitrary waveforms
+//! * On-device network analyzer for transfer function measurements
 //! * Input/Output data streaming
 //! * Down to 2 µs latency
 //! * f32 IIR math
 //! * Generic biquad (second order) IIR filter
+//! * Run-time configurable cascade of up to four biquads per channel
+//! * Run-time configurable cross-coupling of the channels (input and output matrices)
+//! * Automatic lock acquisition by sweeping and relocking on loss of lock
+//! * Output saturation tracking and alarms
+//! * Optional automatic AFE gain ranging
 //! * Anti-windup
 //! * Derivative kick avoidance
 //!
@@ -22,14 +30,17 @@
 //! ## Telemetry
 //! Refer to [Telemetry] for information about telemetry reported by this application.
 //!
+//! If a channel output is continuously saturated for longer than the `saturation_alarm` setting, a
+//! [SaturationAlarm] is published to `<prefix>/alarm`.
+//!
 //! ## Livestreaming
-//! This application streams raw ADC and DAC data over UDP. Refer to
+//! This application streams raw ADC and DAC data together with the signal generator frequencies
+//! over UDP. The frequencies allow reconstructing frequency sweeps (chirps). Refer to
 //! [stabilizer::net::data_stream](../stabilizer/net/data_stream/index.html) for more information.
 #![deny(warnings)]
 #![no_std]
 #![no_main]
 
-use core::mem::MaybeUninit;
 use core::sync::atomic::{fence, Ordering};
 
 use fugit::ExtU64;
@@ -38,38 +49,54 @@ use mutex_trait::prelude::*;
 use idsp::iir;
 
 use stabilizer::{
+    dsp::{dual_iir, lock, network_analyzer, Input, Output, Process},
     hardware::{
         self,
-        adc::{Adc0Input, Adc1Input, AdcCode},
+        adc::{Adc0Input, Adc1Input},
         afe::Gain,
         dac::{Dac0Output, Dac1Output, DacCode},
         hal,
-        signal_generator::{self, SignalGenerator},
-        timers::SamplingTimer,
+        input_stamper::InputStamper,
+        setup::SamplingConfig,
+        signal_generator,
+        timers::{SamplingTimer, TimestampTimer},
         DigitalInput0, DigitalInput1, SystemTimer, Systick, AFE0, AFE1,
     },
     net::{
-        data_stream::{FrameGenerator, StreamFormat, StreamTarget},
+        data_stream::{
+            FrameGenerator, StreamFormat, StreamTarget, MAX_FANOUT_TARGETS,
+        },
+        decimation::Decimation,
         miniconf::Miniconf,
-        telemetry::{Telemetry, TelemetryBuffer},
+        telemetry::{
+            AnalyzerPoint, SaturationAlarm, Telemetry, TelemetryBuffer,
+        },
         NetworkState, NetworkUsers,
     },
+    settings::{self as store, SettingsStore},
 };
 
 const SCALE: f32 = i16::MAX as _;
 
-// The number of cascaded IIR biquads per channel. Select 1 or 2!
-const IIR_CASCADE_LENGTH: usize = 1;
+// The maximum number of cascaded IIR biquads per channel. The number of active biquads is
+// configured at run-time.
+const IIR_CASCADE_LENGTH: usize = 4;
 
-// The number of samples in each batch process
-const BATCH_SIZE: usize = 8;
+// The default sampling configuration. With 2^7 = 128 100MHz timer ticks between each sample,
+// there is 1.28uS per sample, corresponding to a sampling frequency of 781.25 KHz. There are 2^3 =
+// 8 samples in each batch.
+const SAMPLING: SamplingConfig = SamplingConfig {
+    sample_ticks_log2: 7,
+    batch_size_log2: 3,
+};
 
-// The logarithm of the number of 100MHz timer ticks between each sample. With a value of 2^7 =
-// 128, there is 1.28uS per sample, corresponding to a sampling frequency of 781.25 KHz.
-const SAMPLE_TICKS_LOG2: u8 = 7;
-const SAMPLE_TICKS: u32 = 1 << SAMPLE_TICKS_LOG2;
-const SAMPLE_PERIOD: f32 =
-    SAMPLE_TICKS as f32 * hardware::design_parameters::TIMER_PERIOD;
+// The stream formats supported by the application.
+const STREAM_FORMATS: [StreamFormat; 4] = [
+    StreamFormat::AdcDacData,
+    StreamFormat::AdcDacFrequency,
+    StreamFormat::IirState,
+    StreamFormat::SignalGenerator,
+];
 
 #[derive(Clone, Copy, Debug, Miniconf)]
 pub struct Settings {
@@ -85,19 +112,75 @@ pub struct Settings {
     #[miniconf(defer)]
     afe: [Gain; 2],
 
+    /// Enable automatic AFE gain ranging.
+    ///
+    /// # Path
+    /// `auto_range/<n>`
+    ///
+    /// * <n> specifies which channel to configure. <n> := [0, 1]
+    ///
+    /// # Value
+    /// "true" or "false"
+    ///
+    /// # Note
+    /// The gain is adjusted once per telemetry period based on the ADC peak level. The IIR input
+    /// is scaled so that the loop gain in volts is the same as at the gain configured in `afe/<n>`.
+    #[miniconf(defer)]
+    auto_range: [bool; 2],
+
     /// Configure the IIR filter parameters.
     ///
     /// # Path
     /// `iir_ch/<n>/<m>`
     ///
     /// * <n> specifies which channel to configure. <n> := [0, 1]
-    /// * <m> specifies which cascade to configure. <m> := [0, 1], depending on [IIR_CASCADE_LENGTH]
+    /// * <m> specifies which cascade to configure. <m> := [0, 3], see [IIR_CASCADE_LENGTH]
     ///
     /// # Value
     /// See [iir::IIR#miniconf]
     #[miniconf(defer)]
     iir_ch: miniconf::Array<[iir::IIR<f32>; IIR_CASCADE_LENGTH], 2>,
 
+    /// Configure the number of active IIR biquads per channel.
+    ///
+    /// # Path
+    /// `cascade_len/<n>`
+    ///
+    /// * <n> specifies which channel to configure. <n> := [0, 1]
+    ///
+    /// # Value
+    /// The number of active biquads (0-4). Only the first `cascade_len` biquads of `iir_ch/<n>` are
+    /// computed, all others are bypassed. Changing the number resets the filter state of the
+    /// channel.
+    #[miniconf(defer)]
+    cascade_len: [usize; 2],
+
+    /// Configure the input matrix mixing the ADC inputs into the IIR cascade inputs.
+    ///
+    /// # Path
+    /// `input_matrix/<n>`
+    ///
+    /// * <n> specifies which IIR cascade to configure. <n> := [0, 1]
+    ///
+    /// # Value
+    /// The gains `[g0, g1]` from ADC0 and ADC1 to the IIR cascade, e.g. `[1.0, -1.0]` to filter the
+    /// difference of the inputs.
+    #[miniconf(defer)]
+    input_matrix: [[f32; 2]; 2],
+
+    /// Configure the output matrix mixing the IIR cascade outputs into the DAC outputs.
+    ///
+    /// # Path
+    /// `output_matrix/<n>`
+    ///
+    /// * <n> specifies which DAC output to configure. <n> := [0, 1]
+    ///
+    /// # Value
+    /// The gains `[g0, g1]` from IIR cascade 0 and 1 to the DAC output. The output saturates at the
+    /// DAC range.
+    #[miniconf(defer)]
+    output_matrix: [[f32; 2]; 2],
+
     /// Specified true if DI1 should be used as a "hold" input.
     ///
     /// # Path
@@ -125,6 +208,16 @@ pub struct Settings {
     /// Any non-zero value less than 65536.
     telemetry_period: u16,
 
+    /// Specifies the time in seconds a channel output must be continuously saturated before an
+    /// alarm is published.
+    ///
+    /// # Path
+    /// `saturation_alarm`
+    ///
+    /// # Value
+    /// Any non-negative value. Zero disables the alarm.
+    saturation_alarm: f32,
+
     /// Specifies the target for data livestreaming.
     ///
     /// # Path
@@ -134,6 +227,45 @@ pub struct Settings {
     /// See [StreamTarget#miniconf]
     stream_target: StreamTarget,
 
+    /// Specifies additional UDP targets receiving the same data livestream.
+    ///
+    /// # Path
+    /// `stream_fanout/<n>`
+    ///
+    /// * <n> specifies which fan-out target to configure. <n> := [0, 1, 2]
+    ///
+    /// # Value
+    /// See [StreamTarget#miniconf]. Targets with an unspecified IP address are unused. The
+    /// fan-out targets also receive the stream if `stream_target` is unspecified.
+    #[miniconf(defer)]
+    stream_fanout: [StreamTarget; MAX_FANOUT_TARGETS],
+
+    /// Specifies the content of the data livestream.
+    ///
+    /// # Path
+    /// `stream_format`
+    ///
+    /// # Value
+    /// See [StreamFormat#miniconf]
+    ///
+    /// # Note
+    /// Supported formats are `AdcDacData`, `AdcDacFrequency`, `IirState`, and `SignalGenerator`.
+    stream_format: StreamFormat,
+
+    /// Specifies the decimation of the data livestream.
+    ///
+    /// # Path
+    /// `stream_decimation`
+    ///
+    /// # Value
+    /// See [Decimation#miniconf]
+    ///
+    /// # Note
+    /// The streamed data is averaged over `2^log2` batches and the decimation is recorded in the
+    /// stream frame header.
+    #[miniconf(defer)]
+    stream_decimation: Decimation,
+
     /// Specifies the config for signal generators to add on to DAC0/DAC1 outputs.
     ///
     /// # Path
@@ -143,8 +275,51 @@ pub struct Settings {
     ///
     /// # Value
     /// See [signal_generator::BasicConfig#miniconf]
+    ///
+    /// # Note
+    /// The waveform of the `Arbitrary` signal is uploaded using the `waveform/<n>/...` commands.
+    ///
+    /// A signal can be triggered by rising edges on DI0 or DI1. DI0 edges are timestamped and
+    /// trigger at the corresponding sample. DI1 is sampled once per batch.
     #[miniconf(defer)]
     signal_generator: miniconf::Array<signal_generator::BasicConfig, 2>,
+
+    /// Specifies the automatic lock acquisition of each channel.
+    ///
+    /// # Path
+    /// `relock/<n>`
+    ///
+    /// * <n> specifies which channel to configure. <n> := [0, 1]
+    ///
+    /// # Value
+    /// See [lock::BasicConfig#miniconf]
+    #[miniconf(defer)]
+    relock: miniconf::Array<lock::BasicConfig, 2>,
+
+    /// Specifies the sample rate and batch size.
+    ///
+    /// # Path
+    /// `sampling`
+    ///
+    /// # Value
+    /// See [SamplingConfig#miniconf]
+    ///
+    /// # Note
+    /// The sampling configuration is only applied at boot. Use the `settings/apply` command to
+    /// store it and restart.
+    #[miniconf(defer)]
+    sampling: SamplingConfig,
+
+    /// Specifies the network analyzer measurement. Measured points are published to
+    /// `<prefix>/analyzer`.
+    ///
+    /// # Path
+    /// `analyzer`
+    ///
+    /// # Value
+    /// See [network_analyzer::BasicConfig#miniconf]
+    #[miniconf(defer)]
+    analyzer: network_analyzer::BasicConfig,
 }
 
 impl Default for Settings {
@@ -152,6 +327,8 @@ impl Default for Settings {
         Self {
             // Analog frontend programmable gain amplifier gains (G1, G2, G5, G10)
             afe: [Gain::G1, Gain::G1],
+            // Fixed AFE gains.
+            auto_range: [false; 2],
             // IIR filter tap gains are an array `[b0, b1, b2, a1, a2]` such that the
             // new output is computed as `y0 = a1*y1 + a2*y2 + b0*x0 + b1*x1 + b2*x2`.
             // The array is `iir_state[channel-index][cascade-index][coeff-index]`.
@@ -159,6 +336,11 @@ impl Default for Settings {
             // representations, for example as described in https://arxiv.org/abs/1508.06319
             iir_ch: [[iir::IIR::new(1., -SCALE, SCALE); IIR_CASCADE_LENGTH]; 2]
                 .into(),
+            // A single active biquad per channel.
+            cascade_len: [1; 2],
+            // No cross-coupling: ADC0 -> IIR0 -> DAC0, ADC1 -> IIR1 -> DAC1
+            input_matrix: [[1., 0.], [0., 1.]],
+            output_matrix: [[1., 0.], [0., 1.]],
 
             // Permit the DI1 digital input to suppress filter output updates.
             allow_hold: false,
@@ -166,15 +348,121 @@ impl Default for Settings {
             force_hold: false,
             // The default telemetry period in seconds.
             telemetry_period: 10,
+            // No saturation alarm.
+            saturation_alarm: 0.,
 
             signal_generator: [signal_generator::BasicConfig::default(); 2]
                 .into(),
 
+            // Automatic locking disabled.
+            relock: [lock::BasicConfig::default(); 2].into(),
+
+            sampling: SAMPLING,
+
+            analyzer: network_analyzer::BasicConfig::default(),
+
             stream_target: StreamTarget::default(),
+            stream_fanout: [StreamTarget::default(); MAX_FANOUT_TARGETS],
+            stream_format: StreamFormat::AdcDacFrequency,
+            stream_decimation: Decimation::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// Get the signal processing configuration.
+    ///
+    /// # Note
+    /// Automatic locking of a channel is disabled if its configuration is invalid.
+    ///
+    /// # Args
+    /// * `gains` - The current AFE gains. The input matrix is scaled to compensate for any
+    ///   deviation from the configured gains.
+    /// * `sampling` - The active sampling configuration.
+    fn processing(
+        &self,
+        gains: [Gain; 2],
+        sampling: &SamplingConfig,
+    ) -> dual_iir::Config<IIR_CASCADE_LENGTH> {
+        let mut lock = [lock::Config::default(); 2];
+        for (i, (lock, config)) in
+            lock.iter_mut().zip(self.relock.iter()).enumerate()
+        {
+            match config.try_into_config(
+                sampling.sample_period(),
+                sampling.batch_size(),
+                DacCode::FULL_SCALE,
+            ) {
+                Ok(config) => *lock = config,
+                Err(err) => log::error!(
+                    "Failed to configure automatic locking of channel {}: {:?}",
+                    i,
+                    err
+                ),
+            }
+        }
+
+        let mut input_matrix = self.input_matrix;
+        for row in input_matrix.iter_mut() {
+            for (m, (afe, gain)) in
+                row.iter_mut().zip(self.afe.iter().zip(gains.iter()))
+            {
+                *m *= afe.as_multiplier() / gain.as_multiplier();
+            }
+        }
+
+        dual_iir::Config {
+            iir_ch: *self.iir_ch,
+            cascade_len: self.cascade_len,
+            input_matrix,
+            output_matrix: self.output_matrix,
+            allow_hold: self.allow_hold,
+            force_hold: self.force_hold,
+            lock,
+            saturation_alarm: (self.saturation_alarm / sampling.sample_period())
+                as u32,
         }
     }
 }
 
+/// Rising edge detection on the digital inputs to trigger the signal generators.
+pub struct TriggerInputs {
+    timestamper: InputStamper,
+    sampling: SamplingConfig,
+    di1: bool,
+}
+
+impl TriggerInputs {
+    /// Get the sample indices of rising edges since the previous batch.
+    ///
+    /// # Note
+    /// DI0 edges are captured by the timestamper and resolved to the sample of the batch. DI1 does
+    /// not support input capture. It is polled once per batch and a rising edge triggers at the
+    /// first sample of the batch. DI1 pulses shorter than a batch may be missed.
+    ///
+    /// # Args
+    /// * `di1` - The current state of DI1.
+    fn update(&mut self, di1: bool) -> [Option<usize>; 2] {
+        // Note: An over-capture indicates that earlier edges were dropped. Only the latest edge is
+        // relevant for triggering.
+        let di0 = self.timestamper.latest_timestamp().unwrap_or_else(|t| t);
+
+        // The timestamp timer and the sampling timer count at the same rate and are started
+        // together (see `start`), so the timestamp counts ticks since the first sample up to a
+        // constant offset of a few timer cycles. The timestamp timer wraps around at a multiple of
+        // the batch period, so the index within the batch is the sample index of the edge.
+        let di0 = di0.map(|timestamp| {
+            (timestamp >> self.sampling.sample_ticks_log2) as usize
+                & (self.sampling.batch_size() - 1)
+        });
+
+        let rising = di1 && !self.di1;
+        self.di1 = di1;
+
+        [di0, rising.then_some(0)]
+    }
+}
+
 #[rtic::app(device = stabilizer::hardware::hal::stm32, peripherals = true, dispatchers=[DCMI, JPEG, LTDC, SDMMC])]
 mod app {
     use super::*;
@@ -188,17 +476,22 @@ mod app {
 
         settings: Settings,
         telemetry: TelemetryBuffer,
-        signal_generator: [SignalGenerator; 2],
+        processor: dual_iir::DualIir<IIR_CASCADE_LENGTH>,
+        afes: (AFE0, AFE1),
+        #[lock_free]
+        sampling: SamplingConfig,
+        stream_format: StreamFormat,
+        stream_decimation: Decimation,
     }
 
     #[local]
     struct Local {
         sampling_timer: SamplingTimer,
+        timestamp_timer: TimestampTimer,
         digital_inputs: (DigitalInput0, DigitalInput1),
-        afes: (AFE0, AFE1),
+        triggers: TriggerInputs,
         adcs: (Adc0Input, Adc1Input),
         dacs: (Dac0Output, Dac1Output),
-        iir_state: [[iir::Vec5<f32>; IIR_CASCADE_LENGTH]; 2],
         generator: FrameGenerator,
         cpu_temp_sensor: stabilizer::hardware::cpu_temp_sensor::CpuTempSensor,
     }
@@ -208,56 +501,85 @@ mod app {
         let clock = SystemTimer::new(|| monotonics::now().ticks() as u32);
 
         // Configure the microcontroller
-        let (stabilizer, _pounder) = hardware::setup::setup(
-            c.core,
-            c.device,
-            clock,
-            BATCH_SIZE,
-            SAMPLE_TICKS,
-        );
+        let (stabilizer, _pounder) =
+            hardware::setup::setup(c.core, c.device, clock, |flash| {
+                // The sampling configuration is restored from the default profile.
+                let mut settings = Settings::default();
+                SettingsStore::new(flash, env!("CARGO_BIN_NAME"))
+                    .load(store::DEFAULT_PROFILE, &mut settings)
+                    .ok();
+
+                if settings.sampling.is_valid() {
+                    settings.sampling
+                } else {
+                    log::warn!("Invalid sampling: {:?}", settings.sampling);
+                    SAMPLING
+                }
+            });
+        let sampling = stabilizer.sampling;
 
         let mut network = NetworkUsers::new(
-            stabilizer.net.stack,
-            stabilizer.net.phy,
+            stabilizer.net,
             clock,
             env!("CARGO_BIN_NAME"),
-            stabilizer.net.mac_address,
-            option_env!("BROKER")
-                .unwrap_or("10.34.16.1")
-                .parse()
-                .unwrap(),
+            stabilizer.flash,
         );
 
-        let generator = network
-            .configure_streaming(StreamFormat::AdcDacData, BATCH_SIZE as _);
-
         let settings = Settings::default();
 
+        let generator = network.configure_streaming(
+            settings.stream_format,
+            sampling.batch_size() as _,
+            sampling.sample_period_ns(),
+        );
+
+        let mut processor = dual_iir::DualIir::new(
+            settings.processing(settings.afe, &sampling),
+            [
+                settings.signal_generator[0]
+                    .try_into_config(
+                        sampling.sample_period(),
+                        DacCode::FULL_SCALE,
+                    )
+                    .unwrap(),
+                settings.signal_generator[1]
+                    .try_into_config(
+                        sampling.sample_period(),
+                        DacCode::FULL_SCALE,
+                    )
+                    .unwrap(),
+            ],
+        );
+
+        // Play back any restored arbitrary waveforms.
+        for (channel, generator) in
+            processor.signal_generator.iter_mut().enumerate()
+        {
+            generator.set_waveform(network.waveform(channel));
+        }
+
         let shared = Shared {
             network,
             settings,
             telemetry: TelemetryBuffer::default(),
-            signal_generator: [
-                SignalGenerator::new(
-                    settings.signal_generator[0]
-                        .try_into_config(SAMPLE_PERIOD, DacCode::FULL_SCALE)
-                        .unwrap(),
-                ),
-                SignalGenerator::new(
-                    settings.signal_generator[1]
-                        .try_into_config(SAMPLE_PERIOD, DacCode::FULL_SCALE)
-                        .unwrap(),
-                ),
-            ],
+            processor,
+            afes: stabilizer.afes,
+            sampling,
+            stream_format: settings.stream_format,
+            stream_decimation: settings.stream_decimation,
         };
 
         let mut local = Local {
             sampling_timer: stabilizer.adc_dac_timer,
+            timestamp_timer: stabilizer.timestamp_timer,
             digital_inputs: stabilizer.digital_inputs,
-            afes: stabilizer.afes,
+            triggers: TriggerInputs {
+                timestamper: stabilizer.timestamper,
+                sampling,
+                di1: false,
+            },
             adcs: stabilizer.adcs,
             dacs: stabilizer.dacs,
-            iir_state: [[[0.; 5]; IIR_CASCADE_LENGTH]; 2],
             generator,
             cpu_temp_sensor: stabilizer.temperature_sensor,
         };
@@ -274,13 +596,21 @@ mod app {
         ethernet_link::spawn().unwrap();
         start::spawn_after(100.millis()).unwrap();
 
+        // Start capturing DI0 edges for signal generator triggers. Edges are only captured once
+        // the timestamp timer is started.
+        local.triggers.timestamper.start();
+
         (shared, local, init::Monotonics(stabilizer.systick))
     }
 
-    #[task(priority = 1, local=[sampling_timer])]
+    #[task(priority = 1, local=[sampling_timer, timestamp_timer])]
     fn start(c: start::Context) {
-        // Start sampling ADCs and DACs.
-        c.local.sampling_timer.start();
+        // Start sampling ADCs and DACs. The timestamp timer is started together with the sampling
+        // timer, so that DI0 timestamps count from the first sample.
+        cortex_m::interrupt::free(|_| {
+            c.local.sampling_timer.start();
+            c.local.timestamp_timer.start();
+        });
     }
 
     /// Main DSP processing routine.
@@ -299,93 +629,110 @@ mod app {
     ///
     /// Because the ADC and DAC operate at the same rate, these two constraints actually implement
     /// the same time bounds, meeting one also means the other is also met.
-    #[task(binds=DMA1_STR4, local=[digital_inputs, adcs, dacs, iir_state, generator], shared=[settings, signal_generator, telemetry], priority=3)]
+    ///
+    /// The hardware-independent processing itself is implemented by [dual_iir::DualIir].
+    #[task(binds=DMA1_STR4, local=[digital_inputs, triggers, adcs, dacs, generator], shared=[processor, telemetry, stream_format, stream_decimation], priority=3)]
     #[link_section = ".itcm.process"]
     fn process(c: process::Context) {
         let process::SharedResources {
-            settings,
+            processor,
             telemetry,
-            signal_generator,
+            stream_format,
+            stream_decimation,
         } = c.shared;
 
         let process::LocalResources {
             digital_inputs,
+            triggers,
             adcs: (adc0, adc1),
             dacs: (dac0, dac1),
-            iir_state,
             generator,
         } = c.local;
 
-        (settings, telemetry, signal_generator).lock(
-            |settings, telemetry, signal_generator| {
+        (processor, telemetry, stream_format, stream_decimation).lock(
+            |processor, telemetry, stream_format, stream_decimation| {
                 let digital_inputs =
                     [digital_inputs.0.is_high(), digital_inputs.1.is_high()];
                 telemetry.digital_inputs = digital_inputs;
-
-                let hold = settings.force_hold
-                    || (digital_inputs[1] && settings.allow_hold);
+                let triggers = triggers.update(digital_inputs[1]);
 
                 (adc0, adc1, dac0, dac1).lock(|adc0, adc1, dac0, dac1| {
-                    let adc_samples = [adc0, adc1];
-                    let dac_samples = [dac0, dac1];
+                    let input = Input {
+                        adcs: [adc0, adc1],
+                        digital_inputs,
+                        timestamp: None,
+                        triggers,
+                    };
+                    let mut output = Output { dacs: [dac0, dac1] };
 
                     // Preserve instruction and data ordering w.r.t. DMA flag access.
                     fence(Ordering::SeqCst);
 
-                    for channel in 0..adc_samples.len() {
-                        adc_samples[channel]
-                            .iter()
-                            .zip(dac_samples[channel].iter_mut())
-                            .zip(&mut signal_generator[channel])
-                            .map(|((ai, di), signal)| {
-                                let x = f32::from(*ai as i16);
-                                let y = settings.iir_ch[channel]
-                                    .iter()
-                                    .zip(iir_state[channel].iter_mut())
-                                    .fold(x, |yi, (ch, state)| {
-                                        ch.update(state, yi, hold)
-                                    });
-
-                                // Note(unsafe): The filter limits must ensure that the value is in range.
-                                // The truncation introduces 1/2 LSB distortion.
-                                let y: i16 = unsafe { y.to_int_unchecked() };
-
-                                let y = y.saturating_add(signal);
-
-                                // Convert to DAC code
-                                *di = DacCode::from(y).0;
-                            })
-                            .last();
+                    // The signal generator frequencies at the first sample of the batch.
+                    let frequency = [
+                        processor.signal_generator[0].frequency(),
+                        processor.signal_generator[1].frequency(),
+                    ];
+
+                    processor.process(&input, &mut output);
+
+                    if let Some(point) = processor.analyzer.take_result() {
+                        analyzer_point::spawn(point).ok();
                     }
 
                     // Stream the data.
-                    const N: usize = BATCH_SIZE * core::mem::size_of::<i16>()
-                        / core::mem::size_of::<MaybeUninit<u8>>();
-                    generator.add::<_, { N * 4 }>(|buf| {
-                        for (data, buf) in adc_samples
-                            .iter()
-                            .chain(dac_samples.iter())
-                            .zip(buf.chunks_exact_mut(N))
-                        {
-                            let data = unsafe {
-                                core::slice::from_raw_parts(
-                                    data.as_ptr() as *const MaybeUninit<u8>,
-                                    N,
+                    let n = input.adcs[0].len() * core::mem::size_of::<i16>();
+                    generator.set_format(*stream_format);
+                    generator.set_decimation(*stream_decimation);
+                    match *stream_format {
+                        StreamFormat::AdcDacData => {
+                            generator.add(n * 4, |buf| {
+                                stabilizer::dsp::stream_adc_dac(
+                                    &input, &output, buf,
                                 )
-                            };
-                            buf.copy_from_slice(data)
+                            })
                         }
-                    });
-                    // Update telemetry measurements.
-                    telemetry.adcs = [
-                        AdcCode(adc_samples[0][0]),
-                        AdcCode(adc_samples[1][0]),
-                    ];
+                        StreamFormat::AdcDacFrequency => {
+                            generator.add(n * 4 + 8, |buf| {
+                                stabilizer::dsp::stream_adc_dac_frequency(
+                                    &input, &output, frequency, buf,
+                                )
+                            })
+                        }
+                        StreamFormat::IirState => generator.add(n * 8, |buf| {
+                            stabilizer::dsp::stream_iir_state(
+                                processor.monitor(),
+                                buf,
+                            )
+                        }),
+                        StreamFormat::SignalGenerator => {
+                            generator.add(n * 2, |buf| {
+                                stabilizer::dsp::stream_signal(
+                                    processor.monitor(),
+                                    buf,
+                                )
+                            })
+                        }
+                        // Unsupported formats are rejected by `settings_update`.
+                        _ => {}
+                    }
 
-                    telemetry.dacs = [
-                        DacCode(dac_samples[0][0]),
-                        DacCode(dac_samples[1][0]),
-                    ];
+                    // Update telemetry measurements.
+                    telemetry.update(
+                        input.adcs,
+                        [&*output.dacs[0], &*output.dacs[1]],
+                    );
+
+                    telemetry.lock = Some(processor.lock_status());
+                    telemetry.saturation = Some(processor.saturation());
+
+                    for (channel, &alarm) in
+                        processor.saturation_alarm().iter().enumerate()
+                    {
+                        if alarm {
+                            saturation_alarm::spawn(channel).ok();
+                        }
+                    }
 
                     // Preserve instruction and data ordering w.r.t. DMA flag access.
                     fence(Ordering::SeqCst);
@@ -401,27 +748,74 @@ mod app {
                 NetworkState::SettingsChanged(_path) => {
                     settings_update::spawn().unwrap()
                 }
+                NetworkState::RestartRequested => {
+                    // Allow the command response to be transmitted before restarting.
+                    restart::spawn_after(1.secs()).ok();
+                }
+                NetworkState::WaveformChanged(channel) => {
+                    waveform_update::spawn(channel).unwrap()
+                }
                 NetworkState::Updated => {}
                 NetworkState::NoChange => cortex_m::asm::wfi(),
             }
         }
     }
 
-    #[task(priority = 1, local=[afes], shared=[network, settings, signal_generator])]
+    #[task(priority = 1, shared=[network, settings, processor, afes, sampling, stream_format, stream_decimation])]
     fn settings_update(mut c: settings_update::Context) {
-        let settings = c.shared.network.lock(|net| *net.miniconf.settings());
-        c.shared.settings.lock(|current| *current = settings);
+        let settings = c.shared.network.lock(|net| net.settings());
+        let previous = c
+            .shared
+            .settings
+            .lock(|current| core::mem::replace(current, settings));
+        let sampling = *c.shared.sampling;
+
+        if settings.sampling != sampling {
+            log::info!(
+                "Sampling configuration {:?} will be applied after restart",
+                settings.sampling
+            );
+        }
 
-        c.local.afes.0.set_gain(settings.afe[0]);
-        c.local.afes.1.set_gain(settings.afe[1]);
+        // Automatically ranged AFEs keep their current gain.
+        let gains = c.shared.afes.lock(|afes| {
+            if !settings.auto_range[0] {
+                afes.0.set_gain(settings.afe[0]);
+            }
+            if !settings.auto_range[1] {
+                afes.1.set_gain(settings.afe[1]);
+            }
+            [afes.0.get_gain(), afes.1.get_gain()]
+        });
 
-        // Update the signal generators
+        c.shared.processor.lock(|processor| {
+            processor.configure(settings.processing(gains, &sampling))
+        });
+
+        for (i, &len) in settings.cascade_len.iter().enumerate() {
+            if len > IIR_CASCADE_LENGTH {
+                log::warn!(
+                    "Limiting IIR cascade length of channel {} to {}",
+                    i,
+                    IIR_CASCADE_LENGTH
+                );
+            }
+        }
+
+        // The signal generators are only updated if their configuration changed, so that running
+        // sweeps continue.
         for (i, &config) in settings.signal_generator.iter().enumerate() {
-            match config.try_into_config(SAMPLE_PERIOD, DacCode::FULL_SCALE) {
+            if config == previous.signal_generator[i] {
+                continue;
+            }
+
+            match config
+                .try_into_config(sampling.sample_period(), DacCode::FULL_SCALE)
+            {
                 Ok(config) => {
-                    c.shared
-                        .signal_generator
-                        .lock(|generator| generator[i].update_waveform(config));
+                    c.shared.processor.lock(|processor| {
+                        processor.signal_generator[i].update_waveform(config)
+                    });
                 }
                 Err(err) => log::error!(
                     "Failed to update signal generation on DAC{}: {:?}",
@@ -431,31 +825,135 @@ mod app {
             }
         }
 
-        let target = settings.stream_target.into();
-        c.shared.network.lock(|net| net.direct_stream(target));
+        // The measurement only restarts if the network analyzer configuration changed.
+        if settings.analyzer != previous.analyzer {
+            match settings
+                .analyzer
+                .try_into_config(sampling.sample_period(), DacCode::FULL_SCALE)
+            {
+                Ok(config) => c
+                    .shared
+                    .processor
+                    .lock(|processor| processor.analyzer.configure(config)),
+                Err(err) => log::error!(
+                    "Failed to configure the network analyzer: {:?}",
+                    err
+                ),
+            }
+        }
+
+        if STREAM_FORMATS.contains(&settings.stream_format) {
+            c.shared
+                .stream_format
+                .lock(|format| *format = settings.stream_format);
+        } else {
+            log::error!(
+                "Unsupported stream format: {:?}",
+                settings.stream_format
+            );
+        }
+
+        if settings.stream_decimation.is_valid() {
+            c.shared
+                .stream_decimation
+                .lock(|decimation| *decimation = settings.stream_decimation);
+        } else {
+            log::error!(
+                "Unsupported stream decimation: {:?}",
+                settings.stream_decimation
+            );
+        }
+
+        c.shared.network.lock(|net| {
+            net.direct_stream(settings.stream_target, settings.stream_fanout)
+        });
     }
 
-    #[task(priority = 1, shared=[network, settings, telemetry], local=[cpu_temp_sensor])]
+    #[task(priority = 1, shared=[network, settings, telemetry, processor, afes, sampling], local=[cpu_temp_sensor])]
     fn telemetry(mut c: telemetry::Context) {
+        let sampling = *c.shared.sampling;
         let telemetry: TelemetryBuffer =
-            c.shared.telemetry.lock(|telemetry| *telemetry);
+            c.shared.telemetry.lock(|telemetry| telemetry.take());
 
-        let (gains, telemetry_period) = c
-            .shared
-            .settings
-            .lock(|settings| (settings.afe, settings.telemetry_period));
+        let settings = c.shared.settings.lock(|settings| *settings);
+
+        let (gains, ranged) = c.shared.afes.lock(|afes| {
+            let current = [afes.0.get_gain(), afes.1.get_gain()];
+            if settings.auto_range[0] {
+                afes.0.set_gain(
+                    current[0].auto_range(&telemetry.adc_statistics[0]),
+                );
+            }
+            if settings.auto_range[1] {
+                afes.1.set_gain(
+                    current[1].auto_range(&telemetry.adc_statistics[1]),
+                );
+            }
+            (current, [afes.0.get_gain(), afes.1.get_gain()])
+        });
+
+        if ranged != gains {
+            log::info!("AFE gains ranged to {:?}", ranged);
+            c.shared.processor.lock(|processor| {
+                processor.configure(settings.processing(ranged, &sampling))
+            });
+        }
 
         c.shared.network.lock(|net| {
             net.telemetry.publish(&telemetry.finalize(
                 gains[0],
                 gains[1],
                 c.local.cpu_temp_sensor.get_temperature().unwrap(),
+                net.profile().cloned(),
+                sampling.sample_period(),
+                net.stream_statistics(),
             ))
         });
 
         // Schedule the telemetry task in the future.
-        telemetry::Monotonic::spawn_after((telemetry_period as u64).secs())
-            .unwrap();
+        telemetry::Monotonic::spawn_after(
+            (settings.telemetry_period as u64).secs(),
+        )
+        .unwrap();
+    }
+
+    #[task(priority = 1, capacity = 2, shared=[network, settings])]
+    fn saturation_alarm(mut c: saturation_alarm::Context, channel: usize) {
+        let rail_time =
+            c.shared.settings.lock(|settings| settings.saturation_alarm);
+        log::warn!("Channel {} saturated for {} s", channel, rail_time);
+
+        c.shared.network.lock(|net| {
+            net.telemetry.alarm(&SaturationAlarm { channel, rail_time })
+        });
+    }
+
+    #[task(priority = 1, capacity = 2, shared=[network, afes, sampling])]
+    fn analyzer_point(
+        mut c: analyzer_point::Context,
+        point: network_analyzer::Point,
+    ) {
+        let gains = c
+            .shared
+            .afes
+            .lock(|afes| [afes.0.get_gain(), afes.1.get_gain()]);
+        let point =
+            AnalyzerPoint::new(point, c.shared.sampling.sample_period(), gains);
+
+        c.shared.network.lock(|net| net.telemetry.analyzer(&point));
+    }
+
+    #[task(priority = 1, capacity = 2, shared=[network, processor])]
+    fn waveform_update(c: waveform_update::Context, channel: usize) {
+        (c.shared.network, c.shared.processor).lock(|net, processor| {
+            processor.signal_generator[channel]
+                .set_waveform(net.waveform(channel))
+        });
+    }
+
+    #[task(priority = 1)]
+    fn restart(_: restart::Context) {
+        cortex_m::peripheral::SCB::sys_reset();
     }
 
     #[task(priority = 1, shared=[network])]
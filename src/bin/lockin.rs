@@ -5,6 +5,7 @@
 //!
 //! ## Features
 //! * Up to 800 kHz sampling
+//! * Sample rate and batch size configurable at boot
 //! * Up to 400 kHz modulation frequency
 //! * Supports internal and external reference sources:
 //!     1. Internal: Generate reference internally and output on one of the channel outputs
@@ -28,74 +29,53 @@
 #![no_std]
 #![no_main]
 
-use core::{
-    convert::TryFrom,
-    mem::MaybeUninit,
-    sync::atomic::{fence, Ordering},
-};
+use core::sync::atomic::{fence, Ordering};
 
 use fugit::ExtU64;
 use mutex_trait::prelude::*;
 
-use idsp::{Accu, Complex, ComplexExt, Lockin, RPLL};
-
 use stabilizer::{
+    dsp::{
+        lockin::{self, Conf, LockinMode},
+        Input, Output, Process,
+    },
     hardware::{
         self,
-        adc::{Adc0Input, Adc1Input, AdcCode},
+        adc::{Adc0Input, Adc1Input},
         afe::Gain,
-        dac::{Dac0Output, Dac1Output, DacCode},
+        dac::{Dac0Output, Dac1Output},
         hal,
         input_stamper::InputStamper,
-        signal_generator,
+        setup::SamplingConfig,
         timers::SamplingTimer,
         DigitalInput0, DigitalInput1, SystemTimer, Systick, AFE0, AFE1,
     },
     net::{
-        data_stream::{FrameGenerator, StreamFormat, StreamTarget},
+        data_stream::{
+            FrameGenerator, StreamFormat, StreamTarget, MAX_FANOUT_TARGETS,
+        },
+        decimation::Decimation,
         miniconf::Miniconf,
-        serde::{Deserialize, Serialize},
         telemetry::{Telemetry, TelemetryBuffer},
         NetworkState, NetworkUsers,
     },
+    settings::{self as store, SettingsStore},
 };
 
-// The logarithm of the number of samples in each batch process. This corresponds with 2^3 samples
-// per batch = 8 samples
-const BATCH_SIZE_LOG2: u32 = 3;
-const BATCH_SIZE: usize = 1 << BATCH_SIZE_LOG2;
-
-// The logarithm of the number of 100MHz timer ticks between each sample. This corresponds with a
-// sampling period of 2^7 = 128 ticks. At 100MHz, 10ns per tick, this corresponds to a sampling
-// period of 1.28 uS or 781.25 KHz.
-const SAMPLE_TICKS_LOG2: u32 = 7;
-const SAMPLE_TICKS: u32 = 1 << SAMPLE_TICKS_LOG2;
-
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
-enum Conf {
-    /// Output the lockin magnitude.
-    Magnitude,
-    /// Output the phase of the lockin
-    Phase,
-    /// Output the lockin reference frequency as a sinusoid
-    ReferenceFrequency,
-    /// Output the logarithmic power of the lockin
-    LogPower,
-    /// Output the in-phase component of the lockin signal.
-    InPhase,
-    /// Output the quadrature component of the lockin signal.
-    Quadrature,
-    /// Output the lockin internal modulation frequency as a sinusoid
-    Modulation,
-}
+// The default sampling configuration. This corresponds with a sampling period of 2^7 = 128 ticks.
+// At 100MHz, 10ns per tick, this corresponds to a sampling period of 1.28 uS or 781.25 KHz. There
+// are 2^3 = 8 samples per batch.
+const SAMPLING: SamplingConfig = SamplingConfig {
+    sample_ticks_log2: 7,
+    batch_size_log2: 3,
+};
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq)]
-enum LockinMode {
-    /// Utilize an internally generated reference for demodulation
-    Internal,
-    /// Utilize an external modulation signal supplied to DI0
-    External,
-}
+// The stream formats supported by the application.
+const STREAM_FORMATS: [StreamFormat; 3] = [
+    StreamFormat::AdcDacData,
+    StreamFormat::LockinIq,
+    StreamFormat::PhaseFrequency,
+];
 
 #[derive(Copy, Clone, Debug, Miniconf)]
 pub struct Settings {
@@ -188,6 +168,59 @@ pub struct Settings {
     /// # Value
     /// See [StreamTarget#miniconf]
     stream_target: StreamTarget,
+
+    /// Specifies additional UDP targets receiving the same data livestream.
+    ///
+    /// # Path
+    /// `stream_fanout/<n>`
+    ///
+    /// * <n> specifies which fan-out target to configure. <n> := [0, 1, 2]
+    ///
+    /// # Value
+    /// See [StreamTarget#miniconf]. Targets with an unspecified IP address are unused. The
+    /// fan-out targets also receive the stream if `stream_target` is unspecified.
+    #[miniconf(defer)]
+    stream_fanout: [StreamTarget; MAX_FANOUT_TARGETS],
+
+    /// Specifies the content of the data livestream.
+    ///
+    /// # Path
+    /// `stream_format`
+    ///
+    /// # Value
+    /// See [StreamFormat#miniconf]
+    ///
+    /// # Note
+    /// Supported formats are `AdcDacData`, `LockinIq`, and `PhaseFrequency`.
+    stream_format: StreamFormat,
+
+    /// Specifies the decimation of the data livestream.
+    ///
+    /// # Path
+    /// `stream_decimation`
+    ///
+    /// # Value
+    /// See [Decimation#miniconf]
+    ///
+    /// # Note
+    /// The streamed data is averaged over `2^log2` batches and the decimation is recorded in the
+    /// stream frame header.
+    #[miniconf(defer)]
+    stream_decimation: Decimation,
+
+    /// Specifies the sample rate and batch size.
+    ///
+    /// # Path
+    /// `sampling`
+    ///
+    /// # Value
+    /// See [SamplingConfig#miniconf]
+    ///
+    /// # Note
+    /// The sampling configuration is only applied at boot. Use the `settings/apply` command to
+    /// store it and restart. The lockin requires at least two samples per batch.
+    #[miniconf(defer)]
+    sampling: SamplingConfig,
 }
 
 impl Default for Settings {
@@ -208,6 +241,25 @@ impl Default for Settings {
             telemetry_period: 10,
 
             stream_target: StreamTarget::default(),
+            stream_fanout: [StreamTarget::default(); MAX_FANOUT_TARGETS],
+            stream_format: StreamFormat::AdcDacData,
+            stream_decimation: Decimation::default(),
+
+            sampling: SAMPLING,
+        }
+    }
+}
+
+impl Settings {
+    /// Get the signal processing configuration.
+    fn processing(&self) -> lockin::Config {
+        lockin::Config {
+            lockin_mode: self.lockin_mode,
+            pll_tc: self.pll_tc,
+            lockin_tc: self.lockin_tc,
+            lockin_harmonic: self.lockin_harmonic,
+            lockin_phase: self.lockin_phase,
+            output_conf: self.output_conf,
         }
     }
 }
@@ -224,6 +276,11 @@ mod app {
         network: NetworkUsers<Settings, Telemetry>,
         settings: Settings,
         telemetry: TelemetryBuffer,
+        processor: lockin::Lockin,
+        #[lock_free]
+        sampling: SamplingConfig,
+        stream_format: StreamFormat,
+        stream_decimation: Decimation,
     }
 
     #[local]
@@ -234,9 +291,6 @@ mod app {
         afes: (AFE0, AFE1),
         adcs: (Adc0Input, Adc1Input),
         dacs: (Dac0Output, Dac1Output),
-        pll: RPLL,
-        lockin: Lockin<4>,
-        signal_generator: signal_generator::SignalGenerator,
         generator: FrameGenerator,
         cpu_temp_sensor: stabilizer::hardware::cpu_temp_sensor::CpuTempSensor,
     }
@@ -246,42 +300,53 @@ mod app {
         let clock = SystemTimer::new(|| monotonics::now().ticks() as u32);
 
         // Configure the microcontroller
-        let (mut stabilizer, _pounder) = hardware::setup::setup(
-            c.core,
-            c.device,
-            clock,
-            BATCH_SIZE,
-            SAMPLE_TICKS,
-        );
+        let (mut stabilizer, _pounder) =
+            hardware::setup::setup(c.core, c.device, clock, |flash| {
+                // The sampling configuration is restored from the default profile.
+                let mut settings = Settings::default();
+                SettingsStore::new(flash, env!("CARGO_BIN_NAME"))
+                    .load(store::DEFAULT_PROFILE, &mut settings)
+                    .ok();
+
+                // The internal reference requires at least two samples per batch.
+                if settings.sampling.is_valid()
+                    && settings.sampling.batch_size_log2 >= 1
+                {
+                    settings.sampling
+                } else {
+                    log::warn!("Invalid sampling: {:?}", settings.sampling);
+                    SAMPLING
+                }
+            });
+        let sampling = stabilizer.sampling;
 
         let mut network = NetworkUsers::new(
-            stabilizer.net.stack,
-            stabilizer.net.phy,
+            stabilizer.net,
             clock,
             env!("CARGO_BIN_NAME"),
-            stabilizer.net.mac_address,
-            option_env!("BROKER")
-                .unwrap_or("10.34.16.1")
-                .parse()
-                .unwrap(),
+            stabilizer.flash,
         );
 
-        let generator = network
-            .configure_streaming(StreamFormat::AdcDacData, BATCH_SIZE as _);
+        let settings = Settings::default();
+
+        let generator = network.configure_streaming(
+            settings.stream_format,
+            sampling.batch_size() as _,
+            sampling.sample_period_ns(),
+        );
 
         let shared = Shared {
             network,
             telemetry: TelemetryBuffer::default(),
-            settings: Settings::default(),
-        };
-
-        let signal_config = signal_generator::Config {
-            // Same frequency as batch size.
-            phase_increment: [1 << (32 - BATCH_SIZE_LOG2); 2],
-            // 1V Amplitude
-            amplitude: DacCode::try_from(1.0).unwrap().into(),
-            signal: signal_generator::Signal::Cosine,
-            phase_offset: 0,
+            settings,
+            processor: lockin::Lockin::new(
+                settings.processing(),
+                sampling.sample_ticks_log2 as _,
+                sampling.batch_size_log2 as _,
+            ),
+            sampling,
+            stream_format: settings.stream_format,
+            stream_decimation: settings.stream_decimation,
         };
 
         let mut local = Local {
@@ -291,13 +356,6 @@ mod app {
             adcs: stabilizer.adcs,
             dacs: stabilizer.dacs,
             timestamper: stabilizer.timestamper,
-
-            pll: RPLL::new(SAMPLE_TICKS_LOG2 + BATCH_SIZE_LOG2),
-            lockin: Lockin::default(),
-            signal_generator: signal_generator::SignalGenerator::new(
-                signal_config,
-            ),
-
             generator,
             cpu_temp_sensor: stabilizer.temperature_sensor,
         };
@@ -333,125 +391,85 @@ mod app {
     ///
     /// See `dual-iir` for general notes on processing time and timing.
     ///
-    /// This is an implementation of a externally (DI0) referenced PLL lockin on the ADC0 signal.
-    /// It outputs either I/Q or power/phase on DAC0/DAC1. Data is normalized to full scale.
-    /// PLL bandwidth, filter bandwidth, slope, and x/y or power/phase post-filters are available.
-    #[task(binds=DMA1_STR4, shared=[settings, telemetry], local=[adcs, dacs, lockin, timestamper, pll, generator, signal_generator], priority=3)]
+    /// The hardware-independent processing itself is implemented by [lockin::Lockin].
+    #[task(binds=DMA1_STR4, shared=[processor, telemetry, stream_format, stream_decimation], local=[adcs, dacs, timestamper, generator], priority=3)]
     #[link_section = ".itcm.process"]
     fn process(c: process::Context) {
         let process::SharedResources {
-            settings,
+            processor,
             telemetry,
+            stream_format,
+            stream_decimation,
         } = c.shared;
 
         let process::LocalResources {
             timestamper,
             adcs: (adc0, adc1),
             dacs: (dac0, dac1),
-            pll,
-            lockin,
-            signal_generator,
             generator,
         } = c.local;
 
-        (settings, telemetry).lock(|settings, telemetry| {
-            let (reference_phase, reference_frequency) =
-                match settings.lockin_mode {
-                    LockinMode::External => {
-                        let timestamp =
-                            timestamper.latest_timestamp().unwrap_or(None); // Ignore data from timer capture overflows.
-                        let (pll_phase, pll_frequency) = pll.update(
-                            timestamp.map(|t| t as i32),
-                            settings.pll_tc[0],
-                            settings.pll_tc[1],
-                        );
-                        (pll_phase, (pll_frequency >> BATCH_SIZE_LOG2) as i32)
-                    }
-                    LockinMode::Internal => {
-                        // Reference phase and frequency are known.
-                        (1i32 << 30, 1i32 << (32 - BATCH_SIZE_LOG2))
-                    }
-                };
-
-            let sample_frequency =
-                reference_frequency.wrapping_mul(settings.lockin_harmonic);
-            let sample_phase = settings.lockin_phase.wrapping_add(
-                reference_phase.wrapping_mul(settings.lockin_harmonic),
-            );
-
-            (adc0, adc1, dac0, dac1).lock(|adc0, adc1, dac0, dac1| {
-                let adc_samples = [adc0, adc1];
-                let mut dac_samples = [dac0, dac1];
-
-                // Preserve instruction and data ordering w.r.t. DMA flag access.
-                fence(Ordering::SeqCst);
-
-                let output: Complex<i32> = adc_samples[0]
-                    .iter()
-                    // Zip in the LO phase.
-                    .zip(Accu::new(sample_phase, sample_frequency))
-                    // Convert to signed, MSB align the ADC sample, update the Lockin (demodulate, filter)
-                    .map(|(&sample, phase)| {
-                        let s = (sample as i16 as i32) << 16;
-                        lockin.update(s, phase, settings.lockin_tc)
-                    })
-                    // Decimate
-                    .last()
-                    .unwrap()
-                    * 2; // Full scale assuming the 2f component is gone.
-
-                // Convert to DAC data.
-                for (channel, samples) in dac_samples.iter_mut().enumerate() {
-                    for sample in samples.iter_mut() {
-                        let value = match settings.output_conf[channel] {
-                            Conf::Magnitude => output.abs_sqr() as i32 >> 16,
-                            Conf::Phase => output.arg() >> 16,
-                            Conf::LogPower => output.log2() << 8,
-                            Conf::ReferenceFrequency => {
-                                reference_frequency >> 16
-                            }
-                            Conf::InPhase => output.re >> 16,
-                            Conf::Quadrature => output.im >> 16,
-
-                            Conf::Modulation => {
-                                signal_generator.next().unwrap() as i32
-                            }
-                        };
-
-                        *sample = DacCode::from(value as i16).0;
-                    }
-                }
-
-                // Stream the data.
-                const N: usize = BATCH_SIZE * core::mem::size_of::<i16>()
-                    / core::mem::size_of::<MaybeUninit<u8>>();
-                generator.add::<_, { N * 4 }>(|buf| {
-                    for (data, buf) in adc_samples
-                        .iter()
-                        .chain(dac_samples.iter())
-                        .zip(buf.chunks_exact_mut(N))
-                    {
-                        let data = unsafe {
-                            core::slice::from_raw_parts(
-                                data.as_ptr() as *const MaybeUninit<u8>,
-                                N,
+        (processor, telemetry, stream_format, stream_decimation).lock(
+            |processor, telemetry, stream_format, stream_decimation| {
+                // Ignore data from timer capture overflows.
+                let timestamp = timestamper.latest_timestamp().unwrap_or(None);
+
+                (adc0, adc1, dac0, dac1).lock(|adc0, adc1, dac0, dac1| {
+                    let input = Input {
+                        adcs: [adc0, adc1],
+                        // The digital inputs are not used for processing.
+                        digital_inputs: [false; 2],
+                        timestamp,
+                        triggers: [None; 2],
+                    };
+                    let mut output = Output { dacs: [dac0, dac1] };
+
+                    // Preserve instruction and data ordering w.r.t. DMA flag access.
+                    fence(Ordering::SeqCst);
+
+                    processor.process(&input, &mut output);
+
+                    // Stream the data.
+                    let n = input.adcs[0].len() * core::mem::size_of::<i16>();
+                    generator.set_format(*stream_format);
+                    generator.set_decimation(*stream_decimation);
+                    match *stream_format {
+                        StreamFormat::AdcDacData => {
+                            generator.add(n * 4, |buf| {
+                                stabilizer::dsp::stream_adc_dac(
+                                    &input, &output, buf,
+                                )
+                            })
+                        }
+                        StreamFormat::LockinIq => generator.add(8, |buf| {
+                            stabilizer::dsp::stream_lockin_iq(
+                                processor.monitor(),
+                                buf,
                             )
-                        };
-                        buf.copy_from_slice(data)
+                        }),
+                        StreamFormat::PhaseFrequency => {
+                            generator.add(8, |buf| {
+                                stabilizer::dsp::stream_phase_frequency(
+                                    processor.monitor(),
+                                    buf,
+                                )
+                            })
+                        }
+                        // Unsupported formats are rejected by `settings_update`.
+                        _ => {}
                     }
-                });
-
-                // Update telemetry measurements.
-                telemetry.adcs =
-                    [AdcCode(adc_samples[0][0]), AdcCode(adc_samples[1][0])];
 
-                telemetry.dacs =
-                    [DacCode(dac_samples[0][0]), DacCode(dac_samples[1][0])];
+                    // Update telemetry measurements.
+                    telemetry.update(
+                        input.adcs,
+                        [&*output.dacs[0], &*output.dacs[1]],
+                    );
 
-                // Preserve instruction and data ordering w.r.t. DMA flag access.
-                fence(Ordering::SeqCst);
-            });
-        });
+                    // Preserve instruction and data ordering w.r.t. DMA flag access.
+                    fence(Ordering::SeqCst);
+                });
+            },
+        );
     }
 
     #[idle(shared=[network])]
@@ -461,28 +479,67 @@ mod app {
                 NetworkState::SettingsChanged(_path) => {
                     settings_update::spawn().unwrap()
                 }
+                NetworkState::RestartRequested => {
+                    // Allow the command response to be transmitted before restarting.
+                    restart::spawn_after(1.secs()).ok();
+                }
+                // The lockin does not use signal generators.
+                NetworkState::WaveformChanged(_) => {}
                 NetworkState::Updated => {}
                 NetworkState::NoChange => cortex_m::asm::wfi(),
             }
         }
     }
 
-    #[task(priority = 1, local=[afes], shared=[network, settings])]
+    #[task(priority = 1, local=[afes], shared=[network, settings, processor, sampling, stream_format, stream_decimation])]
     fn settings_update(mut c: settings_update::Context) {
-        let settings = c.shared.network.lock(|net| *net.miniconf.settings());
+        let settings = c.shared.network.lock(|net| net.settings());
         c.shared.settings.lock(|current| *current = settings);
 
+        if settings.sampling != *c.shared.sampling {
+            log::info!(
+                "Sampling configuration {:?} will be applied after restart",
+                settings.sampling
+            );
+        }
+        c.shared
+            .processor
+            .lock(|processor| processor.config = settings.processing());
+
         c.local.afes.0.set_gain(settings.afe[0]);
         c.local.afes.1.set_gain(settings.afe[1]);
 
-        let target = settings.stream_target.into();
-        c.shared.network.lock(|net| net.direct_stream(target));
+        if STREAM_FORMATS.contains(&settings.stream_format) {
+            c.shared
+                .stream_format
+                .lock(|format| *format = settings.stream_format);
+        } else {
+            log::error!(
+                "Unsupported stream format: {:?}",
+                settings.stream_format
+            );
+        }
+
+        if settings.stream_decimation.is_valid() {
+            c.shared
+                .stream_decimation
+                .lock(|decimation| *decimation = settings.stream_decimation);
+        } else {
+            log::error!(
+                "Unsupported stream decimation: {:?}",
+                settings.stream_decimation
+            );
+        }
+
+        c.shared.network.lock(|net| {
+            net.direct_stream(settings.stream_target, settings.stream_fanout)
+        });
     }
 
-    #[task(priority = 1, local=[digital_inputs, cpu_temp_sensor], shared=[network, settings, telemetry])]
+    #[task(priority = 1, local=[digital_inputs, cpu_temp_sensor], shared=[network, settings, telemetry, sampling])]
     fn telemetry(mut c: telemetry::Context) {
         let mut telemetry: TelemetryBuffer =
-            c.shared.telemetry.lock(|telemetry| *telemetry);
+            c.shared.telemetry.lock(|telemetry| telemetry.take());
 
         telemetry.digital_inputs = [
             c.local.digital_inputs.0.is_high(),
@@ -499,6 +556,9 @@ mod app {
                 gains[0],
                 gains[1],
                 c.local.cpu_temp_sensor.get_temperature().unwrap(),
+                net.profile().cloned(),
+                c.shared.sampling.sample_period(),
+                net.stream_statistics(),
             ))
         });
 
@@ -507,6 +567,11 @@ mod app {
             .unwrap();
     }
 
+    #[task(priority = 1)]
+    fn restart(_: restart::Context) {
+        cortex_m::peripheral::SCB::sys_reset();
+    }
+
     #[task(priority = 1, shared=[network])]
     fn ethernet_link(mut c: ethernet_link::Context) {
         c.shared.network.lock(|net| net.processor.handle_link());
@@ -3,7 +3,19 @@ use serde::{Deserialize, Serialize};
 use core::convert::TryFrom;
 use num_enum::TryFromPrimitive;
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize, TryFromPrimitive)]
+use crate::dsp::statistics::Statistics;
+
+// Automatic gain ranging decreases the gain if the input peak level exceeds this fraction of the ADC
+// full scale.
+const RANGE_HIGH: f32 = 0.9;
+
+// Automatic gain ranging increases the gain if the input peak level at the increased gain would
+// remain below this fraction of the ADC full scale. The gap to `RANGE_HIGH` provides hysteresis.
+const RANGE_LOW: f32 = 0.4;
+
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, TryFromPrimitive,
+)]
 #[repr(u8)]
 pub enum Gain {
     G1 = 0b00,
@@ -28,6 +40,62 @@ impl Gain {
             Gain::G10 => 10.0,
         }
     }
+
+    /// Get the next larger gain, if any.
+    pub fn increased(self) -> Option<Self> {
+        match self {
+            Gain::G1 => Some(Gain::G2),
+            Gain::G2 => Some(Gain::G5),
+            Gain::G5 => Some(Gain::G10),
+            Gain::G10 => None,
+        }
+    }
+
+    /// Get the next smaller gain, if any.
+    pub fn decreased(self) -> Option<Self> {
+        match self {
+            Gain::G1 => None,
+            Gain::G2 => Some(Gain::G1),
+            Gain::G5 => Some(Gain::G2),
+            Gain::G10 => Some(Gain::G5),
+        }
+    }
+
+    /// Select the gain for the measured input level.
+    ///
+    /// # Note
+    /// The gain is changed by at most one step. It is decreased if the input is at or close to
+    /// full scale and increased if the input would remain well below full scale at the increased
+    /// gain.
+    ///
+    /// # Args
+    /// * `statistics` - The ADC code statistics measured at this gain.
+    ///
+    /// # Returns
+    /// The gain to use.
+    pub fn auto_range(self, statistics: &Statistics) -> Self {
+        let peak = match (statistics.min(), statistics.max()) {
+            (Some(min), Some(max)) => {
+                (min as i32).abs().max((max as i32).abs()) as f32
+                    / -(i16::MIN as f32)
+            }
+            _ => return self,
+        };
+
+        if statistics.full_scale() > 0 || peak >= RANGE_HIGH {
+            self.decreased().unwrap_or(self)
+        } else {
+            match self.increased() {
+                Some(gain)
+                    if peak * gain.as_multiplier() / self.as_multiplier()
+                        < RANGE_LOW =>
+                {
+                    gain
+                }
+                _ => self,
+            }
+        }
+    }
 }
 
 impl<A0, A1> ProgrammableGainAmplifier<A0, A1>
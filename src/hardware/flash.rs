@@ -0,0 +1,226 @@
+//! Stabilizer flash storage
+//!
+//! # Design
+//! The STM32H743 provides two 1 MiB flash banks. The firmware image is linked into the first bank
+//! only (see `memory.x`), so the second bank is free for non-volatile data. The last six 128 KiB
+//! sectors of the second bank are reserved for storing settings. The first two sectors are reserved
+//! for storing arbitrary waveforms.
+//!
+//! The reserved sectors are exposed through the `embedded-storage` NOR flash traits with offsets
+//! relative to the start of the first reserved sector of each region. This allows storage logic on
+//! top of it to be generic over the flash implementation.
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+use stm32h7xx_hal::flash::{self, LockedFlashBank};
+
+// The size of each flash sector in bytes.
+const SECTOR_SIZE: usize = 128 * 1024;
+
+// The sectors within the second flash bank that are reserved for settings storage.
+const SETTINGS_SECTORS: core::ops::Range<usize> = 2..8;
+
+// The sectors within the second flash bank that are reserved for waveform storage.
+const WAVEFORM_SECTORS: core::ops::Range<usize> = 0..2;
+
+/// Errors that may occur when accessing the reserved flash.
+#[derive(Copy, Clone, Debug)]
+pub enum Error {
+    /// The requested access is out of the bounds of the reserved sectors.
+    OutOfBounds,
+    /// The flash controller reported an error.
+    Flash(flash::Error),
+}
+
+impl NorFlashError for Error {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Error::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            Error::Flash(error) => error.kind(),
+        }
+    }
+}
+
+/// The flash sectors reserved for settings storage.
+pub struct Flash {
+    bank: LockedFlashBank,
+}
+
+impl Flash {
+    /// Construct the settings flash.
+    ///
+    /// # Args
+    /// * `bank` - The second flash bank of the device.
+    pub fn new(bank: LockedFlashBank) -> Self {
+        assert!(bank.len() >= SETTINGS_SECTORS.end * SECTOR_SIZE);
+        Self { bank }
+    }
+
+    /// Get the flash sectors reserved for waveform storage.
+    pub fn waveforms(&mut self) -> WaveformFlash<'_> {
+        WaveformFlash {
+            bank: &mut self.bank,
+        }
+    }
+}
+
+/// The flash sectors reserved for waveform storage.
+pub struct WaveformFlash<'a> {
+    bank: &'a mut LockedFlashBank,
+}
+
+// The size of a region of reserved sectors in bytes.
+fn capacity(sectors: &core::ops::Range<usize>) -> usize {
+    (sectors.end - sectors.start) * SECTOR_SIZE
+}
+
+// Check that an access is within a region and get its offset within the flash bank.
+fn check(
+    sectors: &core::ops::Range<usize>,
+    offset: u32,
+    length: usize,
+) -> Result<usize, Error> {
+    let offset = offset as usize;
+    if offset + length > capacity(sectors) {
+        return Err(Error::OutOfBounds);
+    }
+
+    Ok(sectors.start * SECTOR_SIZE + offset)
+}
+
+fn read(
+    bank: &LockedFlashBank,
+    sectors: &core::ops::Range<usize>,
+    offset: u32,
+    bytes: &mut [u8],
+) -> Result<(), Error> {
+    let offset = check(sectors, offset, bytes.len())?;
+    bytes.copy_from_slice(&bank.read_all()[offset..offset + bytes.len()]);
+    Ok(())
+}
+
+fn erase(
+    bank: &mut LockedFlashBank,
+    sectors: &core::ops::Range<usize>,
+    from: u32,
+    to: u32,
+) -> Result<(), Error> {
+    let (from, to) = (from as usize, to as usize);
+
+    // Only complete sectors can be erased.
+    if from % SECTOR_SIZE != 0
+        || to % SECTOR_SIZE != 0
+        || to > capacity(sectors)
+    {
+        return Err(Error::OutOfBounds);
+    }
+
+    let mut bank = bank.unlocked();
+    for sector in from / SECTOR_SIZE..to / SECTOR_SIZE {
+        bank.erase_sector((sectors.start + sector) as u8)
+            .map_err(Error::Flash)?;
+    }
+
+    Ok(())
+}
+
+fn write(
+    bank: &mut LockedFlashBank,
+    sectors: &core::ops::Range<usize>,
+    offset: u32,
+    bytes: &[u8],
+) -> Result<(), Error> {
+    let offset = check(sectors, offset, bytes.len())?;
+    bank.unlocked()
+        .program(offset, bytes.iter())
+        .map_err(Error::Flash)
+}
+
+impl ErrorType for Flash {
+    type Error = Error;
+}
+
+impl ReadNorFlash for Flash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+        read(&self.bank, &SETTINGS_SECTORS, offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        capacity(&SETTINGS_SECTORS)
+    }
+}
+
+impl NorFlash for Flash {
+    const WRITE_SIZE: usize = 32;
+    const ERASE_SIZE: usize = SECTOR_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        erase(&mut self.bank, &SETTINGS_SECTORS, from, to)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+        write(&mut self.bank, &SETTINGS_SECTORS, offset, bytes)
+    }
+}
+
+impl ErrorType for WaveformFlash<'_> {
+    type Error = Error;
+}
+
+impl ReadNorFlash for WaveformFlash<'_> {
+    const READ_SIZE: usize = Flash::READ_SIZE;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+        read(self.bank, &WAVEFORM_SECTORS, offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        capacity(&WAVEFORM_SECTORS)
+    }
+}
+
+impl NorFlash for WaveformFlash<'_> {
+    const WRITE_SIZE: usize = Flash::WRITE_SIZE;
+    const ERASE_SIZE: usize = Flash::ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        erase(self.bank, &WAVEFORM_SECTORS, from, to)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+        write(self.bank, &WAVEFORM_SECTORS, offset, bytes)
+    }
+}
+
+// Allow temporarily borrowing the flash for storage logic taking ownership of it, e.g. to restore
+// settings during setup.
+impl ErrorType for &mut Flash {
+    type Error = Error;
+}
+
+impl ReadNorFlash for &mut Flash {
+    const READ_SIZE: usize = Flash::READ_SIZE;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+        (**self).read(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        (**self).capacity()
+    }
+}
+
+impl NorFlash for &mut Flash {
+    const WRITE_SIZE: usize = Flash::WRITE_SIZE;
+    const ERASE_SIZE: usize = Flash::ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        (**self).erase(from, to)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+        (**self).write(offset, bytes)
+    }
+}
@@ -1,5 +1,6 @@
 pub use embedded_hal;
 ///! Module for all hardware-specific setup of Stabilizer
+pub use stabilizer_dsp::signal_generator;
 pub use stm32h7xx_hal as hal;
 
 pub mod adc;
@@ -8,11 +9,11 @@ pub mod cpu_temp_sensor;
 pub mod dac;
 pub mod delay;
 pub mod design_parameters;
+pub mod flash;
 pub mod input_stamper;
 pub mod pounder;
 pub mod setup;
 pub mod shared_adc;
-pub mod signal_generator;
 pub mod timers;
 
 mod eeprom;
@@ -10,28 +10,38 @@ use stm32h7xx_hal::{
     prelude::*,
 };
 
+use miniconf::Miniconf;
 use smoltcp_nal::smoltcp;
 
+use crate::net::{config::NetworkConfig, data_stream};
+
 use super::{
     adc, afe, cpu_temp_sensor::CpuTempSensor, dac, delay, design_parameters,
-    eeprom, input_stamper::InputStamper, pounder,
+    eeprom, flash::Flash, input_stamper::InputStamper, pounder,
     pounder::dds_output::DdsOutput, shared_adc::SharedAdc, timers,
     DigitalInput0, DigitalInput1, EemDigitalInput0, EemDigitalInput1,
     EemDigitalOutput0, EemDigitalOutput1, EthernetPhy, NetworkStack,
     SystemTimer, Systick, AFE0, AFE1,
 };
 
-const NUM_TCP_SOCKETS: usize = 4;
+// Note: One TCP socket is reserved for the data stream. The UDP socket is reserved for DNS queries.
+const NUM_TCP_SOCKETS: usize = 5;
 const NUM_UDP_SOCKETS: usize = 1;
 const NUM_SOCKETS: usize = NUM_UDP_SOCKETS + NUM_TCP_SOCKETS;
 
 pub struct NetStorage {
-    pub ip_addrs: [smoltcp::wire::IpCidr; 1],
+    // Note: The IPv4 address is followed by the link-local IPv6 address.
+    pub ip_addrs: [smoltcp::wire::IpCidr; 2],
 
-    // Note: There is an additional socket set item required for the DHCP socket.
-    pub sockets: [smoltcp::iface::SocketStorage<'static>; NUM_SOCKETS + 1],
+    // Note: There are additional socket set items required for the DHCP, mDNS and UDP stream
+    // sockets.
+    pub sockets: [smoltcp::iface::SocketStorage<'static>;
+        NUM_SOCKETS + 2 + data_stream::NUM_SOCKETS],
     pub tcp_socket_storage: [TcpSocketStorage; NUM_TCP_SOCKETS],
     pub udp_socket_storage: [UdpSocketStorage; NUM_UDP_SOCKETS],
+    pub mdns_socket_storage: UdpSocketStorage,
+    pub stream_socket_storage: [UdpSocketStorage; data_stream::NUM_SOCKETS],
+    pub multicast_groups: [Option<(smoltcp::wire::Ipv4Address, ())>; 1],
     pub neighbor_cache:
         [Option<(smoltcp::wire::IpAddress, smoltcp::iface::Neighbor)>; 8],
     pub routes_cache:
@@ -81,15 +91,22 @@ impl TcpSocketStorage {
 impl Default for NetStorage {
     fn default() -> Self {
         NetStorage {
-            // Placeholder for the real IP address, which is initialized at runtime.
+            // Placeholder for the real IP addresses, which are initialized at runtime.
             ip_addrs: [smoltcp::wire::IpCidr::Ipv6(
                 smoltcp::wire::Ipv6Cidr::SOLICITED_NODE_PREFIX,
-            )],
+            ); 2],
             neighbor_cache: [None; 8],
             routes_cache: [None; 8],
-            sockets: [smoltcp::iface::SocketStorage::EMPTY; NUM_SOCKETS + 1],
+            sockets: [smoltcp::iface::SocketStorage::EMPTY;
+                NUM_SOCKETS + 2 + data_stream::NUM_SOCKETS],
             tcp_socket_storage: [TcpSocketStorage::new(); NUM_TCP_SOCKETS],
             udp_socket_storage: [UdpSocketStorage::new(); NUM_UDP_SOCKETS],
+            mdns_socket_storage: UdpSocketStorage::new(),
+            stream_socket_storage: [UdpSocketStorage::new();
+                data_stream::NUM_SOCKETS],
+            // Note: The interface is a member of the mDNS group from the start. No membership
+            // report is required for the link-local group.
+            multicast_groups: [Some((crate::net::mdns::MDNS_GROUP, ()))],
         }
     }
 }
@@ -99,6 +116,14 @@ pub struct NetworkDevices {
     pub stack: NetworkStack,
     pub phy: EthernetPhy,
     pub mac_address: smoltcp::wire::EthernetAddress,
+    /// The network configuration restored at boot.
+    pub config: NetworkConfig,
+    /// The RTT channel receiving console commands.
+    pub console: rtt_target::DownChannel,
+    /// The UDP socket for mDNS. It is not managed by the network stack.
+    pub mdns: smoltcp::iface::SocketHandle,
+    /// The UDP sockets for the data stream. They are not managed by the network stack.
+    pub stream: [smoltcp::iface::SocketHandle; data_stream::NUM_SOCKETS],
 }
 
 /// The GPIO pins available on the EEM connector, if Pounder is not present.
@@ -109,6 +134,61 @@ pub struct EemGpioDevices {
     pub lvds7: EemDigitalOutput1,
 }
 
+/// The ADC/DAC sampling configuration.
+///
+/// # Note
+/// The sampling configuration is applied at boot. It can only be changed by restarting.
+///
+/// # Miniconf
+/// `{"sample_ticks_log2": 7, "batch_size_log2": 3}`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Miniconf)]
+pub struct SamplingConfig {
+    /// The logarithm of the number of timer ticks between samples. The sampling timer runs at
+    /// [design_parameters::TIMER_FREQUENCY], so a value of 7 corresponds to a sampling period of
+    /// 1.28 µs (781.25 kHz). Valid values are 7 to 16.
+    pub sample_ticks_log2: u8,
+
+    /// The logarithm of the number of samples in each batch. Valid values are 0 to 5, limited by
+    /// [design_parameters::MAX_SAMPLE_BUFFER_SIZE] and a batch period of at most one monotonic
+    /// timer tick.
+    pub batch_size_log2: u8,
+}
+
+impl SamplingConfig {
+    /// The number of timer ticks between samples.
+    pub fn sample_ticks(&self) -> u32 {
+        1 << self.sample_ticks_log2
+    }
+
+    /// The number of samples in each batch.
+    pub fn batch_size(&self) -> usize {
+        1 << self.batch_size_log2
+    }
+
+    /// The time in seconds between samples.
+    pub fn sample_period(&self) -> f32 {
+        self.sample_ticks() as f32 * design_parameters::TIMER_PERIOD
+    }
+
+    /// The time in nanoseconds between samples.
+    pub fn sample_period_ns(&self) -> u32 {
+        self.sample_ticks()
+            * (1_000_000_000 / design_parameters::TIMER_FREQUENCY.to_Hz())
+    }
+
+    /// Check if the configuration is supported by the hardware.
+    pub fn is_valid(&self) -> bool {
+        (7..=16).contains(&self.sample_ticks_log2)
+            && (1 << self.batch_size_log2.min(31))
+                <= design_parameters::MAX_SAMPLE_BUFFER_SIZE
+            // The batch period must not exceed the RTIC Monotonic timer period.
+            && (self.batch_size() as u32 * self.sample_ticks()) as f32
+                * design_parameters::TIMER_PERIOD
+                * (super::MONOTONIC_FREQUENCY as f32)
+                < 1.
+    }
+}
+
 /// The available hardware interfaces on Stabilizer.
 pub struct StabilizerDevices {
     pub systick: Systick,
@@ -122,6 +202,8 @@ pub struct StabilizerDevices {
     pub net: NetworkDevices,
     pub digital_inputs: (DigitalInput0, DigitalInput1),
     pub eem_gpio: EemGpioDevices,
+    pub flash: Flash,
+    pub sampling: SamplingConfig,
 }
 
 /// The available Pounder-specific hardware interfaces.
@@ -193,8 +275,9 @@ fn load_itcm() {
 /// * `core` - The cortex-m peripherals.
 /// * `device` - The microcontroller peripherals to be configured.
 /// * `clock` - A `SystemTimer` implementing `Clock`.
-/// * `batch_size` - The size of each ADC/DAC batch.
-/// * `sample_ticks` - The number of timer ticks between each sample.
+/// * `sampling` - A closure providing the sampling configuration. It is called with the settings
+///   flash before the sampling hardware is configured, so the configuration may be restored from
+///   stored settings.
 ///
 /// # Returns
 /// (stabilizer, pounder) where `stabilizer` is a `StabilizerDevices` structure containing all
@@ -205,11 +288,10 @@ pub fn setup(
     mut core: stm32h7xx_hal::stm32::CorePeripherals,
     device: stm32h7xx_hal::stm32::Peripherals,
     clock: SystemTimer,
-    batch_size: usize,
-    sample_ticks: u32,
+    sampling: impl FnOnce(&mut Flash) -> SamplingConfig,
 ) -> (StabilizerDevices, Option<PounderDevices>) {
-    // Set up RTT logging
-    {
+    // Set up RTT logging and the console channel
+    let console = {
         // Enable debug during WFE/WFI-induced sleep
         device.DBGMCU.cr.modify(|_, w| w.dbgsleep_d1().set_bit());
 
@@ -249,7 +331,9 @@ pub fn setup(
             .map(|()| log::set_max_level(log::LevelFilter::Trace))
             .unwrap();
         log::info!("Starting");
-    }
+
+        channels.down.0
+    };
 
     let pwr = device.PWR.constrain();
     let vos = pwr.freeze();
@@ -301,13 +385,19 @@ pub fn setup(
     let dma_streams =
         hal::dma::dma::StreamsTuple::new(device.DMA1, ccdr.peripheral.DMA1);
 
-    // Verify that batch period does not exceed RTIC Monotonic timer period.
-    assert!(
-        (batch_size as u32 * sample_ticks) as f32
-            * design_parameters::TIMER_PERIOD
-            * (super::MONOTONIC_FREQUENCY as f32)
-            < 1.
-    );
+    let mut flash = {
+        let (_, bank2) = device.FLASH.split();
+        Flash::new(bank2.unwrap())
+    };
+
+    let sampling = sampling(&mut flash);
+    assert!(sampling.is_valid());
+    log::info!("Sampling: {:?}", sampling);
+
+    let network_config = NetworkConfig::load(&mut flash);
+    log::info!("Network: {:?}", network_config);
+    let batch_size = sampling.batch_size();
+    let sample_ticks = sampling.sample_ticks();
 
     // Configure timer 2 to trigger conversions for the ADC
     let mut sampling_timer = {
@@ -348,7 +438,7 @@ pub fn setup(
 
         let mut shadow_sampling_timer =
             timers::ShadowSamplingTimer::new(timer3);
-        shadow_sampling_timer.set_period_ticks(sample_ticks as u16 - 1);
+        shadow_sampling_timer.set_period_ticks((sample_ticks - 1) as u16);
 
         // The shadow sampling timer is a slave-mode timer to the sampling timer. It should
         // always be in-sync - thus, we configure it to operate in slave mode using "Trigger
@@ -638,23 +728,40 @@ pub fn setup(
 
         unsafe { ethernet::enable_interrupt() };
 
-        // Configure IP address according to DHCP socket availability
-        let ip_addrs: smoltcp::wire::IpAddress = option_env!("STATIC_IP")
-            .unwrap_or("0.0.0.0")
-            .parse()
-            .unwrap();
+        // Pass all multicast frames, so that mDNS queries and IPv6 neighbor solicitations are
+        // received.
+        // Note(unsafe): The frame filter is not modified by the ethernet driver after its
+        // configuration.
+        unsafe {
+            (*hal::stm32::ETHERNET_MAC::ptr())
+                .macpfr
+                .modify(|_, w| w.pm().set_bit())
+        };
 
         // Note(unwrap): The hardware configuration function is only allowed to be called once.
         // Unwrapping is intended to panic if called again to prevent re-use of global memory.
         let store =
             cortex_m::singleton!(: NetStorage = NetStorage::default()).unwrap();
 
-        store.ip_addrs[0] = smoltcp::wire::IpCidr::new(ip_addrs, 24);
+        // Configure the static IP address. If DHCP is used, the address remains unspecified until
+        // it is acquired.
+        let (ip, prefix_len, gateway) = if network_config.dhcp {
+            (smoltcp::wire::Ipv4Address::UNSPECIFIED, 24, [0; 4])
+        } else {
+            (
+                smoltcp::wire::Ipv4Address(network_config.ip),
+                network_config.prefix_len(),
+                network_config.gateway,
+            )
+        };
+        store.ip_addrs[0] = smoltcp::wire::IpCidr::new(ip.into(), prefix_len);
+        store.ip_addrs[1] =
+            smoltcp::wire::IpCidr::Ipv6(crate::net::ipv6::link_local(mac_addr));
 
         let mut routes =
             smoltcp::iface::Routes::new(&mut store.routes_cache[..]);
         routes
-            .add_default_ipv4_route(smoltcp::wire::Ipv4Address::UNSPECIFIED)
+            .add_default_ipv4_route(smoltcp::wire::Ipv4Address(gateway))
             .unwrap();
 
         let neighbor_cache =
@@ -668,9 +775,10 @@ pub fn setup(
         .neighbor_cache(neighbor_cache)
         .ip_addrs(&mut store.ip_addrs[..])
         .routes(routes)
+        .ipv4_multicast_groups(&mut store.multicast_groups[..])
         .finalize();
 
-        if ip_addrs.is_unspecified() {
+        if network_config.dhcp {
             interface.add_socket(smoltcp::socket::Dhcpv4Socket::new());
         }
 
@@ -718,10 +826,42 @@ pub fn setup(
 
         stack.seed_random_port(&random_seed);
 
+        // The mDNS and stream sockets are added after the network stack took over the other
+        // sockets, so that they are not used for other connections. The stream sockets are not
+        // restricted to IPv4 remotes like the sockets of the network stack.
+        let mut add_udp_socket =
+            |storage: &'static mut UdpSocketStorage| {
+                let rx_buffer = smoltcp::socket::UdpSocketBuffer::new(
+                    &mut storage.rx_metadata[..],
+                    &mut storage.rx_storage[..],
+                );
+                let tx_buffer = smoltcp::socket::UdpSocketBuffer::new(
+                    &mut storage.tx_metadata[..],
+                    &mut storage.tx_storage[..],
+                );
+
+                stack.interface_mut().add_socket(
+                    smoltcp::socket::UdpSocket::new(rx_buffer, tx_buffer),
+                )
+            };
+
+        let mdns = add_udp_socket(&mut store.mdns_socket_storage);
+        let mut stream = [mdns; data_stream::NUM_SOCKETS];
+        for (handle, storage) in stream
+            .iter_mut()
+            .zip(store.stream_socket_storage.iter_mut())
+        {
+            *handle = add_udp_socket(storage);
+        }
+
         NetworkDevices {
             stack,
             phy: lan8742a,
             mac_address: mac_addr,
+            config: network_config,
+            console,
+            mdns,
+            stream,
         }
     };
 
@@ -1003,6 +1143,8 @@ pub fn setup(
         timestamp_timer,
         digital_inputs,
         eem_gpio,
+        flash,
+        sampling,
     };
 
     // info!("Version {} {}", build_info::PKG_VERSION, build_info::GIT_VERSION.unwrap());
@@ -1,5 +1,9 @@
 #![no_std]
 #![cfg_attr(feature = "nightly", feature(core_intrinsics))]
 
+pub use stabilizer_dsp as dsp;
+pub use stabilizer_settings as settings;
+
 pub mod hardware;
 pub mod net;
+pub mod waveform;
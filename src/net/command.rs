@@ -0,0 +1,218 @@
+//! Stabilizer MQTT command interface
+//!
+//! # Design
+//! Commands are one-off requests acting on the device as opposed to run-time settings, e.g.
+//! storing the current settings to flash. A command is issued by publishing to the
+//! `<prefix>/command/<command>` topic, optionally with a command-specific payload.
+//!
+//! The result of every command is published as `{"code": <code>, "msg": <msg>}`, where a `code`
+//! of zero indicates success. The response is published to the response topic of the request if
+//! one is provided and to `<prefix>/log` otherwise. The response to `network/get` additionally
+//! contains the network configuration as `config`.
+use core::fmt::Write;
+use heapless::{String, Vec};
+use minimq::{
+    types::{SubscriptionOptions, TopicFilter},
+    Publication, QoS,
+};
+use serde::Serialize;
+
+use super::{config::NetworkConfig, dns::BrokerStack};
+use crate::hardware::SystemTimer;
+
+// The maximum size of a serialized response in bytes. This covers a response containing the
+// network configuration.
+const MAX_RESPONSE_SIZE: usize = 1024;
+
+/// The response to a command.
+#[derive(Debug, Serialize)]
+pub struct Response {
+    code: u8,
+    msg: String<256>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    config: Option<NetworkConfig>,
+}
+
+impl Response {
+    /// Construct a response indicating success.
+    ///
+    /// # Args
+    /// * `msg` - A message describing the result.
+    pub fn ok(msg: &str) -> Self {
+        Self {
+            code: 0,
+            msg: truncate(msg),
+            config: None,
+        }
+    }
+
+    /// Construct a response containing the network configuration.
+    ///
+    /// # Args
+    /// * `config` - The network configuration.
+    pub fn config(config: &NetworkConfig) -> Self {
+        Self {
+            code: 0,
+            msg: String::from("Network config"),
+            config: Some(config.clone()),
+        }
+    }
+
+    /// Construct a response indicating failure.
+    ///
+    /// # Args
+    /// * `msg` - A message describing the failure.
+    pub fn error(msg: &str) -> Self {
+        Self {
+            code: 255,
+            msg: truncate(msg),
+            config: None,
+        }
+    }
+
+    /// Construct a response indicating failure due to an error.
+    ///
+    /// # Args
+    /// * `error` - The error that caused the failure.
+    pub fn from_error(error: impl core::fmt::Debug) -> Self {
+        let mut msg: String<256> = String::new();
+        if write!(&mut msg, "{:?}", error).is_err() {
+            msg = String::from("Command Error");
+        }
+
+        Self {
+            code: 255,
+            msg,
+            config: None,
+        }
+    }
+}
+
+fn truncate<const N: usize>(msg: &str) -> String<N> {
+    let mut end = msg.len().min(N);
+    while !msg.is_char_boundary(end) {
+        end -= 1;
+    }
+    String::from(&msg[..end])
+}
+
+/// The MQTT client receiving commands.
+pub struct CommandClient {
+    mqtt: minimq::Minimq<BrokerStack, SystemTimer, 2048, 1>,
+    command_prefix: String<128>,
+    log_topic: String<128>,
+    subscribed: bool,
+}
+
+impl CommandClient {
+    /// Construct a new command client.
+    ///
+    /// # Args
+    /// * `stack` - A reference to the (shared) underlying network stack connecting to the broker.
+    /// * `clock` - A `SystemTimer` implementing `Clock`.
+    /// * `client_id` - The MQTT client ID of the command client.
+    /// * `prefix` - The device prefix to use for MQTT commands.
+    ///
+    /// # Returns
+    /// A new command client.
+    pub fn new(
+        stack: BrokerStack,
+        clock: SystemTimer,
+        client_id: &str,
+        prefix: &str,
+    ) -> Self {
+        let mqtt =
+            minimq::Minimq::new(BrokerStack::ADDRESS, client_id, stack, clock)
+                .unwrap();
+
+        let mut command_prefix: String<128> = String::from(prefix);
+        command_prefix.push_str("/command").unwrap();
+
+        let mut log_topic: String<128> = String::from(prefix);
+        log_topic.push_str("/log").unwrap();
+
+        Self {
+            mqtt,
+            command_prefix,
+            log_topic,
+            subscribed: false,
+        }
+    }
+
+    /// Update the command client and handle any received command.
+    ///
+    /// # Note
+    /// This should be called regularly to maintain the connection to the broker.
+    ///
+    /// # Args
+    /// * `handler` - A closure called with the command and its payload for every received command.
+    ///   The returned response is published to the requester.
+    pub fn update<F>(&mut self, mut handler: F)
+    where
+        F: FnMut(&str, &[u8]) -> Response,
+    {
+        if !self.mqtt.client().is_connected() {
+            self.subscribed = false;
+        } else if !self.subscribed {
+            let mut topic: String<128> = self.command_prefix.clone();
+            topic.push_str("/#").unwrap();
+
+            let topic_filter = TopicFilter::new(&topic).options(
+                SubscriptionOptions::default().ignore_local_messages(),
+            );
+
+            self.subscribed =
+                self.mqtt.client().subscribe(&[topic_filter], &[]).is_ok();
+        }
+
+        let command_prefix = self.command_prefix.as_str();
+        let log_topic = self.log_topic.as_str();
+
+        match self.mqtt.poll(|client, topic, message, properties| {
+            let response = match topic
+                .strip_prefix(command_prefix)
+                .and_then(|command| command.strip_prefix('/'))
+            {
+                Some(command) => handler(command, message),
+                None => {
+                    log::info!("Unexpected MQTT topic: {}", topic);
+                    return;
+                }
+            };
+
+            let response: Vec<u8, MAX_RESPONSE_SIZE> =
+                match miniconf::serde_json_core::to_vec(&response) {
+                    Ok(response) => response,
+                    // Note(unwrap): The error response is bounded in length, so it always fits.
+                    Err(error) => miniconf::serde_json_core::to_vec(
+                        &Response::from_error(error),
+                    )
+                    .unwrap(),
+                };
+
+            client
+                .publish(
+                    Publication::new(&response)
+                        .topic(log_topic)
+                        .reply(properties)
+                        .qos(QoS::AtLeastOnce)
+                        .finish()
+                        .unwrap(),
+                )
+                .map_err(|e| log::error!("Command response error: {:?}", e))
+                .ok();
+        }) {
+            Err(minimq::Error::Network(
+                smoltcp_nal::NetworkError::NoIpAddress,
+            )) => {}
+
+            Err(minimq::Error::SessionReset) => {
+                log::warn!("Command MQTT session reset");
+                self.subscribed = false;
+            }
+
+            Err(error) => log::info!("Unexpected error: {:?}", error),
+            _ => {}
+        }
+    }
+}
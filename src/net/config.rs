@@ -0,0 +1,223 @@
+//! Persistent network configuration
+//!
+//! # Design
+//! The network configuration (IP configuration, MQTT broker and MQTT prefix) is read from flash at
+//! boot and applied to the network interface and the MQTT clients. It is stored independently of
+//! the application settings so that it applies to all applications on a device.
+//!
+//! The configuration is stored using the [SettingsStore] with its own application ID, so it
+//! occupies one settings slot. If no configuration is stored, the defaults are used. The defaults
+//! use the build-time `STATIC_IP` and `BROKER` environment variables if they are provided.
+//!
+//! The configuration can be changed using the `network/...` MQTT commands or, if MQTT is not
+//! available, the [console](super::console). Changes are stored to flash and applied after a
+//! restart.
+use embedded_storage::nor_flash::NorFlash;
+use heapless::String;
+use miniconf::Miniconf;
+use serde::{Deserialize, Serialize};
+use smoltcp_nal::embedded_nal::{IpAddr, Ipv4Addr};
+
+use crate::settings::{self, SettingsStore};
+
+// The application name under which the network configuration is stored.
+const STORE_APP: &str = "network";
+
+// The MQTT broker used if no broker is configured.
+const DEFAULT_BROKER: &str = "10.34.16.1";
+
+// The MQTT broker port used if no port is configured.
+const DEFAULT_BROKER_PORT: u16 = 1883;
+
+/// The maximum length of the broker address and the MQTT prefix.
+pub const MAX_NAME_LENGTH: usize = 64;
+
+/// The network configuration of the device.
+///
+/// # Miniconf
+/// `{"dhcp": true, "ip": [0, 0, 0, 0], "netmask": [255, 255, 255, 0], "gateway": [0, 0, 0, 0],
+/// "dns": [0, 0, 0, 0], "broker": "10.34.16.1", "broker_port": 1883, "prefix": ""}`
+///
+/// * `dhcp` selects whether the IP configuration is acquired using DHCP. Otherwise the static `ip`,
+///   `netmask` and `gateway` are used.
+/// * `dns` is the name server used to resolve the broker hostname. If unspecified, the default
+///   gateway is used (see [dns](super::dns)).
+/// * `broker` is the IPv4 or IPv6 address or the hostname of the MQTT broker. See
+///   [ipv6](super::ipv6) for the limitations of IPv6.
+/// * `broker_port` is the TCP port of the MQTT broker.
+/// * `prefix` overrides the MQTT device prefix `dt/sinara/<app>/<mac-address>` if not empty.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Miniconf)]
+pub struct NetworkConfig {
+    /// Acquire the IP configuration using DHCP.
+    pub dhcp: bool,
+
+    /// The static IP address.
+    pub ip: [u8; 4],
+
+    /// The static netmask.
+    pub netmask: [u8; 4],
+
+    /// The static default gateway.
+    pub gateway: [u8; 4],
+
+    /// The name server.
+    pub dns: [u8; 4],
+
+    /// The MQTT broker IP address or hostname.
+    pub broker: String<MAX_NAME_LENGTH>,
+
+    /// The MQTT broker port.
+    pub broker_port: u16,
+
+    /// The MQTT device prefix. The default prefix is used if empty.
+    pub prefix: String<MAX_NAME_LENGTH>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        let ip: Ipv4Addr = option_env!("STATIC_IP")
+            .unwrap_or("0.0.0.0")
+            .parse()
+            .unwrap();
+
+        Self {
+            dhcp: ip.is_unspecified(),
+            ip: ip.octets(),
+            netmask: [255, 255, 255, 0],
+            gateway: [0; 4],
+            dns: [0; 4],
+            broker: String::from(
+                option_env!("BROKER").unwrap_or(DEFAULT_BROKER),
+            ),
+            broker_port: DEFAULT_BROKER_PORT,
+            prefix: String::new(),
+        }
+    }
+}
+
+/// Errors of the network configuration.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The static IP address is unspecified.
+    InvalidIp,
+    /// The netmask is not contiguous.
+    InvalidNetmask,
+    /// The broker is neither an IP address nor a valid hostname.
+    InvalidBroker,
+    /// The broker port is zero.
+    InvalidBrokerPort,
+    /// The prefix contains MQTT wildcards or leading or trailing separators.
+    InvalidPrefix,
+}
+
+impl NetworkConfig {
+    /// Check the configuration.
+    pub fn validate(&self) -> Result<(), Error> {
+        if !self.dhcp && Ipv4Addr::from(self.ip).is_unspecified() {
+            return Err(Error::InvalidIp);
+        }
+
+        let netmask = u32::from_be_bytes(self.netmask);
+        if netmask.leading_ones() != netmask.count_ones() {
+            return Err(Error::InvalidNetmask);
+        }
+
+        // Hostnames consist of labels of up to 63 letters, digits and hyphens.
+        let valid_label = |label: &str| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label
+                    .bytes()
+                    .all(|c| c.is_ascii_alphanumeric() || c == b'-')
+        };
+        if self.broker().is_none()
+            && !self
+                .broker
+                .trim_end_matches('.')
+                .split('.')
+                .all(valid_label)
+        {
+            return Err(Error::InvalidBroker);
+        }
+
+        if self.broker_port == 0 {
+            return Err(Error::InvalidBrokerPort);
+        }
+
+        if self.prefix.contains(['#', '+'])
+            || self.prefix.starts_with('/')
+            || self.prefix.ends_with('/')
+        {
+            return Err(Error::InvalidPrefix);
+        }
+
+        Ok(())
+    }
+
+    /// Get the IP address of the MQTT broker if it is not given as a hostname.
+    pub fn broker(&self) -> Option<IpAddr> {
+        self.broker.parse().ok()
+    }
+
+    /// Get the length of the static netmask in bits.
+    pub fn prefix_len(&self) -> u8 {
+        u32::from_be_bytes(self.netmask).count_ones() as u8
+    }
+
+    /// Restore the stored configuration.
+    ///
+    /// # Note
+    /// The default configuration is returned if no valid configuration is stored.
+    ///
+    /// # Args
+    /// * `flash` - The flash the settings are stored in.
+    pub fn load(flash: impl NorFlash) -> Self {
+        let mut config = Self::default();
+        match SettingsStore::new(flash, STORE_APP)
+            .load(settings::DEFAULT_PROFILE, &mut config)
+        {
+            Ok(_) => {}
+            Err(settings::Error::NotFound) => return Self::default(),
+            Err(error) => {
+                log::warn!("Failed to restore network config: {:?}", error);
+                return Self::default();
+            }
+        }
+
+        match config.validate() {
+            Ok(()) => {
+                log::info!("Restored network config");
+                config
+            }
+            Err(error) => {
+                log::warn!("Invalid stored network config: {:?}", error);
+                Self::default()
+            }
+        }
+    }
+
+    /// Store the configuration.
+    ///
+    /// # Note
+    /// This erases flash sectors, which may block for a significant amount of time.
+    ///
+    /// # Args
+    /// * `flash` - The flash the settings are stored in.
+    pub fn save<F: NorFlash>(
+        &self,
+        flash: F,
+    ) -> Result<usize, settings::Error<F::Error>> {
+        SettingsStore::new(flash, STORE_APP)
+            .save(settings::DEFAULT_PROFILE, self)
+    }
+
+    /// Erase the stored configuration so that the defaults are used.
+    ///
+    /// # Args
+    /// * `flash` - The flash the settings are stored in.
+    pub fn erase<F: NorFlash>(
+        flash: F,
+    ) -> Result<(), settings::Error<F::Error>> {
+        SettingsStore::new(flash, STORE_APP).erase(settings::DEFAULT_PROFILE)
+    }
+}
@@ -0,0 +1,99 @@
+//! Stabilizer RTT command console
+//!
+//! # Design
+//! The console receives commands over the RTT down channel of the debug probe. It is a fallback
+//! for configuring the network (see [config](super::config)) if the MQTT broker is not reachable,
+//! e.g. after moving a device to a different network.
+//!
+//! Each line received is a command followed by an optional payload, separated by whitespace. The
+//! commands and payloads are the same as the MQTT `network/...` commands. For example:
+//!
+//! ```text
+//! network/set/broker "192.168.1.10"
+//! network/apply
+//! ```
+//!
+//! The response to each command is logged. Lines longer than 256 bytes are discarded.
+//!
+//! # Note
+//! The RTT down channel only buffers 16 bytes. Longer commands are received correctly as long as
+//! the console is updated while the host writes to the channel.
+use heapless::Vec;
+use rtt_target::DownChannel;
+
+use super::command::Response;
+
+// The maximum length of a command line.
+const MAX_LINE_LENGTH: usize = 256;
+
+/// The command console.
+pub struct Console {
+    channel: DownChannel,
+    line: Vec<u8, MAX_LINE_LENGTH>,
+    // Set if the current line is too long. It is discarded up to the next line break.
+    discard: bool,
+}
+
+impl Console {
+    /// Construct the console.
+    ///
+    /// # Args
+    /// * `channel` - The RTT channel to receive commands from.
+    pub fn new(channel: DownChannel) -> Self {
+        Self {
+            channel,
+            line: Vec::new(),
+            discard: false,
+        }
+    }
+
+    /// Handle any received commands.
+    ///
+    /// # Note
+    /// This should be called regularly so that the RTT channel does not overflow.
+    ///
+    /// # Args
+    /// * `handler` - A closure called with the command and its payload for every received command.
+    ///   The returned response is logged.
+    pub fn update<F>(&mut self, mut handler: F)
+    where
+        F: FnMut(&str, &[u8]) -> Response,
+    {
+        let mut buffer = [0; 16];
+        loop {
+            let len = self.channel.read(&mut buffer);
+            if len == 0 {
+                return;
+            }
+
+            for &byte in &buffer[..len] {
+                if byte != b'\n' && byte != b'\r' {
+                    if !self.discard && self.line.push(byte).is_err() {
+                        log::warn!("Console line too long");
+                        self.line.clear();
+                        self.discard = true;
+                    }
+                    continue;
+                }
+
+                if self.discard {
+                    self.discard = false;
+                    continue;
+                }
+
+                if let Ok(line) = core::str::from_utf8(&self.line) {
+                    let line = line.trim();
+                    if !line.is_empty() {
+                        let (command, payload) = line
+                            .split_once(char::is_whitespace)
+                            .unwrap_or((line, ""));
+                        let response =
+                            handler(command, payload.trim().as_bytes());
+                        log::info!("{}: {:?}", command, response);
+                    }
+                }
+                self.line.clear();
+            }
+        }
+    }
+}
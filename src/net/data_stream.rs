@@ -4,6 +4,26 @@
 //! Data streamining utilizes UDP packets to send live data streams at high throughput.
 //! Packets are always sent in a best-effort fashion, and data may be dropped.
 //!
+//! Alternatively, the stream can be sent over a TCP connection (see [StreamProtocol]). Stabilizer
+//! connects to the stream target and each frame is preceded by its size in bytes as a
+//! little-endian <u16>. Frames are retransmitted by TCP and are only dropped if the connection is
+//! lost. If the connection cannot keep up with the data rate, the (bounded) frame buffers fill up
+//! and subsequent batches are dropped until buffers are available again. Dropped batches are
+//! counted (see [StreamStatistics]) and can be identified by gaps in the sequence numbers. A
+//! gap-free record is received as long as the connection bandwidth exceeds the stream data rate.
+//!
+//! The stream target may be an IPv4 or IPv6 endpoint (see [ipv6](super::ipv6)).
+//!
+//! ## Multicast and Fan-out
+//! UDP streams can be sent to an IPv4 multicast group by using the group address as the stream
+//! target. Sending to a group does not require Stabilizer to be a member of the group, so no IGMP
+//! membership is reported. The receivers join the group and IGMP snooping switches forward the
+//! stream to them.
+//!
+//! Additionally, a UDP stream can be sent to up to [MAX_FANOUT_TARGETS] further unicast or
+//! multicast targets. The same frames are sent to each target. Every target has its own UDP socket
+//! so that an unreachable target does not stall the others.
+//!
 //! Stabilizer organizes livestreamed data into batches within a "Frame" that will be sent as a UDP
 //! packet. Each frame consits of a header followed by sequential batch serializations. The packet
 //! header is constant for all streaming capabilities, but the serialization format after the header
@@ -18,28 +38,62 @@
 //! * **Batch Size** <u8>: the number of samples in each batch of data.
 //! * **Sequence Number** <u32>: an the sequence number of the first batch in the frame.
 //!   This can be used to determine if and how many stream batches are lost.
+//! * **Header Version** <u8>: the version of the header layout, currently 1. The fields following
+//!   the version depend on the version.
+//! * **Decimation** <u8>: the logarithm of the decimation factor of the batches in the frame. See
+//!   [decimation](super::decimation).
+//! * **Reserved** <u16>: zero.
+//! * **Timestamp** <u64>: the device time of the first sample of the first batch in the frame in
+//!   nanoseconds since the start of sampling. The time is derived from the sampling timer and is
+//!   exact with respect to the sample clock.
+//! * **Sample Period** <u32>: the (undecimated) time between samples in nanoseconds. The time of
+//!   sample `n` of the frame is `timestamp + n * sample_period * (1 << decimation)`.
+//!
+//! ## Decimation
+//! The stream can be decimated by a power of two before framing (see [Decimation]). A decimated
+//! batch has the same layout as an undecimated batch, but each of its values represents the
+//! average over the decimation factor of consecutive values. The sequence number counts decimated
+//! batches. Formats that cannot be decimated are streamed undecimated with a decimation of zero in
+//! the header.
+//!
+//! ## Statistics
+//! The stream counts generated, sent, and dropped batches and frames (see [StreamStatistics]).
+//! Batches dropped by the [FrameGenerator] because all frame buffers are in use indicate that
+//! Stabilizer cannot transmit the stream fast enough. Frames dropped by the [DataStream] are lost
+//! on the network side, e.g. while the stream is not connected or when sending fails. A UDP frame
+//! counts as sent once it reaches any of the targets. Failures to send to individual targets are
+//! counted separately.
 //!
 //! # Example
 //! A sample Python script is available in `scripts/stream_throughput.py` to demonstrate reception
 //! of livestreamed data.
 use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicU32, Ordering};
 use heapless::{
     pool::{Box, Init, Pool, Uninit},
     spsc::{Consumer, Producer, Queue},
 };
-use num_enum::IntoPrimitive;
-use serde::{Deserialize, Serialize};
-use smoltcp_nal::embedded_nal::{IpAddr, Ipv4Addr, SocketAddr, UdpClientStack};
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use smoltcp_nal::embedded_nal::{
+    self, IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpClientStack,
+};
+use smoltcp_nal::smoltcp::{iface::SocketHandle, socket::UdpSocket};
 
-use super::NetworkReference;
+use super::decimation::{self, Decimation, Decimator};
+use super::{ipv6, NetworkReference};
 
 // Magic first bytes indicating a UDP frame of straming data
 const MAGIC: u16 = 0x057B;
 
+// The version of the header layout.
+const HEADER_VERSION: u8 = 1;
+
 // The size of the header, calculated in words.
-// The header has a 16-bit magic word, an 8-bit format, 8-bit batch-size, and 32-bit sequence
-// number, which corresponds to 8 bytes.
-const HEADER_SIZE: usize = 8;
+// The header has a 16-bit magic word, an 8-bit format, 8-bit batch-size, 32-bit sequence
+// number, 8-bit version, 8-bit decimation, 16 reserved bits, 64-bit timestamp and 32-bit sample
+// period, which corresponds to 24 bytes.
+const HEADER_SIZE: usize = 24;
 
 // The number of frames that can be buffered.
 const FRAME_COUNT: usize = 4;
@@ -53,6 +107,13 @@ const FRAME_SIZE: usize = 1500 - 40 - 20 - HEADER_SIZE;
 // allocated frame buffer should fit in the queue.
 const FRAME_QUEUE_SIZE: usize = FRAME_COUNT * 2;
 
+/// The maximum number of additional UDP targets of the stream.
+pub const MAX_FANOUT_TARGETS: usize = 3;
+
+/// The number of UDP sockets of the stream: one for the stream target and one for each fan-out
+/// target.
+pub const NUM_SOCKETS: usize = 1 + MAX_FANOUT_TARGETS;
+
 // Static storage used for a heapless::Pool of frame buffers.
 static mut FRAME_DATA: [u8; core::mem::size_of::<u8>()
     * FRAME_SIZE
@@ -60,25 +121,110 @@ static mut FRAME_DATA: [u8; core::mem::size_of::<u8>()
 
 type Frame = [MaybeUninit<u8>; FRAME_SIZE];
 
-/// Represents the destination for the UDP stream to send data to.
+/// Specifies the transport protocol of the stream.
+#[derive(
+    Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub enum StreamProtocol {
+    /// Frames are sent as UDP packets in a best-effort fashion.
+    #[default]
+    Udp,
+    /// Frames are sent over a TCP connection to the target.
+    Tcp,
+}
+
+/// The IP address of a stream target.
+///
+/// # Miniconf
+/// An array of 4 bytes for an IPv4 address or of 16 bytes for an IPv6 address.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StreamAddress {
+    V4([u8; 4]),
+    V6([u8; 16]),
+}
+
+impl Default for StreamAddress {
+    fn default() -> Self {
+        Self::V4([0; 4])
+    }
+}
+
+impl From<StreamAddress> for IpAddr {
+    fn from(address: StreamAddress) -> IpAddr {
+        match address {
+            StreamAddress::V4(ip) => IpAddr::V4(Ipv4Addr::from(ip)),
+            StreamAddress::V6(ip) => IpAddr::V6(Ipv6Addr::from(ip)),
+        }
+    }
+}
+
+impl Serialize for StreamAddress {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::V4(ip) => ip.serialize(serializer),
+            Self::V6(ip) => serializer.collect_seq(ip),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for StreamAddress {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        let ip = heapless::Vec::<u8, 16>::deserialize(deserializer)?;
+        match ip.len() {
+            // Note(unwrap): The length is checked to match.
+            4 => Ok(Self::V4(ip[..].try_into().unwrap())),
+            16 => Ok(Self::V6(ip[..].try_into().unwrap())),
+            len => Err(serde::de::Error::invalid_length(len, &"4 or 16 bytes")),
+        }
+    }
+}
+
+/// Represents the destination for the stream to send data to.
 ///
 /// # Miniconf
-/// `{"ip": <addr>, "port": <port>}`
+/// `{"ip": <addr>, "port": <port>, "protocol": <protocol>}`
 ///
-/// * `<addr>` is an array of 4 bytes. E.g. `[192, 168, 0, 1]`
+/// * `<addr>` is an array of 4 bytes for an IPv4 address, e.g. `[192, 168, 0, 1]`, or of 16
+///   bytes for an IPv6 address. See [StreamAddress]. UDP streams may be sent to an IPv4 multicast
+///   group, e.g. `[239, 1, 2, 3]`.
 /// * `<port>` is any unsigned 16-bit value.
+/// * `<protocol>` is optional and any of the [StreamProtocol] variants in double quotes. The
+///   default is `"Udp"`.
 ///
 /// ## Example
-/// `{"ip": [192, 168,0, 1], "port": 1111}`
-#[derive(Copy, Clone, Debug, Serialize, Deserialize, Default)]
+/// `{"ip": [192, 168,0, 1], "port": 1111, "protocol": "Tcp"}`
+#[derive(
+    Copy, Clone, Debug, Serialize, Deserialize, Default, PartialEq, Eq,
+)]
 pub struct StreamTarget {
-    pub ip: [u8; 4],
+    pub ip: StreamAddress,
     pub port: u16,
+    #[serde(default)]
+    pub protocol: StreamProtocol,
 }
 
 /// Specifies the format of streamed data
+///
+/// # Miniconf
+/// Any of the variants enclosed in double quotes, e.g. `"AdcDacData"`. Each application supports a
+/// subset of the formats.
 #[repr(u8)]
-#[derive(Debug, Copy, Clone, PartialEq, Eq, IntoPrimitive)]
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    IntoPrimitive,
+    TryFromPrimitive,
+    Serialize,
+    Deserialize,
+)]
 pub enum StreamFormat {
     /// Reserved, unused format specifier.
     Unknown = 0,
@@ -95,19 +241,122 @@ pub enum StreamFormat {
     /// Streamed data in FLS (fiber length stabilization) format. See the FLS application for
     /// detailed definition.
     Fls = 2,
+
+    /// Streamed data contains ADC0, ADC1, DAC0, and DAC1 as in [StreamFormat::AdcDacData] followed
+    /// by the signal generator frequencies of DAC0 and DAC1 at the first sample of the batch. The
+    /// frequencies are <u32> tuning words in little-endian format where `1 << 32` corresponds to
+    /// the sample rate.
+    ///
+    /// # Example
+    /// With a batch size of 2, the serialization would take the following form:
+    /// ```
+    /// <ADC0[0]> <ADC0[1]> <ADC1[0]> <ADC1[1]> <DAC0[0]> <DAC0[1]> <DAC1[0]> <DAC1[1]> <FTW0> <FTW1>
+    /// ```
+    AdcDacFrequency = 3,
+
+    /// Streamed data contains the IIR cascade inputs of both channels followed by the IIR cascade
+    /// outputs of both channels as <f32> in little-endian format.
+    ///
+    /// # Example
+    /// With a batch size of 2, the serialization would take the following form:
+    /// ```
+    /// <IN0[0]> <IN0[1]> <IN1[0]> <IN1[1]> <OUT0[0]> <OUT0[1]> <OUT1[0]> <OUT1[1]>
+    /// ```
+    IirState = 4,
+
+    /// Streamed data contains the demodulated lockin in-phase and quadrature components of each
+    /// batch as <i32> in little-endian format.
+    ///
+    /// # Example
+    /// The serialization of each batch takes the following form:
+    /// ```
+    /// <I> <Q>
+    /// ```
+    LockinIq = 5,
+
+    /// Streamed data contains the lockin reference phase and frequency of each batch as <i32> in
+    /// little-endian format. `1 << 32` corresponds to a full turn and the sample rate respectively.
+    ///
+    /// # Example
+    /// The serialization of each batch takes the following form:
+    /// ```
+    /// <PHASE> <FTW>
+    /// ```
+    PhaseFrequency = 6,
+
+    /// Streamed data contains the signal generator outputs added to DAC0 and DAC1 as <i16> in
+    /// little-endian format.
+    ///
+    /// # Example
+    /// With a batch size of 2, the serialization would take the following form:
+    /// ```
+    /// <SIG0[0]> <SIG0[1]> <SIG1[0]> <SIG1[1]>
+    /// ```
+    SignalGenerator = 7,
 }
 
 impl From<StreamTarget> for SocketAddr {
     fn from(target: StreamTarget) -> SocketAddr {
-        SocketAddr::new(
-            IpAddr::V4(Ipv4Addr::new(
-                target.ip[0],
-                target.ip[1],
-                target.ip[2],
-                target.ip[3],
-            )),
-            target.port,
-        )
+        SocketAddr::new(target.ip.into(), target.port)
+    }
+}
+
+/// The statistics of the data stream.
+///
+/// # Note
+/// All counts are totals since boot and wrap around on overflow.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct StreamStatistics {
+    /// The number of batches added to stream frames.
+    pub batches: u32,
+
+    /// The number of frames enqueued for transmission.
+    pub frames: u32,
+
+    /// The number of frames sent.
+    pub sent: u32,
+
+    /// The number of frames dropped instead of being sent, e.g. while the stream is not connected
+    /// or if sending to all of the targets fails.
+    pub dropped: u32,
+
+    /// The number of times a UDP frame could not be sent to one of the targets.
+    pub target_errors: u32,
+
+    /// The number of batches dropped because no frame buffer was available.
+    pub overflows: u32,
+
+    /// The number of times all frame buffers were exhausted.
+    pub exhausted: u32,
+}
+
+// The stream counters shared by the generator and the transmitter.
+#[derive(Debug, Default)]
+struct Counters {
+    batches: AtomicU32,
+    frames: AtomicU32,
+    sent: AtomicU32,
+    dropped: AtomicU32,
+    target_errors: AtomicU32,
+    overflows: AtomicU32,
+    exhausted: AtomicU32,
+}
+
+impl Counters {
+    fn increment(counter: &AtomicU32) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn statistics(&self) -> StreamStatistics {
+        StreamStatistics {
+            batches: self.batches.load(Ordering::Relaxed),
+            frames: self.frames.load(Ordering::Relaxed),
+            sent: self.sent.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            target_errors: self.target_errors.load(Ordering::Relaxed),
+            overflows: self.overflows.load(Ordering::Relaxed),
+            exhausted: self.exhausted.load(Ordering::Relaxed),
+        }
     }
 }
 
@@ -115,12 +364,14 @@ impl From<StreamTarget> for SocketAddr {
 ///
 /// # Args
 /// * `stack` - A reference to the shared network stack.
+/// * `sockets` - The UDP sockets for the stream, which are not managed by the network stack.
 ///
 /// # Returns
 /// (generator, stream) where `generator` can be used to enqueue "batches" for transmission. The
 /// `stream` is the logically consumer (UDP transmitter) of the enqueued data.
 pub fn setup_streaming(
     stack: NetworkReference,
+    sockets: [SocketHandle; NUM_SOCKETS],
 ) -> (FrameGenerator, DataStream) {
     // The queue needs to be at least as large as the frame count to ensure that every allocated
     // frame can potentially be enqueued for transmission.
@@ -131,13 +382,17 @@ pub fn setup_streaming(
 
     let frame_pool = cortex_m::singleton!(: Pool<Frame> = Pool::new()).unwrap();
 
+    let counters =
+        cortex_m::singleton!(: Counters = Counters::default()).unwrap();
+
     // Note(unsafe): We guarantee that FRAME_DATA is only accessed once in this function.
     let memory = unsafe { &mut FRAME_DATA };
     frame_pool.grow(memory);
 
-    let generator = FrameGenerator::new(producer, frame_pool);
+    let generator = FrameGenerator::new(producer, frame_pool, counters);
 
-    let stream = DataStream::new(stack, consumer, frame_pool);
+    let stream =
+        DataStream::new(stack, sockets, consumer, frame_pool, counters);
 
     (generator, stream)
 }
@@ -154,6 +409,9 @@ impl StreamFrame {
         format_id: u8,
         batch_size: u8,
         sequence_number: u32,
+        decimation_log2: u8,
+        timestamp: u64,
+        sample_period: u32,
     ) -> Self {
         let mut buffer = buffer.init([MaybeUninit::uninit(); FRAME_SIZE]);
 
@@ -162,6 +420,9 @@ impl StreamFrame {
             .iter()
             .chain(&[format_id, batch_size])
             .chain(sequence_number.to_le_bytes().iter())
+            .chain(&[HEADER_VERSION, decimation_log2, 0, 0])
+            .chain(timestamp.to_le_bytes().iter())
+            .chain(sample_period.to_le_bytes().iter())
             .enumerate()
         {
             buffer[offset].write(*byte);
@@ -173,17 +434,17 @@ impl StreamFrame {
         }
     }
 
-    pub fn add_batch<F, const T: usize>(&mut self, mut f: F)
+    pub fn add_batch<F>(&mut self, len: usize, mut f: F)
     where
         F: FnMut(&mut [MaybeUninit<u8>]),
     {
-        f(&mut self.buffer[self.offset..self.offset + T]);
+        f(&mut self.buffer[self.offset..self.offset + len]);
 
-        self.offset += T;
+        self.offset += len;
     }
 
-    pub fn is_full<const T: usize>(&self) -> bool {
-        self.offset + T > self.buffer.len()
+    pub fn is_full(&self, len: usize) -> bool {
+        self.offset + len > self.buffer.len()
     }
 
     pub fn finish(&self) -> &[MaybeUninit<u8>] {
@@ -199,12 +460,19 @@ pub struct FrameGenerator {
     sequence_number: u32,
     format: u8,
     batch_size: u8,
+    sample_period: u32,
+    timestamp: u64,
+    counters: &'static Counters,
+    exhausted: bool,
+    decimator: Decimator,
+    batch: [MaybeUninit<u8>; decimation::MAX_BATCH_SIZE],
 }
 
 impl FrameGenerator {
     fn new(
         queue: Producer<'static, StreamFrame, FRAME_QUEUE_SIZE>,
         pool: &'static Pool<Frame>,
+        counters: &'static Counters,
     ) -> Self {
         Self {
             queue,
@@ -213,6 +481,12 @@ impl FrameGenerator {
             format: StreamFormat::Unknown.into(),
             current_frame: None,
             sequence_number: 0,
+            sample_period: 0,
+            timestamp: 0,
+            counters,
+            exhausted: false,
+            decimator: Decimator::default(),
+            batch: [MaybeUninit::new(0); decimation::MAX_BATCH_SIZE],
         }
     }
 
@@ -225,18 +499,118 @@ impl FrameGenerator {
     /// * `format` - The desired format of the stream.
     /// * `batch_size` - The number of samples in each data batch. See
     /// [crate::hardware::design_parameters::SAMPLE_BUFFER_SIZE]
+    /// * `sample_period` - The time between samples in nanoseconds.
     #[doc(hidden)]
-    pub(crate) fn configure(&mut self, format: impl Into<u8>, batch_size: u8) {
+    pub(crate) fn configure(
+        &mut self,
+        format: impl Into<u8>,
+        batch_size: u8,
+        sample_period: u32,
+    ) {
         self.format = format.into();
         self.batch_size = batch_size;
+        self.sample_period = sample_period;
+    }
+
+    /// Change the format of the stream.
+    ///
+    /// # Note
+    /// If the format changes, the current frame is enqueued for transmission and subsequent
+    /// batches start a new frame.
+    ///
+    /// # Args
+    /// * `format` - The desired format of the stream.
+    pub fn set_format(&mut self, format: impl Into<u8>) {
+        let format = format.into();
+        if format == self.format {
+            return;
+        }
+
+        self.format = format;
+        self.decimator.reset();
+        self.flush();
+    }
+
+    /// Change the decimation of the stream.
+    ///
+    /// # Note
+    /// If the decimation changes, the current frame is enqueued for transmission and subsequent
+    /// batches start a new frame.
+    ///
+    /// # Args
+    /// * `decimation` - The desired decimation of the stream.
+    pub fn set_decimation(&mut self, decimation: Decimation) {
+        if decimation != self.decimator.decimation() {
+            self.decimator.configure(decimation);
+            self.flush();
+        }
+    }
+
+    // Enqueue the current frame for transmission.
+    fn flush(&mut self) {
+        if let Some(frame) = self.current_frame.take() {
+            // Note(unwrap): The queue is designed to be at least as large as the frame buffer
+            // count, so this enqueue should always succeed.
+            self.queue.enqueue(frame).unwrap();
+            Counters::increment(&self.counters.frames);
+        }
     }
 
     /// Add a batch to the current stream frame.
     ///
+    /// # Note
+    /// If the stream is decimated, the batch is decimated and a decimated batch is added once per
+    /// decimation period. Batches that cannot be decimated are added undecimated.
+    ///
+    /// This must be called for every batch of samples (in the order of sampling) as the stream
+    /// timestamp is advanced by one batch period.
+    ///
     /// # Args
+    /// * `len` - The size of the batch data in bytes.
     /// * `f` - A closure that will be provided the buffer to write batch data into. The buffer will
-    ///   be the size of the `T` template argument.
-    pub fn add<F, const T: usize>(&mut self, f: F)
+    ///   be `len` bytes long.
+    pub fn add<F>(&mut self, len: usize, mut f: F)
+    where
+        F: FnMut(&mut [MaybeUninit<u8>]),
+    {
+        let batch_period = self.batch_size as u64 * self.sample_period as u64;
+        let timestamp = self.timestamp;
+        self.timestamp = self.timestamp.wrapping_add(batch_period);
+
+        let log2 = self.decimator.decimation().log2;
+        if log2 == 0 || !decimation::is_supported(self.format, len) {
+            self.push(timestamp, 0, len, f);
+            return;
+        }
+
+        f(&mut self.batch[..len]);
+
+        // Note(unsafe): The batch buffer is initialized at construction and only ever written to.
+        let batch = unsafe {
+            core::slice::from_raw_parts(self.batch.as_ptr() as *const u8, len)
+        };
+
+        let mut data = [0; decimation::MAX_BATCH_SIZE];
+        match self
+            .decimator
+            .update(self.format, self.batch_size as _, batch)
+        {
+            Some(decimated) => data[..len].copy_from_slice(decimated),
+            None => return,
+        }
+
+        // The decimated batch starts with the first batch of the decimation period.
+        let timestamp = self.timestamp.wrapping_sub(batch_period << log2);
+        self.push(timestamp, log2, len, |buf| {
+            for (buf, byte) in buf.iter_mut().zip(data) {
+                buf.write(byte);
+            }
+        });
+    }
+
+    // Add a batch starting at the provided timestamp and of the provided decimation to the current
+    // stream frame.
+    fn push<F>(&mut self, timestamp: u64, log2: u8, len: usize, f: F)
     where
         F: FnMut(&mut [MaybeUninit<u8>]),
     {
@@ -250,8 +624,17 @@ impl FrameGenerator {
                     self.format,
                     self.batch_size,
                     sequence_number,
+                    log2,
+                    timestamp,
+                    self.sample_period,
                 ));
+                self.exhausted = false;
             } else {
+                Counters::increment(&self.counters.overflows);
+                if !self.exhausted {
+                    Counters::increment(&self.counters.exhausted);
+                    self.exhausted = true;
+                }
                 return;
             }
         }
@@ -259,28 +642,38 @@ impl FrameGenerator {
         // Note(unwrap): We ensure the frame is present above.
         let current_frame = self.current_frame.as_mut().unwrap();
 
-        current_frame.add_batch::<_, T>(f);
+        current_frame.add_batch(len, f);
+        Counters::increment(&self.counters.batches);
 
-        if current_frame.is_full::<T>() {
-            // Note(unwrap): The queue is designed to be at least as large as the frame buffer
-            // count, so this enqueue should always succeed.
-            self.queue
-                .enqueue(self.current_frame.take().unwrap())
-                .unwrap();
+        if current_frame.is_full(len) {
+            self.flush();
         }
     }
 }
 
+/// The socket of a stream connection.
+enum Socket {
+    // The UDP sockets of the stream are not managed by the network stack.
+    Udp,
+    Tcp(<NetworkReference as TcpClientStack>::TcpSocket),
+}
+
 /// The "consumer" portion of the data stream.
 ///
 /// # Note
-/// This is responsible for consuming data and sending it over UDP.
+/// This is responsible for consuming data and sending it over UDP or TCP.
 pub struct DataStream {
     stack: NetworkReference,
-    socket: Option<<NetworkReference as UdpClientStack>::UdpSocket>,
+    // The UDP sockets of the stream target and the fan-out targets.
+    udp: [SocketHandle; NUM_SOCKETS],
+    socket: Option<Socket>,
     queue: Consumer<'static, StreamFrame, FRAME_QUEUE_SIZE>,
     frame_pool: &'static Pool<Frame>,
-    remote: SocketAddr,
+    target: StreamTarget,
+    fanout: [StreamTarget; MAX_FANOUT_TARGETS],
+    // The frame being transmitted over TCP and the number of bytes of it that are sent.
+    pending: Option<(StreamFrame, usize)>,
+    counters: &'static Counters,
 }
 
 impl DataStream {
@@ -288,61 +681,194 @@ impl DataStream {
     ///
     /// # Args
     /// * `stack` - A reference to the shared network stack.
+    /// * `udp` - The UDP sockets for the stream target and the fan-out targets.
     /// * `consumer` - The read side of the queue containing data to transmit.
     /// * `frame_pool` - The Pool to return stream frame objects into.
+    /// * `counters` - The stream counters.
     fn new(
         stack: NetworkReference,
+        udp: [SocketHandle; NUM_SOCKETS],
         consumer: Consumer<'static, StreamFrame, FRAME_QUEUE_SIZE>,
         frame_pool: &'static Pool<Frame>,
+        counters: &'static Counters,
     ) -> Self {
         Self {
             stack,
+            udp,
             socket: None,
-            remote: StreamTarget::default().into(),
+            target: StreamTarget::default(),
+            fanout: [StreamTarget::default(); MAX_FANOUT_TARGETS],
             queue: consumer,
             frame_pool,
+            pending: None,
+            counters,
         }
     }
 
+    /// Get the statistics of the stream.
+    pub fn statistics(&self) -> StreamStatistics {
+        self.counters.statistics()
+    }
+
+    // Return a frame to the pool without transmitting it.
+    fn drop_frame(&mut self, frame: StreamFrame) {
+        self.frame_pool.free(frame.buffer);
+        Counters::increment(&self.counters.dropped);
+    }
+
     fn close(&mut self) {
         if let Some(socket) = self.socket.take() {
             log::info!("Closing stream");
             // Note(unwrap): We guarantee that the socket is available above.
-            self.stack.close(socket).unwrap();
+            match socket {
+                Socket::Udp => {
+                    for &handle in self.udp.iter() {
+                        close_udp(&mut self.stack, handle);
+                    }
+                }
+                Socket::Tcp(socket) => {
+                    TcpClientStack::close(&mut self.stack, socket).unwrap()
+                }
+            }
+        }
+
+        // A partially sent frame cannot be continued on a new connection.
+        if let Some((frame, _)) = self.pending.take() {
+            self.drop_frame(frame);
         }
     }
 
+    // Check if any fan-out target is used.
+    fn has_fanout(&self) -> bool {
+        self.fanout.iter().any(|target| {
+            target.protocol == StreamProtocol::Udp
+                && !IpAddr::from(target.ip).is_unspecified()
+        })
+    }
+
     // Open new socket.
     fn open(&mut self) -> Result<(), ()> {
-        // If there is already a socket of if remote address is unspecified,
-        // do not open a new socket.
-        if self.socket.is_some() || self.remote.ip().is_unspecified() {
+        // If there is already a socket, do not open a new socket.
+        if self.socket.is_some() {
             return Err(());
         }
 
-        log::info!("Opening stream");
+        // Without a stream target, the stream is only sent to the fan-out targets.
+        let remote: SocketAddr = self.target.into();
+        if remote.ip().is_unspecified() {
+            if !self.has_fanout() {
+                return Err(());
+            }
 
-        let mut socket = self.stack.socket().or(Err(()))?;
+            log::info!("Opening stream to the fan-out targets");
+            self.socket.replace(Socket::Udp);
+            return Ok(());
+        }
 
-        // Note(unwrap): We only connect with a new socket, so it is guaranteed to not already be
-        // bound.
-        self.stack.connect(&mut socket, self.remote).unwrap();
+        // Sending requires a local address of the same family as the remote.
+        if !ipv6::has_address(&mut self.stack, remote.ip()) {
+            return Err(());
+        }
+
+        // TCP streams cannot be sent to multicast groups.
+        if self.target.protocol == StreamProtocol::Tcp
+            && remote.ip().is_multicast()
+        {
+            return Err(());
+        }
+
+        log::info!("Opening stream");
+
+        let socket = match self.target.protocol {
+            // Note: The UDP sockets are bound when sending.
+            StreamProtocol::Udp => Socket::Udp,
+            // Note: The TCP connection is established in `process()`.
+            StreamProtocol::Tcp => Socket::Tcp(
+                TcpClientStack::socket(&mut self.stack).or(Err(()))?,
+            ),
+        };
 
         self.socket.replace(socket);
 
         Ok(())
     }
 
-    /// Configure the remote endpoint of the stream.
+    /// Configure the remote endpoint and protocol of the stream.
+    ///
+    /// # Args
+    /// * `target` - The destination to send stream data to.
+    pub fn set_target(&mut self, target: StreamTarget) {
+        // Close socket to be reopened if the target has changed.
+        if target != self.target {
+            self.close();
+
+            if target.protocol == StreamProtocol::Tcp
+                && IpAddr::from(target.ip).is_multicast()
+            {
+                log::warn!("TCP streams cannot be sent to multicast groups");
+            }
+        }
+        self.target = target;
+    }
+
+    /// Configure the additional UDP targets of the stream.
+    ///
+    /// # Note
+    /// The fan-out targets receive the same frames as the stream target while the stream is sent
+    /// over UDP or if the stream target is unspecified. Targets with an unspecified IP address are
+    /// unused. Targets using TCP are ignored.
     ///
     /// # Args
-    /// * `remote` - The destination to send stream data to.
-    pub fn set_remote(&mut self, remote: SocketAddr) {
-        // Close socket to be reopened if the remote has changed.
-        if remote != self.remote {
+    /// * `targets` - The additional destinations to send stream data to.
+    pub fn set_fanout(&mut self, targets: [StreamTarget; MAX_FANOUT_TARGETS]) {
+        for (index, (target, handle)) in
+            targets.iter().zip(&self.udp[1..]).enumerate()
+        {
+            if *target == self.fanout[index] {
+                continue;
+            }
+
+            if target.protocol == StreamProtocol::Tcp {
+                log::warn!("Ignoring TCP stream fan-out target {}", index);
+            }
+
+            // Discard frames still pending for the previous target.
+            close_udp(&mut self.stack, *handle);
+        }
+        self.fanout = targets;
+
+        // The stream is closed once no target is left.
+        if IpAddr::from(self.target.ip).is_unspecified() && !self.has_fanout() {
             self.close();
         }
-        self.remote = remote;
+    }
+
+    // Send a frame to the stream target and the fan-out targets over UDP.
+    //
+    // # Returns
+    // True if the frame was sent to any of the targets.
+    fn send_udp(&mut self, data: &[u8]) -> bool {
+        let targets = core::iter::once(&self.target).chain(self.fanout.iter());
+
+        let mut sent = false;
+        for (&handle, target) in self.udp.iter().zip(targets) {
+            let remote: SocketAddr = (*target).into();
+            if target.protocol != StreamProtocol::Udp
+                || remote.ip().is_unspecified()
+            {
+                continue;
+            }
+
+            if ipv6::has_address(&mut self.stack, remote.ip())
+                && send_to(&mut self.stack, handle, remote, data).is_ok()
+            {
+                sent = true;
+            } else {
+                Counters::increment(&self.counters.target_errors);
+            }
+        }
+
+        sent
     }
 
     /// Process any data for transmission.
@@ -353,11 +879,11 @@ impl DataStream {
                 if self.open().is_ok() {
                     // If we just successfully opened the socket, flush old data from queue.
                     while let Some(frame) = self.queue.dequeue() {
-                        self.frame_pool.free(frame.buffer);
+                        self.drop_frame(frame);
                     }
                 }
             }
-            Some(handle) => {
+            Some(Socket::Udp) => {
                 if let Some(frame) = self.queue.dequeue() {
                     // Transmit the frame and return it to the pool.
                     let buf = frame.finish();
@@ -367,10 +893,135 @@ impl DataStream {
                             buf.len() * core::mem::size_of::<MaybeUninit<u8>>(),
                         )
                     };
-                    self.stack.send(handle, data).ok();
-                    self.frame_pool.free(frame.buffer)
+                    if self.send_udp(data) {
+                        self.frame_pool.free(frame.buffer);
+                        Counters::increment(&self.counters.sent);
+                    } else {
+                        self.drop_frame(frame);
+                    }
+                }
+            }
+            Some(Socket::Tcp(_)) => {
+                if let Err(err) = self.process_tcp() {
+                    log::warn!("Stream connection failed: {:?}", err);
+                    self.close();
                 }
             }
         }
     }
+
+    // Connect the TCP socket and transmit as many queued frames as the socket accepts.
+    //
+    // # Note
+    // Each frame is preceded by its size in bytes as a little-endian <u16>.
+    fn process_tcp(
+        &mut self,
+    ) -> Result<(), <NetworkReference as TcpClientStack>::Error> {
+        let handle = match self.socket.as_mut() {
+            Some(Socket::Tcp(handle)) => handle,
+            _ => return Ok(()),
+        };
+
+        if !self.stack.is_connected(handle)? {
+            // Note: Connecting an already connecting socket has no effect.
+            return match ipv6::connect(
+                &mut self.stack,
+                handle,
+                self.target.into(),
+            ) {
+                Err(embedded_nal::nb::Error::Other(err)) => Err(err),
+                _ => Ok(()),
+            };
+        }
+
+        loop {
+            if self.pending.is_none() {
+                match self.queue.dequeue() {
+                    Some(frame) => self.pending.replace((frame, 0)),
+                    None => return Ok(()),
+                };
+            }
+
+            // Note(unwrap): We ensure the pending frame is present above.
+            let (frame, offset) = self.pending.as_mut().unwrap();
+            let buf = frame.finish();
+            // Note(unsafe): The frame is initialized up to its current offset.
+            let data = unsafe {
+                core::slice::from_raw_parts(
+                    buf.as_ptr() as *const u8,
+                    buf.len(),
+                )
+            };
+            let size = (data.len() as u16).to_le_bytes();
+
+            let chunk = if *offset < size.len() {
+                &size[*offset..]
+            } else {
+                &data[*offset - size.len()..]
+            };
+
+            let sent =
+                match TcpClientStack::send(&mut self.stack, handle, chunk) {
+                    Ok(sent) => sent,
+                    Err(embedded_nal::nb::Error::WouldBlock) => 0,
+                    Err(embedded_nal::nb::Error::Other(err)) => {
+                        return Err(err)
+                    }
+                };
+
+            // The socket buffer is full. Continue with the next call.
+            if sent == 0 {
+                return Ok(());
+            }
+
+            *offset += sent;
+            if *offset == size.len() + data.len() {
+                // Note(unwrap): We ensure the pending frame is present above.
+                let (frame, _) = self.pending.take().unwrap();
+                self.frame_pool.free(frame.buffer);
+                Counters::increment(&self.counters.sent);
+            }
+        }
+    }
+}
+
+// Close a UDP socket of the stream and discard its pending frames.
+fn close_udp(stack: &mut NetworkReference, handle: SocketHandle) {
+    stack.lock(|stack| {
+        stack
+            .interface_mut()
+            .get_socket::<UdpSocket>(handle)
+            .close()
+    })
+}
+
+// Send a frame on a UDP socket of the stream.
+//
+// # Note
+// The socket is bound to an ephemeral port if it is closed.
+fn send_to(
+    stack: &mut NetworkReference,
+    handle: SocketHandle,
+    remote: SocketAddr,
+    data: &[u8],
+) -> smoltcp_nal::smoltcp::Result<()> {
+    stack.lock(|stack| {
+        // Note: The network stack closes all sockets when the IP address changes.
+        if !stack
+            .interface_mut()
+            .get_socket::<UdpSocket>(handle)
+            .is_open()
+        {
+            let port = ipv6::ephemeral_port(stack);
+            stack
+                .interface_mut()
+                .get_socket::<UdpSocket>(handle)
+                .bind(port)?;
+        }
+
+        stack
+            .interface_mut()
+            .get_socket::<UdpSocket>(handle)
+            .send_slice(data, ipv6::endpoint(remote))
+    })
 }
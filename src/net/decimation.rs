@@ -0,0 +1,341 @@
+//! Livestream decimation
+//!
+//! # Design
+//! Decimation reduces the rate of streamed data by a power of two before it is framed. The
+//! serialized batches of a stream format are decoded into their individual signals (e.g. ADC0 or
+//! DAC1) according to the layout of the format. Each signal is filtered and decimated by a
+//! cascaded integrator-comb (CIC) filter. A decimated batch has the same layout and size as an
+//! input batch and is emitted after a number of input batches equal to the decimation factor.
+//!
+//! The filters operate in wrapping 64 bit integer arithmetic. Floating point signals are converted
+//! to fixed point with 8 fractional bits and limited to `±2^24` before filtering. Phase signals
+//! would be corrupted by averaging across their wrap-around and are decimated by keeping the first
+//! value of each decimation period instead.
+//!
+//! Formats without a known layout (e.g. `Fls`) and batches larger than [MAX_BATCH_SIZE] are not
+//! decimated (see [is_supported]). They are streamed undecimated regardless of the configured
+//! decimation.
+use miniconf::Miniconf;
+use serde::{Deserialize, Serialize};
+
+use super::data_stream::StreamFormat;
+
+/// The maximum logarithm of the decimation factor.
+pub const MAX_DECIMATION_LOG2: u8 = 10;
+
+/// The maximum number of signals of a stream format.
+const MAX_SIGNALS: usize = 6;
+
+/// The maximum size of a serialized batch in bytes.
+pub const MAX_BATCH_SIZE: usize = 512;
+
+// The number of fractional bits of the fixed point representation of floating point values.
+const FLOAT_FRACTION_BITS: i32 = 8;
+
+/// The averaging filters of a decimated stream.
+#[derive(
+    Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub enum DecimationFilter {
+    /// The mean of the samples of each decimation period (a first order CIC filter).
+    #[default]
+    Boxcar,
+    /// A third order CIC filter with improved alias rejection.
+    Cic,
+}
+
+impl DecimationFilter {
+    fn order(&self) -> usize {
+        match self {
+            Self::Boxcar => 1,
+            Self::Cic => 3,
+        }
+    }
+}
+
+/// The decimation of a livestream.
+///
+/// # Miniconf
+/// `{"log2": 0, "filter": "Boxcar"}`
+///
+/// * `log2` is the logarithm of the decimation factor from 0 (no decimation) to
+///   [MAX_DECIMATION_LOG2].
+/// * `filter` is any of the [DecimationFilter] variants.
+#[derive(
+    Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Miniconf,
+)]
+pub struct Decimation {
+    /// The logarithm of the decimation factor.
+    pub log2: u8,
+
+    /// The averaging filter.
+    pub filter: DecimationFilter,
+}
+
+impl Decimation {
+    /// Check if the decimation is supported.
+    pub fn is_valid(&self) -> bool {
+        self.log2 <= MAX_DECIMATION_LOG2
+    }
+}
+
+/// The value types of streamed signals.
+#[derive(Copy, Clone, Debug)]
+enum Value {
+    /// A two's complement `i16`, e.g. an ADC code.
+    I16,
+    /// An offset binary `u16`, e.g. a DAC code.
+    OffsetU16,
+    /// A `u32`, e.g. a frequency tuning word.
+    U32,
+    /// An `i32`.
+    I32,
+    /// An `i32` phase that wraps around.
+    Phase,
+    /// An `f32`.
+    F32,
+}
+
+impl Value {
+    fn size(&self) -> usize {
+        match self {
+            Self::I16 | Self::OffsetU16 => 2,
+            _ => 4,
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> i64 {
+        let word = |bytes: &[u8]| {
+            u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+        };
+        match self {
+            Self::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as i64,
+            Self::OffsetU16 => {
+                (u16::from_le_bytes([bytes[0], bytes[1]]) ^ 0x8000) as i16
+                    as i64
+            }
+            Self::U32 => word(bytes) as i64,
+            Self::I32 | Self::Phase => word(bytes) as i32 as i64,
+            Self::F32 => {
+                const LIMIT: f32 = (1 << 24) as f32;
+                let x = f32::from_bits(word(bytes)).clamp(-LIMIT, LIMIT);
+                (x * (1 << FLOAT_FRACTION_BITS) as f32) as i64
+            }
+        }
+    }
+
+    fn encode(&self, value: i64, bytes: &mut [u8]) {
+        match self {
+            Self::I16 => {
+                bytes[..2].copy_from_slice(&(value as i16).to_le_bytes())
+            }
+            Self::OffsetU16 => bytes[..2].copy_from_slice(
+                &((value as i16 as u16) ^ 0x8000).to_le_bytes(),
+            ),
+            Self::U32 => {
+                bytes[..4].copy_from_slice(&(value as u32).to_le_bytes())
+            }
+            Self::I32 | Self::Phase => {
+                bytes[..4].copy_from_slice(&(value as i32).to_le_bytes())
+            }
+            Self::F32 => {
+                let x = value as f32 / (1 << FLOAT_FRACTION_BITS) as f32;
+                bytes[..4].copy_from_slice(&x.to_le_bytes())
+            }
+        }
+    }
+}
+
+/// A streamed signal: its value type and whether there is a value for every sample of a batch
+/// (as opposed to a single value per batch).
+type Signal = (Value, bool);
+
+/// Check whether batches of a stream format can be decimated.
+///
+/// # Args
+/// * `format` - The stream format code of the batches.
+/// * `len` - The size of a serialized batch in bytes.
+pub fn is_supported(format: u8, len: usize) -> bool {
+    layout(format).is_some() && len <= MAX_BATCH_SIZE
+}
+
+/// Get the signals of a stream format in their serialization order.
+fn layout(format: u8) -> Option<&'static [Signal]> {
+    use Value::*;
+    const ADC_DAC: [Signal; 4] = [
+        (I16, true),
+        (I16, true),
+        (OffsetU16, true),
+        (OffsetU16, true),
+    ];
+
+    let format = StreamFormat::try_from(format).ok()?;
+    Some(match format {
+        StreamFormat::AdcDacData => &ADC_DAC,
+        StreamFormat::AdcDacFrequency => &[
+            ADC_DAC[0],
+            ADC_DAC[1],
+            ADC_DAC[2],
+            ADC_DAC[3],
+            (U32, false),
+            (U32, false),
+        ],
+        StreamFormat::IirState => {
+            &[(F32, true), (F32, true), (F32, true), (F32, true)]
+        }
+        StreamFormat::LockinIq => &[(I32, false), (I32, false)],
+        StreamFormat::PhaseFrequency => &[(Phase, false), (I32, false)],
+        StreamFormat::SignalGenerator => &[(I16, true), (I16, true)],
+        StreamFormat::Unknown | StreamFormat::Fls => return None,
+    })
+}
+
+/// The CIC filter state of a signal.
+#[derive(Copy, Clone, Debug, Default)]
+struct Cic {
+    integrators: [i64; 3],
+    combs: [i64; 3],
+    count: u32,
+    index: usize,
+}
+
+impl Cic {
+    // Filter an input value, returning a decimated output value at the end of each period.
+    fn update(
+        &mut self,
+        x: i64,
+        value: Value,
+        order: usize,
+        log2: u8,
+    ) -> Option<i64> {
+        if let Value::Phase = value {
+            // Keep the first value of each period.
+            if self.count == 0 {
+                self.integrators[0] = x;
+            }
+        } else {
+            let mut y = x;
+            for integrator in self.integrators[..order].iter_mut() {
+                *integrator = integrator.wrapping_add(y);
+                y = *integrator;
+            }
+        }
+
+        self.count += 1;
+        if self.count < 1 << log2 {
+            return None;
+        }
+        self.count = 0;
+
+        if let Value::Phase = value {
+            return Some(self.integrators[0]);
+        }
+
+        let mut y = self.integrators[order - 1];
+        for comb in self.combs[..order].iter_mut() {
+            let delayed = core::mem::replace(comb, y);
+            y = y.wrapping_sub(delayed);
+        }
+
+        // Note: The DC gain of the filter is `(1 << log2) ^ order`.
+        Some(y >> (log2 as usize * order))
+    }
+}
+
+/// A decimator of serialized stream batches.
+#[derive(Debug)]
+pub struct Decimator {
+    decimation: Decimation,
+    signals: [Cic; MAX_SIGNALS],
+    batches: u32,
+    output: [u8; MAX_BATCH_SIZE],
+}
+
+impl Default for Decimator {
+    fn default() -> Self {
+        Self {
+            decimation: Decimation::default(),
+            signals: [Cic::default(); MAX_SIGNALS],
+            batches: 0,
+            output: [0; MAX_BATCH_SIZE],
+        }
+    }
+}
+
+impl Decimator {
+    /// Get the decimation.
+    pub fn decimation(&self) -> Decimation {
+        self.decimation
+    }
+
+    /// Configure the decimation. The filter state is reset.
+    ///
+    /// # Args
+    /// * `decimation` - The new decimation.
+    pub fn configure(&mut self, decimation: Decimation) {
+        *self = Self {
+            decimation,
+            ..Default::default()
+        };
+    }
+
+    /// Reset the filter state, e.g. after a change of the stream format.
+    pub fn reset(&mut self) {
+        self.configure(self.decimation);
+    }
+
+    /// Decimate a serialized batch.
+    ///
+    /// # Args
+    /// * `format` - The stream format code of the batch.
+    /// * `batch_size` - The number of samples in the batch.
+    /// * `batch` - The serialized batch.
+    ///
+    /// # Returns
+    /// A decimated batch of the same size as the input batch after every decimation period. If the
+    /// batch cannot be decimated (see [is_supported]), it is returned unmodified.
+    pub fn update<'a>(
+        &'a mut self,
+        format: u8,
+        batch_size: usize,
+        batch: &'a [u8],
+    ) -> Option<&'a [u8]> {
+        let log2 = self.decimation.log2;
+        let order = self.decimation.filter.order();
+        let layout = match layout(format) {
+            Some(layout) if log2 > 0 && batch.len() <= MAX_BATCH_SIZE => layout,
+            _ => return Some(batch),
+        };
+
+        let mut offset = 0;
+        for ((value, per_sample), cic) in
+            layout.iter().zip(self.signals.iter_mut())
+        {
+            let count = if *per_sample { batch_size } else { 1 };
+            let size = value.size();
+            for bytes in batch[offset..offset + count * size].chunks_exact(size)
+            {
+                if let Some(y) =
+                    cic.update(value.decode(bytes), *value, order, log2)
+                {
+                    // Note: Each signal has `count` values per decimation period.
+                    let index = offset + (cic.index % count) * size;
+                    value.encode(y, &mut self.output[index..index + size]);
+                    cic.index += 1;
+                }
+            }
+            offset += count * size;
+        }
+
+        self.batches += 1;
+        if self.batches < 1 << log2 {
+            return None;
+        }
+        self.batches = 0;
+        for cic in self.signals.iter_mut() {
+            cic.index = 0;
+        }
+
+        Some(&self.output[..batch.len()])
+    }
+}
@@ -0,0 +1,497 @@
+//! MQTT broker hostname resolution
+//!
+//! # Design
+//! The MQTT broker may be configured as a hostname (see [config](super::config)). The hostname is
+//! resolved to an IPv4 address by the [Resolver] using DNS queries over UDP on the shared network
+//! stack. Queries are sent once the interface has an IP address, i.e. after DHCP completed.
+//!
+//! The MQTT clients are constructed before the broker address is known and connect through a
+//! [BrokerStack]. It substitutes the latest resolved broker address whenever a client connects. Every
+//! (re)connection of a client also requests the hostname to be resolved again, so a changed broker
+//! address is picked up on the next reconnection.
+//!
+//! Hostnames are only resolved to IPv4 addresses. An IPv6 broker is configured using its address.
+//!
+//! # Note
+//! The name servers acquired using DHCP are not accessible from the network stack. Unless a name
+//! server is configured, queries are sent to the default gateway, which commonly forwards DNS
+//! queries.
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use heapless::{String, Vec};
+use minimq::embedded_time::Clock;
+use smoltcp_nal::{
+    embedded_nal::{
+        nb, IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpClientStack,
+        UdpClientStack,
+    },
+    smoltcp::wire::{IpAddress, IpCidr, Ipv4Address},
+    NetworkError,
+};
+
+use super::{config::MAX_NAME_LENGTH, ipv6, NetworkReference};
+use crate::hardware::SystemTimer;
+
+// The UDP port of DNS name servers.
+const DNS_PORT: u16 = 53;
+
+// The size of the DNS message header in bytes.
+const HEADER_SIZE: usize = 12;
+
+// The maximum size of a query: the header, the encoded hostname and the query type and class.
+const MAX_QUERY_SIZE: usize = HEADER_SIZE + MAX_NAME_LENGTH + 2 + 4;
+
+// The maximum size of a DNS message over UDP.
+const MAX_RESPONSE_SIZE: usize = 512;
+
+// The record type and class of IPv4 host addresses.
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+
+// The time to wait for a response in milliseconds.
+const QUERY_TIMEOUT: u32 = 2_000;
+
+// The minimum time between queries in milliseconds.
+const QUERY_INTERVAL: u32 = 5_000;
+
+/// Errors of a DNS query.
+#[derive(Copy, Clone, Debug)]
+pub enum Error {
+    /// The response is malformed.
+    Malformed,
+    /// The name server responded with an error code.
+    Server(u8),
+    /// The hostname does not have an IPv4 address.
+    NotFound,
+    /// The network stack reported an error.
+    Network(NetworkError),
+}
+
+/// The MQTT broker address shared between the resolver and the MQTT clients.
+#[derive(Debug, Default)]
+pub struct Broker {
+    // The IPv4 address of the broker. Zero if unresolved.
+    address: AtomicU32,
+    // The IPv6 address of the broker if it is configured as an IPv6 address.
+    ipv6: Option<Ipv6Addr>,
+    // The TCP port of the broker.
+    port: u16,
+    // Set if an MQTT client is connecting to the broker.
+    connecting: AtomicBool,
+}
+
+impl Broker {
+    /// Get the resolved address of the broker.
+    pub fn address(&self) -> Option<IpAddr> {
+        if let Some(address) = self.ipv6 {
+            return Some(IpAddr::V6(address));
+        }
+
+        let address = Ipv4Addr::from(self.address.load(Ordering::Relaxed));
+        (!address.is_unspecified()).then_some(IpAddr::V4(address))
+    }
+
+    fn set_address(&self, address: Ipv4Addr) {
+        self.address.store(address.into(), Ordering::Relaxed);
+    }
+}
+
+/// A TCP stack connecting MQTT clients to the resolved broker.
+pub struct BrokerStack {
+    stack: NetworkReference,
+    broker: &'static Broker,
+}
+
+impl BrokerStack {
+    /// The placeholder broker address for MQTT clients. It is replaced by the resolved broker
+    /// address and the configured broker port when connecting.
+    pub const ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0));
+
+    /// Construct the stack.
+    ///
+    /// # Args
+    /// * `stack` - The network stack.
+    /// * `broker` - The broker address.
+    pub fn new(stack: NetworkReference, broker: &'static Broker) -> Self {
+        Self { stack, broker }
+    }
+}
+
+impl TcpClientStack for BrokerStack {
+    type TcpSocket = <NetworkReference as TcpClientStack>::TcpSocket;
+    type Error = NetworkError;
+
+    fn socket(&mut self) -> Result<Self::TcpSocket, Self::Error> {
+        TcpClientStack::socket(&mut self.stack)
+    }
+
+    fn connect(
+        &mut self,
+        socket: &mut Self::TcpSocket,
+        _remote: SocketAddr,
+    ) -> nb::Result<(), Self::Error> {
+        self.broker.connecting.store(true, Ordering::Relaxed);
+
+        // Note: The error is ignored by the MQTT clients, just like an interface without address.
+        let address = self
+            .broker
+            .address()
+            .ok_or(nb::Error::Other(NetworkError::NoIpAddress))?;
+
+        ipv6::connect(
+            &mut self.stack,
+            socket,
+            SocketAddr::new(address, self.broker.port),
+        )
+    }
+
+    fn is_connected(
+        &mut self,
+        socket: &Self::TcpSocket,
+    ) -> Result<bool, Self::Error> {
+        self.stack.is_connected(socket)
+    }
+
+    fn send(
+        &mut self,
+        socket: &mut Self::TcpSocket,
+        buffer: &[u8],
+    ) -> nb::Result<usize, Self::Error> {
+        TcpClientStack::send(&mut self.stack, socket, buffer)
+    }
+
+    fn receive(
+        &mut self,
+        socket: &mut Self::TcpSocket,
+        buffer: &mut [u8],
+    ) -> nb::Result<usize, Self::Error> {
+        TcpClientStack::receive(&mut self.stack, socket, buffer)
+    }
+
+    fn close(&mut self, socket: Self::TcpSocket) -> Result<(), Self::Error> {
+        TcpClientStack::close(&mut self.stack, socket)
+    }
+}
+
+/// A pending DNS query.
+struct Query {
+    socket: <NetworkReference as UdpClientStack>::UdpSocket,
+    id: u16,
+    sent: u32,
+}
+
+/// The resolver of the broker hostname.
+pub struct Resolver {
+    stack: NetworkReference,
+    clock: SystemTimer,
+    broker: &'static Broker,
+    hostname: Option<String<MAX_NAME_LENGTH>>,
+    server: Ipv4Addr,
+    query: Option<Query>,
+    last: Option<u32>,
+}
+
+impl Resolver {
+    /// Construct the resolver.
+    ///
+    /// # Args
+    /// * `stack` - The network stack to send queries on.
+    /// * `clock` - A `SystemTimer` implementing `Clock`.
+    /// * `broker` - The broker hostname or IP address.
+    /// * `port` - The TCP port of the broker.
+    /// * `server` - The name server. If unspecified, the default gateway is used.
+    ///
+    /// # Returns
+    /// The resolver and the broker address shared with the MQTT clients.
+    pub fn new(
+        stack: NetworkReference,
+        clock: SystemTimer,
+        broker: &str,
+        port: u16,
+        server: Ipv4Addr,
+    ) -> (Self, &'static Broker) {
+        let ip = broker.parse::<IpAddr>().ok();
+        let ipv6 = match ip {
+            Some(IpAddr::V6(ip)) => Some(ip),
+            _ => None,
+        };
+
+        // Note(unwrap): The network users are only constructed once.
+        let address: &'static Broker = cortex_m::singleton!(: Broker = Broker {
+            ipv6,
+            port,
+            ..Default::default()
+        })
+        .unwrap();
+
+        // A broker IP address does not need to be resolved.
+        let hostname = match ip {
+            Some(IpAddr::V4(ip)) => {
+                address.set_address(ip);
+                None
+            }
+            Some(IpAddr::V6(_)) => None,
+            None => Some(String::from(broker)),
+        };
+
+        let resolver = Self {
+            stack,
+            clock,
+            broker: address,
+            hostname,
+            server,
+            query: None,
+            last: None,
+        };
+
+        (resolver, address)
+    }
+
+    /// Resolve the broker hostname if it is unresolved or an MQTT client is connecting.
+    ///
+    /// # Note
+    /// This should be called regularly to receive responses.
+    pub fn update(&mut self) {
+        let hostname = match self.hostname.as_ref() {
+            Some(hostname) => hostname,
+            None => return,
+        };
+
+        // Note(unwrap): The system timer never fails.
+        let now = self
+            .clock
+            .try_now()
+            .unwrap()
+            .duration_since_epoch()
+            .integer();
+
+        if let Some(query) = self.query.as_mut() {
+            let result = match receive(&mut self.stack, query) {
+                Ok(result) => result,
+                Err(error) => Some(Err(error)),
+            };
+
+            match result {
+                Some(Ok(address)) => {
+                    if self.broker.address() != Some(address.into()) {
+                        log::info!("Resolved {} to {}", hostname, address);
+                    }
+                    self.broker.set_address(address);
+                }
+                Some(Err(error)) => {
+                    log::warn!("Failed to resolve {}: {:?}", hostname, error)
+                }
+                None if now.wrapping_sub(query.sent) > QUERY_TIMEOUT => {
+                    log::warn!("DNS query for {} timed out", hostname)
+                }
+                None => return,
+            }
+
+            // Note(unwrap): The query was checked to exist above.
+            let query = self.query.take().unwrap();
+            UdpClientStack::close(&mut self.stack, query.socket).ok();
+            return;
+        }
+
+        let due = self.broker.address().is_none()
+            || self.broker.connecting.load(Ordering::Relaxed);
+        let ready = self
+            .last
+            .map(|last| now.wrapping_sub(last) >= QUERY_INTERVAL)
+            .unwrap_or(true);
+        if !(due && ready) {
+            return;
+        }
+
+        let server = if self.server.is_unspecified() {
+            match default_gateway(&mut self.stack) {
+                Some(gateway) => gateway,
+                None => return,
+            }
+        } else {
+            self.server
+        };
+
+        self.last = Some(now);
+        self.broker.connecting.store(false, Ordering::Relaxed);
+
+        match send(&mut self.stack, server, hostname, now as u16) {
+            Ok(socket) => {
+                self.query = Some(Query {
+                    socket,
+                    id: now as u16,
+                    sent: now,
+                })
+            }
+            // Note: Queries are only possible once the interface has an IP address.
+            Err(Error::Network(NetworkError::NoIpAddress)) => {}
+            Err(error) => {
+                log::warn!("Failed to query {}: {:?}", hostname, error)
+            }
+        }
+    }
+}
+
+/// Get the default gateway of the interface, if any.
+fn default_gateway(stack: &mut NetworkReference) -> Option<Ipv4Addr> {
+    let mut gateway = None;
+    stack.lock(|stack| {
+        stack.interface_mut().routes_mut().update(|routes| {
+            let default = IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0);
+            if let Some(route) = routes.get(&default) {
+                if let IpAddress::Ipv4(address) = route.via_router {
+                    gateway = Some(Ipv4Addr::from(address.0));
+                }
+            }
+        })
+    });
+
+    gateway.filter(|gateway| !gateway.is_unspecified())
+}
+
+/// Send a query for the IPv4 address of a hostname.
+///
+/// # Returns
+/// The socket the query was sent on.
+fn send(
+    stack: &mut NetworkReference,
+    server: Ipv4Addr,
+    hostname: &str,
+    id: u16,
+) -> Result<<NetworkReference as UdpClientStack>::UdpSocket, Error> {
+    let query = encode_query(hostname, id).ok_or(Error::Malformed)?;
+
+    let mut socket = UdpClientStack::socket(stack).map_err(Error::Network)?;
+    let result = UdpClientStack::connect(
+        stack,
+        &mut socket,
+        SocketAddr::new(server.into(), DNS_PORT),
+    )
+    .map_err(Error::Network)
+    .and_then(|_| {
+        UdpClientStack::send(stack, &mut socket, &query).map_err(|error| {
+            match error {
+                nb::Error::WouldBlock => {
+                    Error::Network(NetworkError::WriteFailure)
+                }
+                nb::Error::Other(error) => Error::Network(error),
+            }
+        })
+    });
+
+    match result {
+        Ok(()) => Ok(socket),
+        Err(error) => {
+            UdpClientStack::close(stack, socket).ok();
+            Err(error)
+        }
+    }
+}
+
+/// Receive the response to a query.
+///
+/// # Returns
+/// The result of the query if a response was received.
+fn receive(
+    stack: &mut NetworkReference,
+    query: &mut Query,
+) -> Result<Option<Result<Ipv4Addr, Error>>, Error> {
+    let mut buffer = [0; MAX_RESPONSE_SIZE];
+    loop {
+        match UdpClientStack::receive(stack, &mut query.socket, &mut buffer) {
+            Ok((len, _)) => {
+                let response = &buffer[..len];
+
+                // Ignore responses to other queries.
+                if response.get(..2) == Some(&query.id.to_be_bytes()) {
+                    return Ok(Some(decode_response(response)));
+                }
+            }
+            Err(nb::Error::WouldBlock) => return Ok(None),
+            Err(nb::Error::Other(error)) => return Err(Error::Network(error)),
+        }
+    }
+}
+
+/// Encode a recursive query for the IPv4 address of a hostname.
+fn encode_query(hostname: &str, id: u16) -> Option<Vec<u8, MAX_QUERY_SIZE>> {
+    let mut query = Vec::new();
+
+    // Header: a single question, recursion desired.
+    for word in [id, 0x0100, 1, 0, 0, 0] {
+        query.extend_from_slice(&word.to_be_bytes()).ok()?;
+    }
+
+    for label in hostname.split('.').filter(|label| !label.is_empty()) {
+        if label.len() > 63 {
+            return None;
+        }
+        query.push(label.len() as u8).ok()?;
+        query.extend_from_slice(label.as_bytes()).ok()?;
+    }
+    query.push(0).ok()?;
+
+    for word in [TYPE_A, CLASS_IN] {
+        query.extend_from_slice(&word.to_be_bytes()).ok()?;
+    }
+
+    Some(query)
+}
+
+/// Decode the first IPv4 address from the response to a query.
+fn decode_response(response: &[u8]) -> Result<Ipv4Addr, Error> {
+    let word = |offset: usize| {
+        response
+            .get(offset..offset + 2)
+            .map(|word| u16::from_be_bytes([word[0], word[1]]))
+            .ok_or(Error::Malformed)
+    };
+
+    let flags = word(2)?;
+    if flags & 0x8000 == 0 {
+        return Err(Error::Malformed);
+    }
+    match flags & 0xF {
+        0 => {}
+        3 => return Err(Error::NotFound),
+        code => return Err(Error::Server(code as u8)),
+    }
+
+    let questions = word(4)?;
+    let answers = word(6)?;
+
+    let mut offset = HEADER_SIZE;
+    for _ in 0..questions {
+        // Skip the name, type and class.
+        offset = skip_name(response, offset)? + 4;
+    }
+
+    for _ in 0..answers {
+        offset = skip_name(response, offset)?;
+        let kind = word(offset)?;
+        let class = word(offset + 2)?;
+        let len = word(offset + 8)? as usize;
+        offset += 10;
+
+        let data =
+            response.get(offset..offset + len).ok_or(Error::Malformed)?;
+        if kind == TYPE_A && class == CLASS_IN && len == 4 {
+            return Ok(Ipv4Addr::new(data[0], data[1], data[2], data[3]));
+        }
+        offset += len;
+    }
+
+    Err(Error::NotFound)
+}
+
+/// Get the offset following an encoded name.
+fn skip_name(message: &[u8], mut offset: usize) -> Result<usize, Error> {
+    loop {
+        match *message.get(offset).ok_or(Error::Malformed)? {
+            0 => return Ok(offset + 1),
+            // A compression pointer ends the name.
+            len if len & 0xC0 == 0xC0 => return Ok(offset + 2),
+            len => offset += 1 + len as usize,
+        }
+    }
+}
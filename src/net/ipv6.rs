@@ -0,0 +1,170 @@
+//! IPv6 support
+//!
+//! # Design
+//! The network interface has a link-local IPv6 address derived from its MAC address (modified
+//! EUI-64, RFC 4291). Stateless address autoconfiguration (SLAAC) of further addresses is not
+//! supported by the network stack.
+//!
+//! The stream target and the MQTT broker may be IPv6 endpoints. The TCP and UDP clients of the
+//! network stack only connect to IPv4 endpoints. Connections to IPv6 endpoints are therefore
+//! established directly on the underlying sockets using the functions in this module.
+//!
+//! # Note
+//! The network stack refuses any use of its TCP sockets while the interface does not have an IPv4
+//! address. TCP connections over IPv6 (MQTT and TCP streams) are only possible once the interface
+//! also has an IPv4 address, i.e. after DHCP completed or with a static IP. UDP streams over IPv6
+//! do not have this restriction.
+use core::sync::atomic::{AtomicU16, Ordering};
+
+use smoltcp_nal::{
+    embedded_nal::{nb, IpAddr, SocketAddr, TcpClientStack},
+    smoltcp::{
+        iface::SocketHandle,
+        socket::{Socket, TcpSocket},
+        wire::{
+            EthernetAddress, IpAddress, IpEndpoint, Ipv4Address, Ipv6Address,
+            Ipv6Cidr,
+        },
+    },
+    NetworkError,
+};
+
+use super::NetworkReference;
+use crate::hardware::NetworkStack;
+
+// The start of the dynamic (ephemeral) port range.
+const DYNAMIC_PORTS_START: u16 = 49152;
+
+// The next candidate for an ephemeral port.
+static NEXT_PORT: AtomicU16 = AtomicU16::new(DYNAMIC_PORTS_START);
+
+/// Get the link-local IPv6 address of an interface.
+///
+/// # Args
+/// * `mac` - The MAC address of the interface.
+///
+/// # Returns
+/// The address in `fe80::/64` with the modified EUI-64 interface identifier of the MAC address.
+pub fn link_local(mac: EthernetAddress) -> Ipv6Cidr {
+    let mac = mac.0;
+    let address = Ipv6Address([
+        0xfe,
+        0x80,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        mac[0] ^ 0x02,
+        mac[1],
+        mac[2],
+        0xff,
+        0xfe,
+        mac[3],
+        mac[4],
+        mac[5],
+    ]);
+    Ipv6Cidr::new(address, 64)
+}
+
+/// Convert a socket address to the endpoint of a socket.
+pub fn endpoint(address: SocketAddr) -> IpEndpoint {
+    let ip = match address.ip() {
+        IpAddr::V4(ip) => IpAddress::Ipv4(Ipv4Address(ip.octets())),
+        IpAddr::V6(ip) => IpAddress::Ipv6(Ipv6Address(ip.octets())),
+    };
+    IpEndpoint::new(ip, address.port())
+}
+
+/// Check if the interface has an address to reach a remote address from.
+///
+/// # Note
+/// The link-local IPv6 address is always available. An IPv4 address is only available once it is
+/// configured.
+pub fn has_address(stack: &mut NetworkReference, remote: IpAddr) -> bool {
+    match remote {
+        IpAddr::V4(_) => has_ipv4_address(stack),
+        IpAddr::V6(_) => true,
+    }
+}
+
+// Check if the interface has an IPv4 address.
+fn has_ipv4_address(stack: &mut NetworkReference) -> bool {
+    stack.lock(|stack| {
+        stack
+            .interface()
+            .ipv4_addr()
+            .map(|address| !address.is_unspecified())
+            .unwrap_or(false)
+    })
+}
+
+/// Get an unused local port from the dynamic port range.
+pub fn ephemeral_port(stack: &NetworkStack) -> u16 {
+    loop {
+        let port = NEXT_PORT.fetch_add(1, Ordering::Relaxed);
+        if port == u16::MAX {
+            NEXT_PORT.store(DYNAMIC_PORTS_START, Ordering::Relaxed);
+        }
+        if port < DYNAMIC_PORTS_START {
+            continue;
+        }
+
+        let used = stack.interface().sockets().any(|(_, socket)| {
+            let local = match socket {
+                Socket::Tcp(socket) => socket.local_endpoint(),
+                Socket::Udp(socket) => socket.endpoint(),
+                _ => return false,
+            };
+            local.port == port
+        });
+        if !used {
+            return port;
+        }
+    }
+}
+
+/// Connect a TCP socket of the network stack to an IPv4 or IPv6 remote.
+///
+/// # Note
+/// IPv4 connections are established by the network stack. Connecting an already connecting
+/// socket has no effect.
+///
+/// # Args
+/// * `stack` - The network stack.
+/// * `socket` - The TCP socket of the network stack to connect.
+/// * `remote` - The remote endpoint to connect to.
+pub fn connect(
+    stack: &mut NetworkReference,
+    socket: &mut SocketHandle,
+    remote: SocketAddr,
+) -> nb::Result<(), NetworkError> {
+    if let IpAddr::V4(_) = remote.ip() {
+        return TcpClientStack::connect(stack, socket, remote);
+    }
+
+    // Note: Like the network stack, IPv6 connections require an IPv4 address to be able to use
+    // the socket.
+    if !has_ipv4_address(stack) {
+        return Err(nb::Error::Other(NetworkError::NoIpAddress));
+    }
+
+    stack.lock(|stack| {
+        if stack
+            .interface_mut()
+            .get_socket::<TcpSocket>(*socket)
+            .is_open()
+        {
+            return Ok(());
+        }
+
+        let port = ephemeral_port(stack);
+        let (internal_socket, context) = stack
+            .interface_mut()
+            .get_socket_and_context::<TcpSocket>(*socket);
+        internal_socket
+            .connect(context, endpoint(remote), port)
+            .map_err(|_| nb::Error::Other(NetworkError::ConnectionFailure))
+    })
+}
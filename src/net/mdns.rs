@@ -0,0 +1,411 @@
+//! mDNS/DNS-SD device announcement
+//!
+//! # Design
+//! Stabilizer announces itself on the local network using multicast DNS (RFC 6762) and DNS service
+//! discovery (RFC 6763), so that devices and their MQTT prefix can be found without MQTT.
+//!
+//! The following records are announced, e.g. for `dual-iir` on a device with MAC address
+//! `04-91-62-d9-7e-5f`:
+//!
+//! * `stabilizer-04-91-62-d9-7e-5f.local` A: the IPv4 address of the device.
+//! * `_stabilizer._udp.local` PTR: the service instance `dual-iir-04-91-62-d9-7e-5f`.
+//! * `dual-iir-04-91-62-d9-7e-5f._stabilizer._udp.local` SRV: the hostname above. The port is zero
+//!   as the service does not accept connections.
+//! * `dual-iir-04-91-62-d9-7e-5f._stabilizer._udp.local` TXT: `app=<app>`, `version=<version>` and
+//!   `prefix=<MQTT prefix>`.
+//! * `_services._dns-sd._udp.local` PTR: `_stabilizer._udp.local` for service type enumeration.
+//!
+//! The records are announced twice whenever the device acquires an IP address. Queries for any of
+//! the records are answered with all records.
+//!
+//! # Note
+//! Probing for name conflicts is not performed as the names are unique by the MAC address. Only
+//! multicast queries from port 5353 are answered. Legacy unicast queries are ignored.
+use heapless::{String, Vec};
+use minimq::embedded_time::Clock;
+use smoltcp_nal::smoltcp::{
+    iface::SocketHandle,
+    socket::UdpSocket,
+    wire::{EthernetAddress, IpEndpoint, Ipv4Address},
+};
+
+use super::NetworkReference;
+use crate::hardware::SystemTimer;
+use core::fmt::Write;
+
+/// The mDNS IPv4 multicast group.
+pub const MDNS_GROUP: Ipv4Address = Ipv4Address([224, 0, 0, 251]);
+
+// The mDNS UDP port.
+const MDNS_PORT: u16 = 5353;
+
+// The service type labels.
+const SERVICE: [&str; 2] = ["_stabilizer", "_udp"];
+
+// The service type enumeration labels.
+const SERVICES: [&str; 3] = ["_services", "_dns-sd", "_udp"];
+
+// The domain of all names.
+const DOMAIN: &str = "local";
+
+// The size of the DNS message header in bytes.
+const HEADER_SIZE: usize = 12;
+
+// The maximum size of an mDNS message.
+const MAX_MESSAGE_SIZE: usize = 512;
+
+// The record types and class.
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+
+// The class flag of records that are unique to this device.
+const CACHE_FLUSH: u16 = 0x8000;
+
+// The time to live of the records in seconds.
+const TTL: u32 = 120;
+
+// The number of announcements and the time between them in milliseconds.
+const ANNOUNCEMENTS: u8 = 2;
+const ANNOUNCEMENT_INTERVAL: u32 = 1_000;
+
+/// The mDNS responder.
+pub struct Mdns {
+    stack: NetworkReference,
+    clock: SystemTimer,
+    socket: SocketHandle,
+    hostname: String<32>,
+    instance: String<48>,
+    txt: [String<136>; 3],
+    address: Option<Ipv4Address>,
+    announcements: u8,
+    last: u32,
+}
+
+impl Mdns {
+    /// Construct the mDNS responder.
+    ///
+    /// # Args
+    /// * `stack` - The network stack.
+    /// * `clock` - A `SystemTimer` implementing `Clock`.
+    /// * `socket` - The UDP socket for mDNS, which is not managed by the network stack.
+    /// * `app` - The name of the application.
+    /// * `mac` - The MAC address of the device.
+    /// * `prefix` - The MQTT prefix of the device.
+    pub fn new(
+        stack: NetworkReference,
+        clock: SystemTimer,
+        socket: SocketHandle,
+        app: &str,
+        mac: EthernetAddress,
+        prefix: &str,
+    ) -> Self {
+        // Note(unwrap): The names are sufficiently long for the application names and MAC
+        // addresses.
+        let mut hostname = String::new();
+        write!(&mut hostname, "stabilizer-{mac}").unwrap();
+        let mut instance = String::new();
+        write!(&mut instance, "{app}-{mac}").unwrap();
+
+        let mut txt: [String<136>; 3] = Default::default();
+        write!(&mut txt[0], "app={app}").unwrap();
+        write!(&mut txt[1], "version={}", env!("CARGO_PKG_VERSION")).unwrap();
+        write!(&mut txt[2], "prefix={prefix}").unwrap();
+
+        Self {
+            stack,
+            clock,
+            socket,
+            hostname,
+            instance,
+            txt,
+            address: None,
+            announcements: 0,
+            last: 0,
+        }
+    }
+
+    /// Announce the device and answer queries.
+    ///
+    /// # Note
+    /// This should be called regularly to answer queries.
+    pub fn update(&mut self) {
+        // Note(unwrap): The system timer never fails.
+        let now = self
+            .clock
+            .try_now()
+            .unwrap()
+            .duration_since_epoch()
+            .integer();
+
+        let handle = self.socket;
+        let (hostname, instance) = (&self.hostname, &self.instance);
+        let (address, query) = self.stack.lock(|stack| {
+            let address = stack
+                .interface()
+                .ipv4_address()
+                .filter(|address| !address.is_unspecified());
+
+            let socket = stack.interface_mut().get_socket::<UdpSocket>(handle);
+
+            // Note: The network stack closes all sockets when the IP address changes.
+            if !socket.is_open() {
+                socket.bind(MDNS_PORT).ok();
+            }
+
+            let mut buffer = [0; MAX_MESSAGE_SIZE];
+            let mut query = false;
+            while let Ok((len, source)) = socket.recv_slice(&mut buffer) {
+                query |= source.port == MDNS_PORT
+                    && is_query(&buffer[..len], hostname, instance)
+                        .unwrap_or(false);
+            }
+
+            (address, query)
+        });
+
+        // Announce the records whenever a new address is acquired.
+        if address != self.address {
+            self.address = address;
+            self.announcements = 0;
+        }
+
+        let address = match address {
+            Some(address) => address,
+            None => return,
+        };
+
+        let announce = self.announcements < ANNOUNCEMENTS
+            && (self.announcements == 0
+                || now.wrapping_sub(self.last) >= ANNOUNCEMENT_INTERVAL);
+        if !(announce || query) {
+            return;
+        }
+
+        if announce {
+            self.announcements += 1;
+            self.last = now;
+        }
+
+        let response = match self.response(address) {
+            Some(response) => response,
+            None => {
+                log::warn!("mDNS response too large");
+                return;
+            }
+        };
+
+        self.stack.lock(|stack| {
+            stack
+                .interface_mut()
+                .get_socket::<UdpSocket>(handle)
+                .send_slice(
+                    &response,
+                    IpEndpoint::new(MDNS_GROUP.into(), MDNS_PORT),
+                )
+                .map_err(|error| log::warn!("mDNS send error: {:?}", error))
+                .ok();
+        });
+    }
+
+    /// Build a response containing all records.
+    fn response(
+        &self,
+        address: Ipv4Address,
+    ) -> Option<Vec<u8, MAX_MESSAGE_SIZE>> {
+        let hostname = [self.hostname.as_str(), DOMAIN];
+        let service = [SERVICE[0], SERVICE[1], DOMAIN];
+        let instance = [self.instance.as_str(), SERVICE[0], SERVICE[1], DOMAIN];
+        let services = [SERVICES[0], SERVICES[1], SERVICES[2], DOMAIN];
+
+        let mut message = Message(Vec::new());
+
+        // Header: an authoritative response with five answers.
+        for word in [0, 0x8400, 0, 5, 0, 0] {
+            message.word(word)?;
+        }
+
+        message.record(&hostname, TYPE_A, CACHE_FLUSH, |data| {
+            data.bytes(&address.0)
+        })?;
+
+        message.record(&service, TYPE_PTR, 0, |data| data.name(&instance))?;
+
+        message.record(&instance, TYPE_SRV, CACHE_FLUSH, |data| {
+            // Priority, weight and port.
+            for word in [0, 0, 0] {
+                data.word(word)?;
+            }
+            data.name(&hostname)
+        })?;
+
+        message.record(&instance, TYPE_TXT, CACHE_FLUSH, |data| {
+            for txt in self.txt.iter() {
+                data.string(txt)?;
+            }
+            Some(())
+        })?;
+
+        message.record(&services, TYPE_PTR, 0, |data| data.name(&service))?;
+
+        Some(message.0)
+    }
+}
+
+/// Check whether a message is a query for any of the records.
+///
+/// # Args
+/// * `message` - The received message.
+/// * `hostname` - The hostname label of the device.
+/// * `instance` - The service instance label of the device.
+fn is_query(message: &[u8], hostname: &str, instance: &str) -> Option<bool> {
+    let word = |offset: usize| {
+        message
+            .get(offset..offset + 2)
+            .map(|word| u16::from_be_bytes([word[0], word[1]]))
+    };
+
+    // Only standard queries are answered.
+    if word(2)? & 0xF800 != 0 {
+        return Some(false);
+    }
+
+    let hostname = [hostname, DOMAIN];
+    let service = [SERVICE[0], SERVICE[1], DOMAIN];
+    let instance = [instance, SERVICE[0], SERVICE[1], DOMAIN];
+    let services = [SERVICES[0], SERVICES[1], SERVICES[2], DOMAIN];
+
+    let mut offset = HEADER_SIZE;
+    for _ in 0..word(4)? {
+        let (name, next) = Name::read(message, offset)?;
+        let kind = word(next)?;
+        offset = next + 4;
+
+        let matches = |labels: &[&str], types: &[u16]| {
+            (kind == TYPE_ANY || types.contains(&kind))
+                && name.matches(message, labels)
+        };
+
+        if matches(&hostname, &[TYPE_A])
+            || matches(&service, &[TYPE_PTR])
+            || matches(&instance, &[TYPE_SRV, TYPE_TXT])
+            || matches(&services, &[TYPE_PTR])
+        {
+            return Some(true);
+        }
+    }
+
+    Some(false)
+}
+
+/// A DNS message being built.
+struct Message(Vec<u8, MAX_MESSAGE_SIZE>);
+
+impl Message {
+    fn bytes(&mut self, bytes: &[u8]) -> Option<()> {
+        self.0.extend_from_slice(bytes).ok()
+    }
+
+    fn word(&mut self, word: u16) -> Option<()> {
+        self.bytes(&word.to_be_bytes())
+    }
+
+    fn string(&mut self, string: &str) -> Option<()> {
+        self.0.push(u8::try_from(string.len()).ok()?).ok()?;
+        self.bytes(string.as_bytes())
+    }
+
+    fn name(&mut self, labels: &[&str]) -> Option<()> {
+        for label in labels {
+            self.string(label)?;
+        }
+        self.0.push(0).ok()
+    }
+
+    fn record(
+        &mut self,
+        name: &[&str],
+        kind: u16,
+        flags: u16,
+        data: impl FnOnce(&mut Self) -> Option<()>,
+    ) -> Option<()> {
+        self.name(name)?;
+        self.word(kind)?;
+        self.word(CLASS_IN | flags)?;
+        self.bytes(&TTL.to_be_bytes())?;
+
+        // The data length is filled in after the data.
+        let start = self.0.len();
+        self.word(0)?;
+        data(self)?;
+        let len = u16::try_from(self.0.len() - start - 2).ok()?;
+        self.0[start..start + 2].copy_from_slice(&len.to_be_bytes());
+        Some(())
+    }
+}
+
+/// An encoded name within a received message.
+struct Name(usize);
+
+impl Name {
+    // The maximum number of compression pointers followed in a name.
+    const MAX_POINTERS: usize = 8;
+
+    /// Read a name.
+    ///
+    /// # Returns
+    /// The name and the offset following it.
+    fn read(message: &[u8], mut offset: usize) -> Option<(Self, usize)> {
+        let name = Self(offset);
+        loop {
+            match *message.get(offset)? {
+                0 => return Some((name, offset + 1)),
+                // A compression pointer ends the name.
+                len if len & 0xC0 == 0xC0 => return Some((name, offset + 2)),
+                len => offset += 1 + len as usize,
+            }
+        }
+    }
+
+    /// Check if the name matches the labels, ignoring case.
+    fn matches(&self, message: &[u8], labels: &[&str]) -> bool {
+        let mut offset = self.0;
+        let mut labels = labels.iter();
+        let mut pointers = 0;
+        loop {
+            let len = match message.get(offset) {
+                Some(len) => *len as usize,
+                None => return false,
+            };
+
+            if len & 0xC0 == 0xC0 {
+                pointers += 1;
+                offset = match message.get(offset + 1) {
+                    Some(low) if pointers <= Self::MAX_POINTERS => {
+                        ((len & 0x3F) << 8) | *low as usize
+                    }
+                    _ => return false,
+                };
+                continue;
+            }
+
+            let label = match message.get(offset + 1..offset + 1 + len) {
+                Some(label) => label,
+                None => return false,
+            };
+
+            match labels.next() {
+                None => return len == 0,
+                Some(expected)
+                    if expected.as_bytes().eq_ignore_ascii_case(label) =>
+                {
+                    offset += 1 + len
+                }
+                Some(_) => return false,
+            }
+        }
+    }
+}
@@ -5,31 +5,70 @@
 ///! telemetry (via MQTT), configuration of run-time settings (via MQTT + Miniconf), and live data
 ///! streaming over raw UDP/TCP sockets. This module encompasses the main processing routines
 ///! related to Stabilizer networking operations.
+///!
+///! Settings can additionally be stored to and restored from flash as named profiles using MQTT
+///! commands (see [command]). The default profile is restored automatically at boot. Boot-time
+///! settings (e.g. the sampling configuration) are applied by storing them as the default profile
+///! and restarting.
+///!
+///! Arbitrary waveforms for the signal generators are uploaded in chunks using MQTT commands. The
+///! uploaded waveforms are staged until they are committed to the application and can be stored to
+///! flash, from where they are restored at boot.
+///!
+///! The network configuration (see [config]) is restored at boot. It is changed using MQTT
+///! commands or, as a fallback, the RTT [console] and applied by restarting. A broker hostname is
+///! resolved using [dns]. The device announces itself on the local network using [mdns].
+///!
+///! The stream target and the MQTT broker may be IPv4 or IPv6 endpoints (see [ipv6]).
 pub use heapless;
 pub use miniconf;
 pub use serde;
 
+pub mod command;
+pub mod config;
+pub mod console;
 pub mod data_stream;
+pub mod decimation;
+pub mod dns;
+pub mod ipv6;
+pub mod mdns;
 pub mod network_processor;
 pub mod telemetry;
 
-use crate::hardware::{EthernetPhy, NetworkManager, NetworkStack, SystemTimer};
-use data_stream::{DataStream, FrameGenerator};
-use minimq::embedded_nal::IpAddr;
+use crate::dsp::signal_generator::Waveform;
+use crate::hardware::{
+    flash::Flash, setup::NetworkDevices, NetworkManager, NetworkStack,
+    SystemTimer,
+};
+use crate::settings::{self, ProfileName, SettingsStore};
+use crate::waveform::{self, WaveformStore};
+use command::{CommandClient, Response};
+use config::NetworkConfig;
+use console::Console;
+use data_stream::{
+    DataStream, FrameGenerator, StreamStatistics, StreamTarget,
+    MAX_FANOUT_TARGETS,
+};
+use dns::{BrokerStack, Resolver};
+use mdns::Mdns;
+use minimq::embedded_nal::Ipv4Addr;
 use network_processor::NetworkProcessor;
 use telemetry::TelemetryClient;
 
+use core::cell::RefCell;
 use core::fmt::Write;
 use heapless::String;
 use miniconf::Miniconf;
-use serde::Serialize;
-use smoltcp_nal::embedded_nal::SocketAddr;
+use serde::{Deserialize, Serialize};
 
 pub type NetworkReference =
     smoltcp_nal::shared::NetworkStackProxy<'static, NetworkStack>;
 
 /// The default MQTT broker IP address if unspecified.
-pub const DEFAULT_MQTT_BROKER: [u8; 4] = [10, 34, 16, 10];
+pub const DEFAULT_MQTT_BROKER: [u8; 4] = [10, 34, 16, 1];
+
+/// The maximum number of samples in each uploaded waveform chunk.
+pub const MAX_WAVEFORM_CHUNK: usize = 64;
 
 pub enum UpdateState {
     NoChange,
@@ -38,17 +77,89 @@ pub enum UpdateState {
 
 pub enum NetworkState {
     SettingsChanged(String<128>),
+    /// A restart was requested to apply the stored boot-time settings.
+    RestartRequested,
+    /// The staged waveform of the contained signal generator channel was committed.
+    WaveformChanged(usize),
     Updated,
     NoChange,
 }
 
+/// Run-time settings held by the Miniconf MQTT client.
+///
+/// # Note
+/// `miniconf::MqttClient` only provides immutable access to its settings. This wrapper allows the
+/// settings to be replaced as a whole (e.g. when restoring them from flash) while transparently
+/// exposing the [Miniconf] paths of the wrapped settings.
+#[derive(Clone, Default)]
+pub struct SettingsCell<S>(RefCell<S>);
+
+impl<S: Clone> SettingsCell<S> {
+    /// Get a copy of the current settings.
+    pub fn get(&self) -> S {
+        self.0.borrow().clone()
+    }
+
+    /// Replace the current settings.
+    pub fn replace(&self, settings: S) {
+        self.0.replace(settings);
+    }
+}
+
+impl<S: Miniconf> Miniconf for SettingsCell<S> {
+    fn set_path<'a, P: miniconf::Peekable<Item = &'a str>>(
+        &mut self,
+        path_parts: &'a mut P,
+        value: &[u8],
+    ) -> Result<usize, miniconf::Error> {
+        self.0.get_mut().set_path(path_parts, value)
+    }
+
+    fn get_path<'a, P: miniconf::Peekable<Item = &'a str>>(
+        &self,
+        path_parts: &'a mut P,
+        value: &mut [u8],
+    ) -> Result<usize, miniconf::Error> {
+        self.0.borrow().get_path(path_parts, value)
+    }
+
+    fn next_path<const TS: usize>(
+        state: &mut [usize],
+        path: &mut String<TS>,
+    ) -> Result<bool, miniconf::IterError> {
+        S::next_path(state, path)
+    }
+
+    fn metadata() -> miniconf::Metadata {
+        S::metadata()
+    }
+}
+
+/// A chunk of an uploaded arbitrary waveform.
+#[derive(Deserialize)]
+struct WaveformChunk {
+    /// The index of the first sample of the chunk within the waveform.
+    offset: usize,
+    /// The samples normalized to the signal amplitude.
+    samples: heapless::Vec<f32, MAX_WAVEFORM_CHUNK>,
+}
+
 /// A structure of Stabilizer's default network users.
 pub struct NetworkUsers<S: Default + Miniconf + Clone, T: Serialize> {
-    pub miniconf: miniconf::MqttClient<S, NetworkReference, SystemTimer, 512>,
+    pub miniconf:
+        miniconf::MqttClient<SettingsCell<S>, BrokerStack, SystemTimer, 512>,
     pub processor: NetworkProcessor,
     stream: DataStream,
     generator: Option<FrameGenerator>,
     pub telemetry: TelemetryClient<T>,
+    commands: CommandClient,
+    store: SettingsStore<Flash>,
+    profile: Option<ProfileName>,
+    waveforms: [Waveform; 2],
+    network: NetworkConfig,
+    console: Console,
+    resolver: Resolver,
+    mdns: Mdns,
 }
 
 impl<S, T> NetworkUsers<S, T>
@@ -59,23 +170,29 @@ where
     /// Construct Stabilizer's default network users.
     ///
     /// # Args
-    /// * `stack` - The network stack that will be used to share with all network users.
-    /// * `phy` - The ethernet PHY connecting the network.
+    /// * `net` - The network devices and the network configuration restored at boot.
     /// * `clock` - A `SystemTimer` implementing `Clock`.
     /// * `app` - The name of the application.
-    /// * `mac` - The MAC address of the network.
-    /// * `broker` - The IP address of the MQTT broker to use.
+    /// * `flash` - The flash used for storing settings.
     ///
     /// # Returns
     /// A new struct of network users.
     pub fn new(
-        stack: NetworkStack,
-        phy: EthernetPhy,
+        net: NetworkDevices,
         clock: SystemTimer,
         app: &str,
-        mac: smoltcp_nal::smoltcp::wire::EthernetAddress,
-        broker: IpAddr,
+        flash: Flash,
     ) -> Self {
+        let NetworkDevices {
+            stack,
+            phy,
+            mac_address: mac,
+            config,
+            console,
+            mdns,
+            stream,
+        } = net;
+
         let stack_manager =
             cortex_m::singleton!(: NetworkManager = NetworkManager::new(stack))
                 .unwrap();
@@ -83,59 +200,175 @@ where
         let processor =
             NetworkProcessor::new(stack_manager.acquire_stack(), phy);
 
-        let prefix = get_device_prefix(app, mac);
+        let (resolver, broker) = Resolver::new(
+            stack_manager.acquire_stack(),
+            clock,
+            &config.broker,
+            config.broker_port,
+            Ipv4Addr::from(config.dns),
+        );
 
-        let settings = miniconf::MqttClient::new(
+        let mut prefix = get_device_prefix(app, mac);
+        if !config.prefix.is_empty() {
+            // The settings topics must not exceed the maximum MQTT topic length.
+            if config.prefix.len()
+                + "/settings/".len()
+                + S::metadata().max_length
+                <= prefix.capacity()
+            {
+                prefix = String::from(config.prefix.as_str());
+            } else {
+                log::warn!("MQTT prefix too long, using {}", prefix);
+            }
+        }
+
+        let mdns = Mdns::new(
             stack_manager.acquire_stack(),
+            clock,
+            mdns,
+            app,
+            mac,
+            &prefix,
+        );
+
+        // Restore the default profile if it was stored.
+        let mut store = SettingsStore::new(flash, app);
+        let mut initial_settings = S::default();
+        let mut profile = None;
+        match store.load(settings::DEFAULT_PROFILE, &mut initial_settings) {
+            Ok(count) => {
+                log::info!("Restored {} stored settings", count);
+                profile = Some(ProfileName::from(settings::DEFAULT_PROFILE));
+            }
+            Err(settings::Error::NotFound) => {}
+            Err(error) => {
+                log::warn!("Failed to restore stored settings: {:?}", error);
+                initial_settings = S::default();
+            }
+        }
+
+        // Restore the stored waveforms.
+        let mut waveforms = [Waveform::default(), Waveform::default()];
+        for (channel, waveform) in waveforms.iter_mut().enumerate() {
+            match WaveformStore::new(store.flash().waveforms())
+                .load(channel, waveform)
+            {
+                Ok(count) => {
+                    log::info!(
+                        "Restored waveform {} ({} samples)",
+                        channel,
+                        count
+                    )
+                }
+                Err(waveform::Error::NotFound) => {}
+                Err(error) => log::warn!(
+                    "Failed to restore waveform {}: {:?}",
+                    channel,
+                    error
+                ),
+            }
+        }
+
+        let settings = miniconf::MqttClient::new(
+            BrokerStack::new(stack_manager.acquire_stack(), broker),
             &get_client_id(app, "settings", mac),
             &prefix,
-            broker,
+            BrokerStack::ADDRESS,
             clock,
-            S::default(),
+            SettingsCell(RefCell::new(initial_settings)),
         )
         .unwrap();
 
         let telemetry = TelemetryClient::new(
-            stack_manager.acquire_stack(),
+            BrokerStack::new(stack_manager.acquire_stack(), broker),
             clock,
             &get_client_id(app, "tlm", mac),
             &prefix,
-            broker,
+        );
+
+        let commands = CommandClient::new(
+            BrokerStack::new(stack_manager.acquire_stack(), broker),
+            clock,
+            &get_client_id(app, "cmd", mac),
+            &prefix,
         );
 
         let (generator, stream) =
-            data_stream::setup_streaming(stack_manager.acquire_stack());
+            data_stream::setup_streaming(stack_manager.acquire_stack(), stream);
 
         NetworkUsers {
             miniconf: settings,
             processor,
             telemetry,
+            commands,
+            store,
+            profile,
+            waveforms,
             stream,
             generator: Some(generator),
+            network: config,
+            console: Console::new(console),
+            resolver,
+            mdns,
         }
     }
 
+    /// Get a copy of the current run-time settings.
+    pub fn settings(&self) -> S {
+        self.miniconf.settings().get()
+    }
+
+    /// Get the name of the active settings profile.
+    ///
+    /// # Returns
+    /// The name of the profile that was last loaded or saved, if any.
+    pub fn profile(&self) -> Option<&ProfileName> {
+        self.profile.as_ref()
+    }
+
+    /// Get the statistics of the data stream.
+    pub fn stream_statistics(&self) -> StreamStatistics {
+        self.stream.statistics()
+    }
+
+    /// Get the staged waveform of a signal generator channel.
+    ///
+    /// # Args
+    /// * `channel` - The signal generator channel.
+    pub fn waveform(&self, channel: usize) -> &Waveform {
+        &self.waveforms[channel]
+    }
+
     /// Enable live data streaming.
     ///
     /// # Args
     /// * `format` - A unique u8 code indicating the format of the data.
+    /// * `batch_size` - The number of samples in each data batch.
+    /// * `sample_period` - The time between samples in nanoseconds.
     pub fn configure_streaming(
         &mut self,
         format: impl Into<u8>,
         batch_size: u8,
+        sample_period: u32,
     ) -> FrameGenerator {
         let mut generator = self.generator.take().unwrap();
-        generator.configure(format, batch_size);
+        generator.configure(format, batch_size, sample_period);
         generator
     }
 
-    /// Direct the stream to the provided remote target.
+    /// Direct the stream to the provided remote targets.
     ///
     /// # Args
-    /// * `remote` - The destination for the streamed data.
-    pub fn direct_stream(&mut self, remote: SocketAddr) {
+    /// * `target` - The destination and protocol for the streamed data.
+    /// * `fanout` - Additional UDP destinations for the streamed data.
+    pub fn direct_stream(
+        &mut self,
+        target: StreamTarget,
+        fanout: [StreamTarget; MAX_FANOUT_TARGETS],
+    ) {
         if self.generator.is_none() {
-            self.stream.set_remote(remote);
+            self.stream.set_target(target);
+            self.stream.set_fanout(fanout);
         }
     }
 
@@ -145,6 +378,10 @@ where
     /// An indication if any of the network users indicated a state change.
     /// The SettingsChanged option contains the path of the settings that changed.
     pub fn update(&mut self) -> NetworkState {
+        // Resolve the broker hostname and announce the device.
+        self.resolver.update();
+        self.mdns.update();
+
         // Update the MQTT clients.
         self.telemetry.update();
 
@@ -159,6 +396,134 @@ where
             UpdateState::Updated => NetworkState::Updated,
         };
 
+        // Handle commands.
+        let miniconf = &mut self.miniconf;
+        let store = &mut self.store;
+        let active = &mut self.profile;
+        let waveforms = &mut self.waveforms;
+        let network = &mut self.network;
+        let mut settings_loaded = false;
+        let mut restart = false;
+        let mut committed = None;
+
+        // The console only provides the network commands.
+        self.console.update(|command, payload| {
+            match command.strip_prefix("network/") {
+                Some(command) => network_command(
+                    command,
+                    payload,
+                    network,
+                    store.flash(),
+                    &mut restart,
+                ),
+                None => Response::error("Unknown command"),
+            }
+        });
+
+        self.commands.update(|command, payload| {
+            if let Some(command) = command.strip_prefix("network/") {
+                return network_command(
+                    command,
+                    payload,
+                    network,
+                    store.flash(),
+                    &mut restart,
+                );
+            }
+
+            if let Some(command) = command.strip_prefix("waveform/") {
+                return waveform_command(
+                    command,
+                    payload,
+                    waveforms,
+                    store.flash(),
+                    &mut committed,
+                );
+            }
+
+            // The payload of the profile commands is the profile name.
+            let name = match core::str::from_utf8(payload) {
+                Ok(name) if name.trim().is_empty() => settings::DEFAULT_PROFILE,
+                Ok(name) => name.trim(),
+                Err(_) => return Response::error("Invalid profile name"),
+            };
+
+            match command {
+                "settings/save" => {
+                    match store.save(name, miniconf.settings()) {
+                        Ok(_) => {
+                            *active = Some(ProfileName::from(name));
+                            Response::ok("Settings saved")
+                        }
+                        Err(error) => Response::from_error(error),
+                    }
+                }
+                "settings/load" => {
+                    let mut settings = S::default();
+                    match store.load(name, &mut settings) {
+                        Ok(_) => {
+                            miniconf.settings().replace(settings);
+                            miniconf.force_republish();
+                            *active = Some(ProfileName::from(name));
+                            settings_loaded = true;
+                            Response::ok("Settings loaded")
+                        }
+                        Err(error) => Response::from_error(error),
+                    }
+                }
+                "settings/apply" => {
+                    match store
+                        .save(settings::DEFAULT_PROFILE, miniconf.settings())
+                    {
+                        Ok(_) => {
+                            *active = Some(ProfileName::from(
+                                settings::DEFAULT_PROFILE,
+                            ));
+                            restart = true;
+                            Response::ok("Settings saved, restarting")
+                        }
+                        Err(error) => Response::from_error(error),
+                    }
+                }
+                "settings/erase" => match store.erase(name) {
+                    Ok(_) => {
+                        if active.as_deref() == Some(name) {
+                            *active = None;
+                        }
+                        Response::ok("Stored settings erased")
+                    }
+                    Err(error) => Response::from_error(error),
+                },
+                "settings/list" => match store.profiles() {
+                    Ok(profiles) => {
+                        let mut msg: String<256> = String::new();
+                        for (i, profile) in profiles.iter().enumerate() {
+                            if i > 0 {
+                                msg.push(',').ok();
+                            }
+                            msg.push_str(profile).ok();
+                        }
+                        Response::ok(&msg)
+                    }
+                    Err(error) => Response::from_error(error),
+                },
+                _ => Response::error("Unknown command"),
+            }
+        });
+
+        if restart {
+            return NetworkState::RestartRequested;
+        }
+
+        if let Some(channel) = committed {
+            return NetworkState::WaveformChanged(channel);
+        }
+
+        // Loaded settings replace all paths, which is indicated by the empty root path.
+        if settings_loaded {
+            return NetworkState::SettingsChanged(String::new());
+        }
+
         // `settings_path` has to be at least as large as `miniconf::mqtt_client::MAX_TOPIC_LENGTH`.
         let mut settings_path: String<128> = String::new();
         match self.miniconf.handled_update(|path, old, new| {
@@ -172,6 +537,124 @@ where
     }
 }
 
+/// Handle an arbitrary waveform command.
+///
+/// # Args
+/// * `command` - The command without the `waveform/` prefix, i.e. `<n>/<action>`.
+/// * `payload` - The command payload.
+/// * `waveforms` - The staged waveforms of both signal generator channels.
+/// * `flash` - The flash to store waveforms in.
+/// * `committed` - Set to the channel of the waveform if it was committed.
+///
+/// # Returns
+/// The response to the command.
+fn waveform_command(
+    command: &str,
+    payload: &[u8],
+    waveforms: &mut [Waveform; 2],
+    flash: &mut Flash,
+    committed: &mut Option<usize>,
+) -> Response {
+    let (channel, action) = match command.split_once('/') {
+        Some(command) => command,
+        None => return Response::error("Unknown command"),
+    };
+
+    let channel = match channel.parse::<usize>() {
+        Ok(channel) if channel < waveforms.len() => channel,
+        _ => return Response::error("Invalid channel"),
+    };
+    let waveform = &mut waveforms[channel];
+
+    match action {
+        "write" => {
+            match miniconf::serde_json_core::from_slice::<WaveformChunk>(
+                payload,
+            ) {
+                Ok((chunk, _)) => {
+                    match waveform.write(chunk.offset, &chunk.samples) {
+                        Ok(len) => {
+                            let mut msg: String<64> = String::new();
+                            write!(&mut msg, "{} samples", len).ok();
+                            Response::ok(&msg)
+                        }
+                        Err(error) => Response::from_error(error),
+                    }
+                }
+                Err(error) => Response::from_error(error),
+            }
+        }
+        "clear" => {
+            waveform.clear();
+            Response::ok("Waveform cleared")
+        }
+        "commit" => {
+            *committed = Some(channel);
+            Response::ok("Waveform committed")
+        }
+        "save" => match WaveformStore::new(flash.waveforms())
+            .save(channel, waveform)
+        {
+            Ok(_) => Response::ok("Waveform saved"),
+            Err(error) => Response::from_error(error),
+        },
+        "erase" => match WaveformStore::new(flash.waveforms()).erase(channel) {
+            Ok(_) => Response::ok("Stored waveform erased"),
+            Err(error) => Response::from_error(error),
+        },
+        _ => Response::error("Unknown command"),
+    }
+}
+
+/// Handle a network configuration command.
+///
+/// # Args
+/// * `command` - The command without the `network/` prefix.
+/// * `payload` - The command payload.
+/// * `config` - The staged network configuration.
+/// * `flash` - The flash to store the network configuration in.
+/// * `restart` - Set if a restart was requested to apply the configuration.
+///
+/// # Returns
+/// The response to the command.
+fn network_command(
+    command: &str,
+    payload: &[u8],
+    config: &mut NetworkConfig,
+    flash: &mut Flash,
+    restart: &mut bool,
+) -> Response {
+    if let Some(path) = command.strip_prefix("set/") {
+        return match config.set(path, payload) {
+            Ok(_) => Response::ok("Network config staged"),
+            Err(error) => Response::from_error(error),
+        };
+    }
+
+    match command {
+        "get" => Response::config(config),
+        "save" | "apply" => {
+            if let Err(error) = config.validate() {
+                return Response::from_error(error);
+            }
+
+            match config.save(flash) {
+                Ok(_) if command == "apply" => {
+                    *restart = true;
+                    Response::ok("Network config saved, restarting")
+                }
+                Ok(_) => Response::ok("Network config saved"),
+                Err(error) => Response::from_error(error),
+            }
+        }
+        "erase" => match NetworkConfig::erase(flash) {
+            Ok(_) => Response::ok("Stored network config erased"),
+            Err(error) => Response::from_error(error),
+        },
+        _ => Response::error("Unknown command"),
+    }
+}
+
 /// Get an MQTT client ID for a client.
 ///
 /// # Args
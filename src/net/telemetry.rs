@@ -10,17 +10,31 @@
 ///! sampling frequency. Instead, the raw codes are stored and the telemetry is generated as
 ///! required immediately before transmission. This ensures that any slower computation required
 ///! for unit conversion can be off-loaded to lower priority tasks.
+///!
+///! In addition to the latest codes, the buffer accumulates the statistics of all ADC and DAC codes
+///! since the previous report (minimum, maximum, mean, RMS, and the number of full scale samples).
+///! This makes short clipping events visible that the latest codes alone would miss.
+///!
+///! The livestream statistics are reported to distinguish batches dropped by Stabilizer from
+///! frames lost on the network.
 use heapless::{String, Vec};
 use serde::Serialize;
 
-use super::NetworkReference;
+use super::{data_stream::StreamStatistics, dns::BrokerStack};
+use crate::dsp::{dual_iir, lock, network_analyzer, statistics};
 use crate::hardware::{adc::AdcCode, afe::Gain, dac::DacCode, SystemTimer};
-use minimq::embedded_nal::IpAddr;
+use crate::settings::ProfileName;
+
+// The maximum size of serialized telemetry in bytes. The worst case telemetry is about 1 KiB. The
+// MQTT client buffer leaves room for the topic and the packet headers.
+const MAX_TELEMETRY_SIZE: usize = 1536;
 
 /// The telemetry client for reporting telemetry data over MQTT.
 pub struct TelemetryClient<T: Serialize> {
-    mqtt: minimq::Minimq<NetworkReference, SystemTimer, 1024, 1>,
+    mqtt: minimq::Minimq<BrokerStack, SystemTimer, 2048, 1>,
     telemetry_topic: String<128>,
+    alarm_topic: String<128>,
+    analyzer_topic: String<128>,
     _telemetry: core::marker::PhantomData<T>,
 }
 
@@ -38,6 +52,126 @@ pub struct TelemetryBuffer {
     pub dacs: [DacCode; 2],
     /// The latest digital input states during processing.
     pub digital_inputs: [bool; 2],
+    /// The latest lock status of channel 0/1, if the application supports automatic locking.
+    pub lock: Option<[lock::Status; 2]>,
+    /// The latest saturation statistics of channel 0/1, if the application tracks saturation.
+    pub saturation: Option<[dual_iir::Saturation; 2]>,
+    /// The statistics of the ADC0/ADC1 codes since the previous report.
+    pub adc_statistics: [statistics::Statistics; 2],
+    /// The statistics of the DAC0/DAC1 codes since the previous report.
+    pub dac_statistics: [statistics::Statistics; 2],
+}
+
+/// The statistics of a signal over a telemetry period in volts.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub struct Statistics {
+    /// The minimum voltage.
+    pub min: Option<f32>,
+
+    /// The maximum voltage.
+    pub max: Option<f32>,
+
+    /// The mean voltage.
+    pub mean: Option<f32>,
+
+    /// The root mean square voltage.
+    pub rms: Option<f32>,
+
+    /// The number of samples at full scale.
+    pub full_scale: u32,
+}
+
+impl Statistics {
+    /// Convert code statistics to volts.
+    ///
+    /// # Args
+    /// * `statistics` - The code statistics.
+    /// * `volt_per_lsb` - The voltage of a code LSB.
+    pub fn new(statistics: &statistics::Statistics, volt_per_lsb: f32) -> Self {
+        Self {
+            min: statistics.min().map(|x| x as f32 * volt_per_lsb),
+            max: statistics.max().map(|x| x as f32 * volt_per_lsb),
+            mean: statistics.mean().map(|x| x * volt_per_lsb),
+            rms: statistics.rms().map(|x| x * volt_per_lsb),
+            full_scale: statistics.full_scale(),
+        }
+    }
+}
+
+/// The saturation statistics of a channel output.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub struct Saturation {
+    /// The total number of saturated output samples.
+    pub count: u32,
+
+    /// The time in seconds the output has been continuously saturated.
+    pub rail_time: f32,
+}
+
+impl Saturation {
+    /// Convert raw saturation statistics.
+    ///
+    /// # Args
+    /// * `saturation` - The raw saturation statistics.
+    /// * `sample_period` - The time in seconds between samples.
+    pub fn new(saturation: dual_iir::Saturation, sample_period: f32) -> Self {
+        Self {
+            count: saturation.count,
+            rail_time: saturation.rail as f32 * sample_period,
+        }
+    }
+}
+
+/// An alarm indicating that a channel output is continuously saturated.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub struct SaturationAlarm {
+    /// The saturated channel.
+    pub channel: usize,
+
+    /// The time in seconds the output has been continuously saturated.
+    pub rail_time: f32,
+}
+
+/// A measured point of the network analyzer.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub struct AnalyzerPoint {
+    /// The index of the frequency in the configured list.
+    pub index: usize,
+
+    /// The frequency in Hertz.
+    pub frequency: f32,
+
+    /// The magnitude of the response of ADC0 and ADC1 in volts per volt of excitation.
+    pub magnitude: [f32; 2],
+
+    /// The phase of the response of ADC0 and ADC1 in turns.
+    pub phase: [f32; 2],
+}
+
+impl AnalyzerPoint {
+    /// Convert a raw network analyzer point.
+    ///
+    /// # Args
+    /// * `point` - The raw network analyzer point.
+    /// * `sample_period` - The time in seconds between samples.
+    /// * `gains` - The AFE gains of ADC0 and ADC1 during the measurement.
+    pub fn new(
+        point: network_analyzer::Point,
+        sample_period: f32,
+        gains: [Gain; 2],
+    ) -> Self {
+        let magnitude = point.magnitude();
+        Self {
+            index: point.index,
+            frequency: point.frequency as f32
+                / ((1u64 << 32) as f32 * sample_period),
+            magnitude: [0, 1].map(|i| {
+                magnitude[i] * AdcCode::VOLT_PER_LSB
+                    / (DacCode::VOLT_PER_LSB * gains[i].as_multiplier())
+            }),
+            phase: point.phase(),
+        }
+    }
 }
 
 /// The telemetry structure is data that is ultimately reported as telemetry over MQTT.
@@ -56,8 +190,33 @@ pub struct Telemetry {
     /// Most recent digital input assertion state.
     pub digital_inputs: [bool; 2],
 
+    /// The current AFE gain of each input.
+    pub afe: [Gain; 2],
+
+    /// The input voltage statistics since the previous report.
+    pub adc_statistics: [Statistics; 2],
+
+    /// The output voltage statistics since the previous report.
+    pub dac_statistics: [Statistics; 2],
+
     /// The CPU temperature in degrees Celsius.
     pub cpu_temp: f32,
+
+    /// The name of the active settings profile.
+    pub profile: Option<ProfileName>,
+
+    /// The livestream statistics.
+    pub stream: StreamStatistics,
+
+    /// The lock state and relock count of each channel. Only reported by applications that support
+    /// automatic locking.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lock: Option<[lock::Status; 2]>,
+
+    /// The output saturation statistics of each channel. Only reported by applications that track
+    /// saturation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub saturation: Option<[Saturation; 2]>,
 }
 
 impl Default for TelemetryBuffer {
@@ -66,29 +225,94 @@ impl Default for TelemetryBuffer {
             adcs: [AdcCode(0), AdcCode(0)],
             dacs: [DacCode(0), DacCode(0)],
             digital_inputs: [false, false],
+            lock: None,
+            saturation: None,
+            adc_statistics: [statistics::Statistics::default(); 2],
+            dac_statistics: [statistics::Statistics::default(); 2],
         }
     }
 }
 
 impl TelemetryBuffer {
+    /// Update the buffer with a processed batch of codes.
+    ///
+    /// # Args
+    /// * `adcs` - The raw ADC0/ADC1 codes of the batch.
+    /// * `dacs` - The raw DAC0/DAC1 codes of the batch.
+    #[inline(always)]
+    pub fn update(&mut self, adcs: [&[u16]; 2], dacs: [&[u16]; 2]) {
+        self.adcs = [AdcCode(adcs[0][0]), AdcCode(adcs[1][0])];
+        self.dacs = [DacCode(dacs[0][0]), DacCode(dacs[1][0])];
+
+        for (statistics, adc) in self.adc_statistics.iter_mut().zip(adcs) {
+            statistics.extend(adc.iter().map(|&x| i16::from(AdcCode(x))));
+        }
+
+        for (statistics, dac) in self.dac_statistics.iter_mut().zip(dacs) {
+            statistics.extend(dac.iter().map(|&x| i16::from(DacCode(x))));
+        }
+    }
+
+    /// Take a copy of the buffer for reporting and restart the accumulation of statistics.
+    pub fn take(&mut self) -> Self {
+        let buffer = *self;
+        self.adc_statistics = Default::default();
+        self.dac_statistics = Default::default();
+        buffer
+    }
+
     /// Convert the telemetry buffer to finalized, SI-unit telemetry for reporting.
     ///
     /// # Args
     /// * `afe0` - The current AFE configuration for channel 0.
     /// * `afe1` - The current AFE configuration for channel 1.
     /// * `cpu_temp` - The current CPU temperature.
+    /// * `profile` - The name of the active settings profile.
+    /// * `sample_period` - The time in seconds between samples.
+    /// * `stream` - The current livestream statistics.
     ///
     /// # Returns
     /// The finalized telemetry structure that can be serialized and reported.
-    pub fn finalize(self, afe0: Gain, afe1: Gain, cpu_temp: f32) -> Telemetry {
+    pub fn finalize(
+        self,
+        afe0: Gain,
+        afe1: Gain,
+        cpu_temp: f32,
+        profile: Option<ProfileName>,
+        sample_period: f32,
+        stream: StreamStatistics,
+    ) -> Telemetry {
         let in0_volts = Into::<f32>::into(self.adcs[0]) / afe0.as_multiplier();
         let in1_volts = Into::<f32>::into(self.adcs[1]) / afe1.as_multiplier();
 
+        let adc_statistics = [
+            Statistics::new(
+                &self.adc_statistics[0],
+                AdcCode::VOLT_PER_LSB / afe0.as_multiplier(),
+            ),
+            Statistics::new(
+                &self.adc_statistics[1],
+                AdcCode::VOLT_PER_LSB / afe1.as_multiplier(),
+            ),
+        ];
+        let dac_statistics = self
+            .dac_statistics
+            .map(|s| Statistics::new(&s, DacCode::VOLT_PER_LSB));
+
         Telemetry {
             cpu_temp,
             adcs: [in0_volts, in1_volts],
             dacs: [self.dacs[0].into(), self.dacs[1].into()],
             digital_inputs: self.digital_inputs,
+            afe: [afe0, afe1],
+            adc_statistics,
+            dac_statistics,
+            profile,
+            stream,
+            lock: self.lock,
+            saturation: self.saturation.map(|saturation| {
+                saturation.map(|s| Saturation::new(s, sample_period))
+            }),
         }
     }
 }
@@ -97,30 +321,37 @@ impl<T: Serialize> TelemetryClient<T> {
     /// Construct a new telemetry client.
     ///
     /// # Args
-    /// * `stack` - A reference to the (shared) underlying network stack.
+    /// * `stack` - A reference to the (shared) underlying network stack connecting to the broker.
     /// * `clock` - A `SystemTimer` implementing `Clock`.
     /// * `client_id` - The MQTT client ID of the telemetry client.
     /// * `prefix` - The device prefix to use for MQTT telemetry reporting.
-    /// * `broker` - The IP address of the MQTT broker to use.
     ///
     /// # Returns
     /// A new telemetry client.
     pub fn new(
-        stack: NetworkReference,
+        stack: BrokerStack,
         clock: SystemTimer,
         client_id: &str,
         prefix: &str,
-        broker: IpAddr,
     ) -> Self {
         let mqtt =
-            minimq::Minimq::new(broker, client_id, stack, clock).unwrap();
+            minimq::Minimq::new(BrokerStack::ADDRESS, client_id, stack, clock)
+                .unwrap();
 
         let mut telemetry_topic: String<128> = String::from(prefix);
         telemetry_topic.push_str("/telemetry").unwrap();
 
+        let mut alarm_topic: String<128> = String::from(prefix);
+        alarm_topic.push_str("/alarm").unwrap();
+
+        let mut analyzer_topic: String<128> = String::from(prefix);
+        analyzer_topic.push_str("/analyzer").unwrap();
+
         Self {
             mqtt,
             telemetry_topic,
+            alarm_topic,
+            analyzer_topic,
             _telemetry: core::marker::PhantomData::default(),
         }
     }
@@ -129,13 +360,19 @@ impl<T: Serialize> TelemetryClient<T> {
     ///
     /// # Note
     /// Telemetry is reported in a "best-effort" fashion. Failure to transmit telemetry will cause
-    /// it to be silently dropped.
+    /// it to be silently dropped. Telemetry that cannot be serialized is dropped with an error.
     ///
     /// # Args
     /// * `telemetry` - The telemetry to report
     pub fn publish(&mut self, telemetry: &T) {
-        let telemetry: Vec<u8, 512> =
-            miniconf::serde_json_core::to_vec(telemetry).unwrap();
+        let telemetry: Vec<u8, MAX_TELEMETRY_SIZE> =
+            match miniconf::serde_json_core::to_vec(telemetry) {
+                Ok(telemetry) => telemetry,
+                Err(e) => {
+                    log::error!("Telemetry serialization error: {:?}", e);
+                    return;
+                }
+            };
         self.mqtt
             .client()
             .publish(
@@ -148,6 +385,50 @@ impl<T: Serialize> TelemetryClient<T> {
             .ok();
     }
 
+    /// Publish an alarm over MQTT
+    ///
+    /// # Note
+    /// Alarms are published to `<prefix>/alarm` in a "best-effort" fashion just like telemetry.
+    ///
+    /// # Args
+    /// * `alarm` - The alarm to report
+    pub fn alarm(&mut self, alarm: &impl Serialize) {
+        let alarm: Vec<u8, 256> =
+            miniconf::serde_json_core::to_vec(alarm).unwrap();
+        self.mqtt
+            .client()
+            .publish(
+                minimq::Publication::new(&alarm)
+                    .topic(&self.alarm_topic)
+                    .finish()
+                    .unwrap(),
+            )
+            .map_err(|e| log::error!("Alarm publishing error: {:?}", e))
+            .ok();
+    }
+
+    /// Publish a network analyzer point over MQTT
+    ///
+    /// # Note
+    /// Points are published to `<prefix>/analyzer` in a "best-effort" fashion just like telemetry.
+    ///
+    /// # Args
+    /// * `point` - The measured point to report
+    pub fn analyzer(&mut self, point: &AnalyzerPoint) {
+        let point: Vec<u8, 256> =
+            miniconf::serde_json_core::to_vec(point).unwrap();
+        self.mqtt
+            .client()
+            .publish(
+                minimq::Publication::new(&point)
+                    .topic(&self.analyzer_topic)
+                    .finish()
+                    .unwrap(),
+            )
+            .map_err(|e| log::error!("Analyzer publishing error: {:?}", e))
+            .ok();
+    }
+
     /// Update the telemetry client
     ///
     /// # Note
@@ -0,0 +1,210 @@
+//! Stabilizer arbitrary waveform persistence
+//!
+//! # Design
+//! The arbitrary [Waveform]s played back by the signal generators are uploaded at run-time and
+//! held in RAM. They can additionally be stored in non-volatile memory so that they survive a
+//! device reset. Every stored waveform occupies one erase sector of the flash, indexed by the
+//! signal generator channel, so storing or erasing a waveform never affects the others. Stored
+//! waveforms are restored at boot.
+//!
+//! ## Storage Layout
+//! Each stored waveform consists of a header followed by the samples, all in little-endian.
+//!
+//! * **Magic word 0xA7F0** <u16>: a constant to identify a stored waveform.
+//! * **Layout Version** <u16>: the version of this storage layout.
+//! * **Length** <u32>: the number of samples following the header.
+//! * **CRC** <u32>: the CRC-32 of the samples.
+//! * **Samples** <[i16; Length]>: the full scale codes of the waveform samples.
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::dsp::signal_generator::{Waveform, WAVEFORM_CAPACITY};
+use crate::settings::crc32;
+
+// Magic first bytes indicating a stored waveform.
+const MAGIC: u16 = 0xA7F0;
+
+// The version of the storage layout. This must be incremented on any incompatible layout change.
+const VERSION: u16 = 1;
+
+// The size of the header in bytes.
+const HEADER_SIZE: usize = 12;
+
+// The maximum size of a stored waveform in bytes, including the header.
+const MAX_WAVEFORM_SIZE: usize =
+    HEADER_SIZE + WAVEFORM_CAPACITY * core::mem::size_of::<i16>();
+
+// The size of the buffer. This leaves room for padding the largest waveform to a flash write
+// granularity of up to 256 bytes.
+const BUFFER_SIZE: usize = MAX_WAVEFORM_SIZE + 256;
+
+/// Errors that can occur when storing or restoring waveforms.
+#[derive(Copy, Clone, Debug)]
+pub enum Error<E> {
+    /// There is no stored waveform for the requested channel.
+    NotFound,
+    /// There is no storage for the requested channel.
+    InvalidChannel,
+    /// The stored waveform is corrupt.
+    Corrupt,
+    /// The stored waveform does not fit in the flash write granularity.
+    Overflow,
+    /// The underlying flash reported an error.
+    Flash(E),
+}
+
+/// Persistent storage of arbitrary waveforms.
+pub struct WaveformStore<F> {
+    flash: F,
+    buffer: [u8; BUFFER_SIZE],
+}
+
+impl<F: NorFlash> WaveformStore<F> {
+    /// Construct a waveform store.
+    ///
+    /// # Args
+    /// * `flash` - The flash to store waveforms in. Every erase sector holds the waveform of one
+    ///   channel.
+    pub fn new(flash: F) -> Self {
+        Self {
+            flash,
+            buffer: [0; BUFFER_SIZE],
+        }
+    }
+
+    /// Store the waveform of a channel, replacing any previously stored waveform.
+    ///
+    /// # Note
+    /// This erases a flash sector, which may block for a significant amount of time.
+    ///
+    /// # Args
+    /// * `channel` - The signal generator channel of the waveform.
+    /// * `waveform` - The waveform to store.
+    pub fn save(
+        &mut self,
+        channel: usize,
+        waveform: &Waveform,
+    ) -> Result<(), Error<F::Error>> {
+        self.check(channel)?;
+
+        let samples = waveform.samples();
+        let length = HEADER_SIZE + samples.len() * 2;
+        for (chunk, sample) in self.buffer[HEADER_SIZE..length]
+            .chunks_exact_mut(2)
+            .zip(samples)
+        {
+            chunk.copy_from_slice(&sample.to_le_bytes());
+        }
+
+        let crc = crc32(&self.buffer[HEADER_SIZE..length]);
+        for (offset, byte) in MAGIC
+            .to_le_bytes()
+            .iter()
+            .chain(VERSION.to_le_bytes().iter())
+            .chain((samples.len() as u32).to_le_bytes().iter())
+            .chain(crc.to_le_bytes().iter())
+            .enumerate()
+        {
+            self.buffer[offset] = *byte;
+        }
+
+        // Pad the waveform to the write granularity of the flash.
+        let padded =
+            (length + F::WRITE_SIZE - 1) / F::WRITE_SIZE * F::WRITE_SIZE;
+        if padded > self.buffer.len() {
+            return Err(Error::Overflow);
+        }
+        self.buffer[length..padded].fill(0xFF);
+
+        self.erase(channel)?;
+        self.flash
+            .write(Self::offset(channel), &self.buffer[..padded])
+            .map_err(Error::Flash)
+    }
+
+    /// Restore the stored waveform of a channel.
+    ///
+    /// # Args
+    /// * `channel` - The signal generator channel of the waveform.
+    /// * `waveform` - The waveform to restore into. It is only modified if a valid waveform is
+    ///   stored.
+    ///
+    /// # Returns
+    /// The number of restored samples.
+    pub fn load(
+        &mut self,
+        channel: usize,
+        waveform: &mut Waveform,
+    ) -> Result<usize, Error<F::Error>> {
+        self.check(channel)?;
+
+        self.flash
+            .read(Self::offset(channel), &mut self.buffer[..HEADER_SIZE])
+            .map_err(Error::Flash)?;
+
+        let header = &self.buffer[..HEADER_SIZE];
+        let word = |offset: usize| {
+            u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap())
+        };
+        if u16::from_le_bytes([header[0], header[1]]) != MAGIC
+            || u16::from_le_bytes([header[2], header[3]]) != VERSION
+        {
+            return Err(Error::NotFound);
+        }
+
+        let count = word(4) as usize;
+        let crc = word(8);
+        if count > WAVEFORM_CAPACITY {
+            return Err(Error::Corrupt);
+        }
+
+        let length = HEADER_SIZE + count * 2;
+        self.flash
+            .read(
+                Self::offset(channel) + HEADER_SIZE as u32,
+                &mut self.buffer[HEADER_SIZE..length],
+            )
+            .map_err(Error::Flash)?;
+        if crc32(&self.buffer[HEADER_SIZE..length]) != crc {
+            return Err(Error::Corrupt);
+        }
+
+        let mut samples = [0i16; WAVEFORM_CAPACITY];
+        for (sample, chunk) in samples
+            .iter_mut()
+            .zip(self.buffer[HEADER_SIZE..length].chunks_exact(2))
+        {
+            *sample = i16::from_le_bytes([chunk[0], chunk[1]]);
+        }
+
+        waveform.clear();
+        // Note(unwrap): The number of samples is within the waveform capacity.
+        waveform.write_codes(0, &samples[..count]).unwrap();
+        Ok(count)
+    }
+
+    /// Erase the stored waveform of a channel.
+    ///
+    /// # Note
+    /// This erases a flash sector, which may block for a significant amount of time.
+    ///
+    /// # Args
+    /// * `channel` - The signal generator channel of the waveform.
+    pub fn erase(&mut self, channel: usize) -> Result<(), Error<F::Error>> {
+        self.check(channel)?;
+        self.flash
+            .erase(Self::offset(channel), Self::offset(channel + 1))
+            .map_err(Error::Flash)
+    }
+
+    fn offset(channel: usize) -> u32 {
+        (channel * F::ERASE_SIZE) as u32
+    }
+
+    fn check(&self, channel: usize) -> Result<(), Error<F::Error>> {
+        if Self::offset(channel + 1) as usize > self.flash.capacity() {
+            return Err(Error::InvalidChannel);
+        }
+
+        Ok(())
+    }
+}